@@ -22,7 +22,7 @@ cd "$(dirname "$0")"
 # Build the library if needed
 if [ ! -f "target/release/libhyper_processor.so" ]; then
     echo "Building RASP library..."
-    cargo build --release --lib
+    cargo build --release --lib --features preload
 fi
 
 # Build test app if needed
@@ -29,7 +29,7 @@ fi
 
 # Build the library
 echo -e "\n${GREEN}Building RASP library...${NC}"
-cargo build --release --lib
+cargo build --release --lib --features preload
 
 # Create directories
 echo -e "\n${GREEN}Creating directories...${NC}"
@@ -22,7 +22,7 @@ AUDIT_MODE="${AUDIT_MODE:-false}"
 # Ensure RASP library exists
 if [ ! -f "$RASP_LIB" ]; then
     echo -e "${RED}Error: RASP library not found at $RASP_LIB${NC}"
-    echo "Please build it first with: cargo build --release --lib"
+    echo "Please build it first with: cargo build --release --lib --features preload"
     exit 1
 fi
 
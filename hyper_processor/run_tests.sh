@@ -75,7 +75,7 @@ trap cleanup EXIT
 # Build RASP library
 build_rasp() {
   print_header "Building HyperProcessor RASP Library"
-  if cargo build; then # Using debug build for tests for faster compilation
+  if cargo build --lib --features preload; then # Using debug build for tests for faster compilation
     echo "RASP library built successfully."
   else
     echo "ERROR: RASP library build failed!"
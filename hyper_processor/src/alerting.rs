@@ -0,0 +1,268 @@
+// Module for pushing detections to a webhook, with an on-disk spool for failed deliveries
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, event, Level as TracingLevel};
+use crate::config::{AlertSettings, Settings};
+use crate::http;
+
+/// One offending library in a detection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectedLibrary {
+    pub filename: String,
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// JSON body POSTed to the webhook (and stored in the spool).
+#[derive(Debug, Serialize)]
+pub struct Detection {
+    pub timestamp: String,
+    pub host: String,
+    /// `blocked` or `audited`.
+    pub verdict: &'static str,
+    pub pid: u32,
+    pub ppid: i32,
+    pub process_name: String,
+    pub cmdline: String,
+    pub libraries: Vec<DetectedLibrary>,
+    /// Path of the forensic bundle written for this detection, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forensic_bundle: Option<String>,
+}
+
+impl Detection {
+    /// Builds a detection for the current process.
+    pub fn new(blocked: bool, libraries: Vec<DetectedLibrary>) -> Self {
+        Detection {
+            timestamp: crate::formats::rfc3339_millis(crate::formats::now_ms()),
+            host: crate::sinks::hostname(),
+            verdict: if blocked { "blocked" } else { "audited" },
+            pid: std::process::id(),
+            ppid: nix::unistd::getppid().as_raw(),
+            process_name: fs::read_to_string("/proc/self/comm")
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default(),
+            cmdline: fs::read("/proc/self/cmdline")
+                .map(|raw| crate::redaction::active().redact_cmdline(&raw))
+                .unwrap_or_default(),
+            libraries,
+            forensic_bundle: None,
+        }
+    }
+}
+
+/// Sends `detection` if alerting is configured for its verdict. Failed deliveries go to the spool.
+/// Never takes longer than `alerting.timeout_ms`, name resolution included.
+pub fn dispatch(settings: &Settings, detection: &Detection) {
+    let alerting = &settings.alerting;
+    let Some(url) = alerting.webhook_url.as_deref() else {
+        return;
+    };
+    if detection.verdict != "blocked" && !alerting.include_audit {
+        return;
+    }
+    let body = match serde_json::to_vec(detection) {
+        Ok(body) => body,
+        Err(e) => {
+            event!(TracingLevel::WARN, error = %e, "[Alert] Could not serialize detection");
+            return;
+        }
+    };
+
+    match http::post(url, &body, timeout(alerting)) {
+        Ok(()) => debug!("[Alert] Detection delivered to {}", url),
+        Err(e) => match alerting.spool_dir.as_deref() {
+            Some(dir) => match spool(Path::new(dir), &body) {
+                Ok(path) => {
+                    event!(TracingLevel::WARN, error = %e, spool_file = %path.display(), "[Alert] Webhook delivery failed; alert spooled");
+                }
+                Err(spool_err) => {
+                    event!(TracingLevel::ERROR, error = %e, spool_error = %spool_err, "[Alert] Webhook delivery failed and alert could not be spooled");
+                }
+            },
+            None => event!(TracingLevel::WARN, error = %e, "[Alert] Webhook delivery failed; no spool_dir configured"),
+        },
+    }
+}
+
+fn timeout(alerting: &AlertSettings) -> Duration {
+    Duration::from_millis(alerting.timeout_ms.max(1))
+}
+
+/// Stores one undelivered alert as `<spool_dir>/<millis>-<pid>-<seq>.json` (owner-only permissions).
+fn spool(dir: &Path, body: &[u8]) -> Result<PathBuf> {
+    static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+        .with_context(|| format!("Could not create spool directory {}", dir.display()))?;
+    let name = format!("{}-{}-{:04}", crate::formats::now_ms(), std::process::id(), SEQUENCE.fetch_add(1, Ordering::Relaxed));
+    let tmp = dir.join(format!("{name}.tmp"));
+    let path = dir.join(format!("{name}.json"));
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp)?;
+    file.write_all(body)?;
+    file.sync_all()?;
+    // Rename so a concurrent flush never sees a partial alert
+    fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+/// Outcome of `flush_spool`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FlushReport {
+    pub sent: usize,
+    pub failed: usize,
+}
+
+/// Re-sends every spooled alert in `dir`, oldest first, deleting each one that is delivered.
+pub fn flush_spool(dir: &Path, url: &str, timeout: Duration) -> Result<FlushReport> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Could not read spool directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+
+    let mut report = FlushReport::default();
+    for file in files {
+        let body = fs::read(&file)?;
+        match http::post(url, &body, timeout) {
+            Ok(()) => {
+                fs::remove_file(&file)?;
+                report.sent += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to deliver {}: {}", file.display(), e);
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+    use tempfile::tempdir;
+
+    /// Accepts `count` requests, answers each with `status`, and returns the request bodies.
+    fn stand_in_server(status: u16, count: usize) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/rasp", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for _ in 0..count {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut chunk = [0u8; 4096];
+                loop {
+                    let n = conn.read(&mut chunk).unwrap();
+                    request.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length: usize = head.lines()
+                            .find_map(|l| l.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        if body.len() >= length {
+                            assert!(head.starts_with("POST /hooks/rasp HTTP/1.1"));
+                            bodies.push(body.to_string());
+                            break;
+                        }
+                    }
+                }
+                write!(conn, "HTTP/1.1 {status} X\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    fn settings(url: &str, spool_dir: Option<&Path>) -> Settings {
+        let mut settings = Settings::default();
+        settings.alerting.webhook_url = Some(url.to_string());
+        settings.alerting.timeout_ms = 300;
+        settings.alerting.spool_dir = spool_dir.map(|d| d.to_string_lossy().into_owned());
+        settings
+    }
+
+    fn detection() -> Detection {
+        Detection::new(true, vec![DetectedLibrary {
+            filename: "libevil.so".into(),
+            path: "/tmp/libevil.so".into(),
+            size: 16384,
+            sha256: "00".repeat(32),
+        }])
+    }
+
+    #[test]
+    fn test_dispatch_delivers_json() {
+        let (url, server) = stand_in_server(204, 1);
+        dispatch(&settings(&url, None), &detection());
+        let bodies = server.join().unwrap();
+        let payload: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(payload["verdict"], "blocked");
+        assert_eq!(payload["pid"], std::process::id());
+        assert_eq!(payload["libraries"][0]["filename"], "libevil.so");
+    }
+
+    #[test]
+    fn test_audit_detection_skipped_by_default() {
+        let dir = tempdir().unwrap();
+        // Nothing listens here; a delivery attempt would end up in the spool
+        let settings = settings("http://127.0.0.1:9/", Some(dir.path()));
+        dispatch(&settings, &Detection::new(false, Vec::new()));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_unresponsive_endpoint_times_out_and_spools() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let dir = tempdir().unwrap();
+
+        let started = Instant::now();
+        dispatch(&settings(&url, Some(dir.path())), &detection());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(listener);
+
+        let spooled: Vec<PathBuf> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(spooled.len(), 1);
+        assert_eq!(spooled[0].extension().unwrap(), "json");
+    }
+
+    #[test]
+    fn test_flush_spool() {
+        let dir = tempdir().unwrap();
+        spool(dir.path(), b"{\"n\":1}").unwrap();
+        spool(dir.path(), b"{\"n\":2}").unwrap();
+
+        let (url, server) = stand_in_server(200, 2);
+        let report = flush_spool(dir.path(), &url, Duration::from_secs(2)).unwrap();
+        assert_eq!(report, FlushReport { sent: 2, failed: 0 });
+        assert_eq!(server.join().unwrap(), vec!["{\"n\":1}", "{\"n\":2}"]);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_flush_keeps_rejected_alerts() {
+        let dir = tempdir().unwrap();
+        spool(dir.path(), b"{}").unwrap();
+        let (url, server) = stand_in_server(500, 1);
+        let report = flush_spool(dir.path(), &url, Duration::from_secs(2)).unwrap();
+        server.join().unwrap();
+        assert_eq!(report, FlushReport { sent: 0, failed: 1 });
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
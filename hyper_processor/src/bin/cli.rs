@@ -1,8 +1,8 @@
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use std::process::{Command, ExitStatus};
 use std::time::Duration;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
 
 #[derive(Parser)]
@@ -17,7 +17,11 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Learn mode - collect libraries used by an application
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Learn {
+        #[command(subcommand)]
+        action: Option<LearnAction>,
+        
         /// Duration to run in learning mode (e.g., "5m", "30s")
         #[arg(short, long, default_value = "30s")]
         duration: String,
@@ -26,20 +30,52 @@ enum Commands {
         #[arg(short, long, default_value = "learned_whitelist.yaml")]
         output: PathBuf,
         
+        /// Also keep the learning records of this run (JSON lines), for `learn merge`
+        #[arg(long)]
+        session: Option<PathBuf>,
+        
         /// Command to run
         #[arg(trailing_var_arg = true, required = true)]
         command: Vec<String>,
     },
     
-    /// Monitor mode - start Prometheus metrics exporter
+    /// Monitor mode - collect reports from protected processes and serve /metrics and /status
     Monitor {
-        /// Address to bind the metrics server
-        #[arg(short, long, default_value = "0.0.0.0:9100")]
+        /// Address to bind the metrics server (host:port, or unix:/path)
+        #[arg(short, long, default_value = "127.0.0.1:9100")]
         bind: String,
         
         /// Path to RASP config file
         #[arg(short, long)]
         config: Option<PathBuf>,
+
+        /// Datagram socket protected processes report to (monitor.socket in their config)
+        #[arg(short, long, default_value = hyper_processor::monitor::DEFAULT_SOCKET)]
+        socket: PathBuf,
+        
+        /// Group (name or gid) given write access to --socket; protected services must be in it
+        #[arg(long)]
+        socket_group: Option<String>,
+        
+        #[command(flatten)]
+        tls: TlsArgs,
+        
+        #[command(flatten)]
+        auth: TokenArgs,
+    },
+    
+    /// Show the processes known to a running monitor
+    Status {
+        /// Base URL of the monitor (plain `http://` only; query HTTPS or Unix-socket monitors with curl)
+        #[arg(short, long, default_value = "http://127.0.0.1:9100")]
+        url: String,
+        
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        
+        #[command(flatten)]
+        auth: TokenArgs,
     },
     
     /// Verify library signatures
@@ -88,6 +124,78 @@ enum Commands {
         /// Include system libraries
         #[arg(short, long)]
         system: bool,
+        
+        /// Allow libraries by file name only instead of pinning path and SHA256
+        #[arg(long)]
+        by_name: bool,
+    },
+    
+    /// Compare a deployed policy with a relearned one; exits 1 on new dependencies or changed hashes
+    Diff {
+        /// Deployed policy (YAML)
+        old: PathBuf,
+        
+        /// Relearned policy (YAML) or learning records (.jsonl)
+        new: PathBuf,
+        
+        /// Print JSON instead of a report
+        #[arg(long)]
+        json: bool,
+        
+        /// Exit 0 when pinned libraries only changed content
+        #[arg(long)]
+        allow_hash_changes: bool,
+    },
+    
+    /// Webhook alert management
+    Alerts {
+        #[command(subcommand)]
+        action: AlertsAction,
+    },
+    
+    /// Query detections recorded in the local event store
+    Events {
+        /// Path to RASP config file (for event_store.dir)
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        
+        /// Event store directory, overriding the config file
+        #[arg(long)]
+        store: Option<PathBuf>,
+        
+        /// Library filename contains this text
+        #[arg(short, long)]
+        library: Option<String>,
+        
+        /// SHA256 starts with this prefix
+        #[arg(long)]
+        hash: Option<String>,
+        
+        /// Process name contains this text
+        #[arg(short, long)]
+        process: Option<String>,
+        
+        /// Only events newer than this: a duration ("7d", "12h") or a date/RFC 3339 time
+        #[arg(short, long)]
+        since: Option<String>,
+        
+        /// Only events older than this: a duration ("1h") or a date/RFC 3339 time
+        #[arg(short, long)]
+        until: Option<String>,
+        
+        /// Only "blocked" or "audited" events
+        #[arg(short, long)]
+        action: Option<String>,
+        
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    
+    /// Inspect forensic bundles written on detection
+    Forensics {
+        #[command(subcommand)]
+        action: ForensicsAction,
     },
     
     /// eBPF kernel-level protection (requires root)
@@ -111,16 +219,117 @@ enum Commands {
     },
 }
 
+/// TLS options of the monitor's HTTP server
+#[derive(Args)]
+struct TlsArgs {
+    /// PEM certificate chain; serves HTTPS instead of plain HTTP
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    
+    /// PEM CA bundle; clients must present a certificate signed by it (mutual TLS)
+    #[arg(long, requires = "tls_cert")]
+    client_ca: Option<PathBuf>,
+}
+
+/// Bearer token shared by the monitor and its clients
+#[derive(Args)]
+struct TokenArgs {
+    /// Bearer token required on (or sent with) monitor requests
+    #[arg(long, env = "HYPER_PROCESSOR_MONITOR_TOKEN", hide_env_values = true, conflicts_with = "token_file")]
+    token: Option<String>,
+    
+    /// File holding the bearer token, so it stays out of the process list
+    #[arg(long)]
+    token_file: Option<PathBuf>,
+}
+
+impl TokenArgs {
+    fn load(self) -> Result<Option<String>> {
+        let token = match self.token_file {
+            Some(path) => std::fs::read_to_string(&path)
+                .with_context(|| format!("Could not read token file {}", path.display()))?,
+            None => match self.token {
+                Some(token) => token,
+                None => return Ok(None),
+            },
+        };
+        let token = token.trim();
+        if token.is_empty() {
+            anyhow::bail!("Bearer token is empty");
+        }
+        Ok(Some(token.to_string()))
+    }
+}
+
+#[derive(Subcommand)]
+enum LearnAction {
+    /// Union learning sessions from several runs
+    Merge {
+        /// Learning files, or directories of per-process learning files; each is one run
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        
+        /// Merged learning records (JSON lines), for `generate` or a later merge
+        #[arg(short, long, default_value = "merged_learning.jsonl")]
+        output: PathBuf,
+        
+        /// Keep only libraries that appeared in at least this many runs
+        #[arg(short, long, default_value_t = 1)]
+        min_occurrences: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum AlertsAction {
+    /// Re-send alerts that could not be delivered when they were raised
+    Flush {
+        /// Path to RASP config file (for alerting.webhook_url / alerting.spool_dir)
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        
+        /// Webhook URL, overriding the config file
+        #[arg(short, long)]
+        url: Option<String>,
+        
+        /// Spool directory, overriding the config file
+        #[arg(short, long)]
+        spool_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ForensicsAction {
+    /// Print a bundle's manifest and contents, or a single file from it
+    Show {
+        /// Bundle (.tar) written to forensics.dir
+        bundle: PathBuf,
+        
+        /// Print this entry (e.g. maps, environ, fds) instead of the summary
+        #[arg(short, long)]
+        file: Option<String>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     
     match cli.command {
-        Commands::Learn { duration, output, command } => {
-            learn_mode(duration, output, command).await
+        Commands::Learn { action: Some(LearnAction::Merge { inputs, output, min_occurrences }), .. } => {
+            merge_learning(inputs, output, min_occurrences)
+        }
+        Commands::Learn { action: None, duration, output, session, command } => {
+            learn_mode(duration, output, session, command).await
+        }
+        Commands::Monitor { bind, config, socket, socket_group, tls, auth } => {
+            monitor_mode(bind, config, socket, socket_group, tls, auth.load()?).await
         }
-        Commands::Monitor { bind, config } => {
-            monitor_mode(bind, config).await
+        Commands::Status { url, json, auth } => {
+            show_status(url, json, auth.load()?)
         }
         Commands::Verify { library, gpg, sha256 } => {
             verify_library(library, gpg, sha256)
@@ -128,8 +337,21 @@ async fn main() -> Result<()> {
         Commands::Protect { audit, config, whitelist, command } => {
             protect_mode(audit, config, whitelist, command)
         }
-        Commands::Generate { input, output, system } => {
-            generate_whitelist(input, output, system)
+        Commands::Generate { input, output, system, by_name } => {
+            generate_whitelist(input, output, system, by_name)
+        }
+        Commands::Diff { old, new, json, allow_hash_changes } => {
+            diff_policies(old, new, json, allow_hash_changes)
+        }
+        Commands::Alerts { action: AlertsAction::Flush { config, url, spool_dir } } => {
+            flush_alerts(config, url, spool_dir)
+        }
+        Commands::Events { config, store, library, hash, process, since, until, action, json } => {
+            let filter = EventFilter { library, hash, process, since, until, action };
+            query_events(config, store, filter, json)
+        }
+        Commands::Forensics { action: ForensicsAction::Show { bundle, file } } => {
+            show_forensics(bundle, file)
         }
         #[cfg(feature = "ebpf")]
         Commands::Ebpf { audit, whitelist, clear, list } => {
@@ -138,7 +360,7 @@ async fn main() -> Result<()> {
     }
 }
 
-async fn learn_mode(duration_str: String, output: PathBuf, command: Vec<String>) -> Result<()> {
+async fn learn_mode(duration_str: String, output: PathBuf, session: Option<PathBuf>, command: Vec<String>) -> Result<()> {
     println!("🎓 Starting learning mode for {}", duration_str);
     
     // Parse duration
@@ -146,7 +368,8 @@ async fn learn_mode(duration_str: String, output: PathBuf, command: Vec<String>)
     
     // Set up environment for learning mode
     let lib_path = find_rasp_library()?;
-    let learning_output = tempfile::NamedTempFile::new()?;
+    // One file per process in here, so children don't overwrite each other
+    let learning_output = tempfile::tempdir()?;
     
     // Prepare environment
     let mut cmd = Command::new(&command[0]);
@@ -155,72 +378,133 @@ async fn learn_mode(duration_str: String, output: PathBuf, command: Vec<String>)
         .env("HYPER_RASP_AUDIT_MODE", "true")
         .env("HYPER_RASP_LEARNING_MODE", "true")
         .env("HYPER_RASP_LEARNING_OUTPUT", learning_output.path())
+        // Children that rebuild their environment still get preloaded and keep learning
+        .env("HYPER_RASP_CHILD_PROCESSES__POLICY", "reinject")
         .env("RUST_LOG", "warn");
+    // Own process group, so workers left behind can be stopped with the application
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
     
     // Start the process
     println!("📚 Running: {}", command.join(" "));
     let mut child = cmd.spawn()
         .context("Failed to start application")?;
+    let group = nix::unistd::Pid::from_raw(-(child.id() as i32));
     
     // Run for specified duration
     tokio::select! {
         _ = tokio::time::sleep(duration) => {
             println!("⏰ Learning period complete, stopping application...");
-            // Try graceful shutdown first
-            #[cfg(unix)]
-            {
-                use nix::sys::signal::{kill, Signal};
-                use nix::unistd::Pid;
-                let _ = kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM);
-            }
-            tokio::time::sleep(Duration::from_secs(2)).await;
-            let _ = child.kill();
         }
         status = wait_for_child(&mut child) => {
             println!("✅ Application exited with: {:?}", status?);
         }
     }
     
+    // Try graceful shutdown first, for the whole process tree
+    {
+        use nix::sys::signal::{kill, Signal};
+        if kill(group, Signal::SIGTERM).is_ok() {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            let _ = kill(group, Signal::SIGKILL);
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    
     // Process collected data
-    process_learning_data(learning_output.path(), &output)?;
+    process_learning_data(learning_output.path(), session.as_deref(), &output)?;
     
     println!("📝 Whitelist saved to: {}", output.display());
     Ok(())
 }
 
-async fn monitor_mode(bind: String, config: Option<PathBuf>) -> Result<()> {
+async fn monitor_mode(bind: String, config: Option<PathBuf>, socket: PathBuf, socket_group: Option<String>, tls: TlsArgs, token: Option<String>) -> Result<()> {
     #[cfg(feature = "metrics")]
     {
         use hyper::service::{make_service_fn, service_fn};
         use hyper::{Server};
+        use std::sync::{Arc, Mutex};
+        use std::sync::atomic::AtomicBool;
+        use hyper_processor::config::Settings;
+        use hyper_processor::monitor::Collector;
         
         println!("📊 Starting Prometheus metrics exporter on {}", bind);
         
         if let Some(cfg) = config {
             env::set_var("HYPER_RASP_CONFIG", cfg);
         }
+        let settings = Settings::load().context("Failed to load RASP config")?;
+        let acceptor = tls_acceptor(&tls)?;
+        let unix_path = bind.strip_prefix("unix:").map(PathBuf::from);
+        if unix_path.is_some() && acceptor.is_some() {
+            anyhow::bail!("TLS options apply to TCP binds only; protect unix: sockets with file permissions");
+        }
+        if unix_path.is_none() && token.is_none() && tls.client_ca.is_none() && !is_loopback(&bind) {
+            eprintln!("⚠️  Serving on {} without --token or --client-ca: anyone who can reach it can read per-process library data", bind);
+        }
         
-        // Initialize metrics (this will be done in the library)
-        // For now, just serve the metrics endpoint
-        
-        let addr = bind.parse()
-            .context("Invalid bind address")?;
-        
-        let make_svc = make_service_fn(|_conn| async {
-            Ok::<_, hyper::Error>(service_fn(metrics_handler))
+        // Protected processes push their reports here; we serve the aggregate
+        let state = Arc::new(MonitorState {
+            collector: Mutex::new(Collector::new()),
+            receiving: AtomicBool::new(false),
+            policy: settings.effective_policy(),
+            token,
         });
+        let listener = bind_monitor_socket(&socket, socket_group.as_deref())?;
+        let receiving = Arc::clone(&state);
+        std::thread::spawn(move || receive_reports(listener, receiving));
+        println!("📥 Collecting process reports on {}", socket.display());
         
-        let server = Server::bind(&addr).serve(make_svc);
-        
-        println!("🚀 Metrics available at http://{}/metrics", bind);
-        println!("Press Ctrl+C to stop");
+        // One service per connection, sharing the monitor state
+        macro_rules! service {
+            () => {{
+                let state = Arc::clone(&state);
+                make_service_fn(move |_conn| {
+                    let state = Arc::clone(&state);
+                    async move {
+                        Ok::<_, hyper::Error>(service_fn(move |req| monitor_handler(req, Arc::clone(&state))))
+                    }
+                })
+            }};
+        }
         
-        // Handle shutdown
-        let graceful = server.with_graceful_shutdown(shutdown_signal());
+        let served = if let Some(path) = unix_path {
+            let listener = bind_http_socket(&path)?;
+            let incoming = hyper::server::accept::poll_fn(move |cx| {
+                listener.poll_accept(cx).map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+            });
+            println!("🚀 Serving /metrics, /status, /healthz, /readyz, /version and /config on {}", bind);
+            println!("Press Ctrl+C to stop");
+            let result = Server::builder(incoming).serve(service!()).with_graceful_shutdown(shutdown_signal()).await;
+            let _ = std::fs::remove_file(path);
+            result
+        } else if let Some(acceptor) = acceptor {
+            let addr: std::net::SocketAddr = bind.parse()
+                .context("Invalid bind address")?;
+            let listener = tokio::net::TcpListener::bind(addr).await
+                .with_context(|| format!("Could not bind {}", addr))?;
+            // Handshakes run off the accept path so a slow client can't stall the others
+            let (handshaken, mut incoming) = tokio::sync::mpsc::channel(64);
+            tokio::spawn(accept_tls(listener, acceptor, handshaken));
+            let incoming = hyper::server::accept::poll_fn(move |cx| {
+                incoming.poll_recv(cx).map(|stream| stream.map(Ok::<_, std::io::Error>))
+            });
+            let client_auth = if tls.client_ca.is_some() { " (client certificates required)" } else { "" };
+            println!("🚀 Serving /metrics, /status, /healthz, /readyz, /version and /config on https://{}{}", bind, client_auth);
+            println!("Press Ctrl+C to stop");
+            Server::builder(incoming).serve(service!()).with_graceful_shutdown(shutdown_signal()).await
+        } else {
+            let addr = bind.parse()
+                .context("Invalid bind address")?;
+            println!("🚀 Serving /metrics, /status, /healthz, /readyz, /version and /config on http://{}", bind);
+            println!("Press Ctrl+C to stop");
+            Server::bind(&addr).serve(service!()).with_graceful_shutdown(shutdown_signal()).await
+        };
         
-        if let Err(e) = graceful.await {
+        if let Err(e) = served {
             eprintln!("Server error: {}", e);
         }
+        let _ = std::fs::remove_file(&socket);
         
         Ok(())
     }
@@ -232,21 +516,273 @@ async fn monitor_mode(bind: String, config: Option<PathBuf>) -> Result<()> {
     }
 }
 
+/// Shared by the report receiver and the HTTP handlers of `monitor`.
+#[cfg(feature = "metrics")]
+struct MonitorState {
+    collector: std::sync::Mutex<hyper_processor::monitor::Collector>,
+    /// Set while the report socket is being read; drives /readyz.
+    receiving: std::sync::atomic::AtomicBool,
+    /// Effective, redacted policy served at /config.
+    policy: serde_json::Value,
+    /// Bearer token every endpoint but /healthz requires, if set.
+    token: Option<String>,
+}
+
+/// Whether a TCP bind address only accepts local connections.
+fn is_loopback(bind: &str) -> bool {
+    bind.parse::<std::net::SocketAddr>()
+        .map(|addr| addr.ip().is_loopback())
+        .unwrap_or_else(|_| bind.starts_with("localhost:"))
+}
+
+/// Builds the server TLS config from --tls-cert/--tls-key, verifying client certificates
+/// against --client-ca when given. `None` means plain HTTP.
+#[cfg(feature = "tls")]
+fn tls_acceptor(tls: &TlsArgs) -> Result<Option<tokio_rustls::TlsAcceptor>> {
+    use std::sync::Arc;
+    use tokio_rustls::rustls::{crypto::ring, server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+    
+    let (Some(cert_path), Some(key_path)) = (&tls.tls_cert, &tls.tls_key) else {
+        return Ok(None);
+    };
+    let certs = read_certs(cert_path)?;
+    let key = rustls_pemfile::private_key(&mut open_pem(key_path)?)
+        .with_context(|| format!("Could not parse {}", key_path.display()))?
+        .with_context(|| format!("No private key in {}", key_path.display()))?;
+    
+    let builder = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?;
+    let builder = match &tls.client_ca {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_path)? {
+                roots.add(cert)
+                    .with_context(|| format!("Invalid CA certificate in {}", ca_path.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), Arc::new(ring::default_provider()))
+                .build()
+                .context("Could not build client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key)
+        .context("Certificate and private key do not match")?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Some(tokio_rustls::TlsAcceptor::from(Arc::new(config))))
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_acceptor(tls: &TlsArgs) -> Result<Option<std::convert::Infallible>> {
+    if tls.tls_cert.is_some() {
+        anyhow::bail!("TLS support not compiled. Build with --features tls");
+    }
+    Ok(None)
+}
+
+#[cfg(feature = "tls")]
+fn open_pem(path: &Path) -> Result<std::io::BufReader<std::fs::File>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Could not open {}", path.display()))?;
+    Ok(std::io::BufReader::new(file))
+}
+
+#[cfg(feature = "tls")]
+fn read_certs(path: &Path) -> Result<Vec<tokio_rustls::rustls::pki_types::CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open_pem(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Could not parse {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// Accepts TCP connections and hands each completed TLS handshake to the HTTP server.
+#[cfg(feature = "tls")]
+async fn accept_tls(
+    listener: tokio::net::TcpListener,
+    acceptor: tokio_rustls::TlsAcceptor,
+    handshaken: tokio::sync::mpsc::Sender<tokio_rustls::server::TlsStream<tokio::net::TcpStream>>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Accept error: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let handshaken = handshaken.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(Duration::from_secs(10), acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = handshaken.send(stream).await;
+                }
+                Ok(Err(e)) => eprintln!("TLS handshake with {} failed: {}", peer, e),
+                Err(_) => eprintln!("TLS handshake with {} timed out", peer),
+            }
+        });
+    }
+}
+
+/// Compares bearer tokens without exiting early on the first differing byte.
+fn token_matches(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Creates the parent directory of a socket path and removes a stale socket left there.
+#[cfg(feature = "metrics")]
+fn prepare_socket_path(path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create {}", parent.display()))?;
+    }
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a socket", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Binds the report socket, replacing a stale one left by a previous monitor, and has the
+/// kernel vouch for each sender's pid.
+#[cfg(feature = "metrics")]
+fn bind_monitor_socket(path: &Path, group: Option<&str>) -> Result<std::os::unix::net::UnixDatagram> {
+    use std::os::unix::fs::PermissionsExt;
+
+    prepare_socket_path(path)?;
+    let socket = std::os::unix::net::UnixDatagram::bind(path)
+        .with_context(|| format!("Could not bind {}", path.display()))?;
+    hyper_processor::monitor::enable_credentials(&socket)?;
+    // Only the owner and the reporting group may send; anyone else could forge verdicts
+    if let Some(group) = group {
+        let gid = match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => nix::unistd::Group::from_name(group)?
+                .with_context(|| format!("Unknown group {}", group))?
+                .gid
+                .as_raw(),
+        };
+        std::os::unix::fs::chown(path, None, Some(gid))
+            .with_context(|| format!("Could not give {} to group {}", path.display(), group))?;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    Ok(socket)
+}
+
+/// Binds the HTTP server to a Unix stream socket, so scrapers don't need an open port.
+#[cfg(feature = "metrics")]
+fn bind_http_socket(path: &Path) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    prepare_socket_path(path)?;
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Could not bind {}", path.display()))?;
+    // Owner and group only: grant scrapers access through the socket's group
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
 #[cfg(feature = "metrics")]
-async fn metrics_handler(_req: hyper::Request<hyper::Body>) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
-    use prometheus::{TextEncoder, Encoder};
-    use hyper::{Body, Response, StatusCode};
+fn receive_reports(socket: std::os::unix::net::UnixDatagram, state: std::sync::Arc<MonitorState>) {
+    use std::sync::atomic::Ordering;
     
-    let encoder = TextEncoder::new();
-    let metric_families = prometheus::gather();
-    let mut buffer = vec![];
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+    state.receiving.store(true, Ordering::SeqCst);
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let (n, peer) = match hyper_processor::monitor::recv_report(&socket, &mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Monitor socket error: {}", e);
+                state.receiving.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        let Some(peer) = peer else {
+            eprintln!("Ignoring report without sender credentials");
+            continue;
+        };
+        if let Ok(mut collector) = state.collector.lock() {
+            if let Err(e) = collector.ingest(peer.pid, &buf[..n]) {
+                eprintln!("Ignoring report: {:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+async fn monitor_handler(
+    req: hyper::Request<hyper::Body>,
+    state: std::sync::Arc<MonitorState>,
+) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+    use hyper::{Body, Method, Response, StatusCode};
+    use std::sync::atomic::Ordering;
+    
+    let respond = |status: StatusCode, content_type: &str, body: String| {
+        Ok(Response::builder()
+            .status(status)
+            .header("Content-Type", content_type)
+            .body(Body::from(body))
+            .unwrap())
+    };
+    const JSON: &str = "application/json";
+    const TEXT: &str = "text/plain; charset=utf-8";
     
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", encoder.format_type())
-        .body(Body::from(buffer))
-        .unwrap())
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, TEXT, "method not allowed\n".into());
+    }
+    // Liveness stays open so probes need no credentials; it reveals nothing
+    if let Some(token) = state.token.as_deref().filter(|_| req.uri().path() != "/healthz") {
+        let presented = req.headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !presented.is_some_and(|presented| token_matches(presented, token)) {
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header("WWW-Authenticate", "Bearer")
+                .header("Content-Type", TEXT)
+                .body(Body::from("unauthorized\n"))
+                .unwrap());
+        }
+    }
+    match req.uri().path() {
+        "/metrics" | "/status" => {
+            let Ok(collector) = state.collector.lock() else {
+                return respond(StatusCode::INTERNAL_SERVER_ERROR, TEXT, "collector unavailable\n".into());
+            };
+            if req.uri().path() == "/status" {
+                respond(StatusCode::OK, JSON, serde_json::to_string(&collector.status()).unwrap_or_default())
+            } else {
+                respond(StatusCode::OK, "text/plain; version=0.0.4", collector.render())
+            }
+        }
+        "/healthz" => respond(StatusCode::OK, TEXT, "ok\n".into()),
+        "/readyz" => {
+            if state.receiving.load(Ordering::SeqCst) {
+                respond(StatusCode::OK, TEXT, "ready\n".into())
+            } else {
+                respond(StatusCode::SERVICE_UNAVAILABLE, TEXT, "not ready: report socket is not being read\n".into())
+            }
+        }
+        "/version" => {
+            let version = serde_json::json!({
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "features": hyper_processor::metrics::enabled_features().split(',').filter(|f| !f.is_empty()).collect::<Vec<_>>(),
+            });
+            respond(StatusCode::OK, JSON, version.to_string())
+        }
+        "/config" => respond(StatusCode::OK, JSON, state.policy.to_string()),
+        _ => respond(StatusCode::NOT_FOUND, TEXT, "not found\n".into()),
+    }
 }
 
 #[cfg(feature = "metrics")]
@@ -334,10 +870,11 @@ fn protect_mode(audit: bool, config: Option<PathBuf>, whitelist: Vec<String>, co
     std::process::exit(status.code().unwrap_or(1));
 }
 
-fn generate_whitelist(input: PathBuf, output: PathBuf, include_system: bool) -> Result<()> {
+fn generate_whitelist(input: PathBuf, output: PathBuf, include_system: bool, by_name: bool) -> Result<()> {
     use std::fs::File;
     use std::io::{BufRead, BufReader, Write};
-    use std::collections::HashSet;
+    use std::collections::{BTreeMap, BTreeSet};
+    use hyper_processor::learning::LearnedLibrary;
     
     println!("🔧 Generating whitelist from: {}", input.display());
     
@@ -345,7 +882,25 @@ fn generate_whitelist(input: PathBuf, output: PathBuf, include_system: bool) ->
         .context("Failed to open input file")?;
     let reader = BufReader::new(file);
     
-    let mut libraries = HashSet::new();
+    // Path -> accepted hashes (empty when the file could not be hashed), plus bare file names
+    let mut pinned: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut names = BTreeSet::new();
+    let mut add = |filename: &str, path: Option<&str>, sha256: Option<&str>| {
+        if !include_system && is_system_library(filename) {
+            return;
+        }
+        match path {
+            Some(path) if !by_name => {
+                let hashes = pinned.entry(path.to_string()).or_default();
+                if let Some(sha256) = sha256.filter(|h| *h != "<error>") {
+                    hashes.insert(sha256.to_string());
+                }
+            }
+            _ => {
+                names.insert(filename.to_string());
+            }
+        }
+    };
     
     for line in reader.lines() {
         let line = line?;
@@ -355,47 +910,51 @@ fn generate_whitelist(input: PathBuf, output: PathBuf, include_system: bool) ->
             continue;
         }
         
-        // Try to parse as learning mode format first
-        if line.contains(r#"{"library":"#) {
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&line) {
-                if let Some(library) = json["library"].as_str() {
-                    if include_system || !is_system_library(library) {
-                        libraries.insert(library.to_string());
-                    }
-                }
-            }
-        } 
-        // Try to parse as audit log format
-        else if line.contains("unauthorized_library_filename") {
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&line) {
-                if let Some(filename) = json["fields"]["unauthorized_library_filename"].as_str() {
-                    if include_system || !is_system_library(filename) {
-                        libraries.insert(filename.to_string());
-                    }
-                }
-            }
+        // Learning mode records, with full identity or (older files) just a name
+        if let Ok(learned) = serde_json::from_str::<LearnedLibrary>(&line) {
+            add(&learned.library, Some(&learned.path), Some(&learned.sha256));
+            continue;
+        }
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(&line) else {
+            continue;
+        };
+        if let Some(library) = json["library"].as_str() {
+            add(library, None, None);
+        }
+        // Audit log events
+        else if let Some(filename) = json["fields"]["unauthorized_library_filename"].as_str() {
+            let fields = &json["fields"];
+            add(filename, fields["unauthorized_library_path"].as_str(), fields["file_hash"].as_str());
         }
     }
     
-    // Get count before moving libraries
-    let library_count = libraries.len();
+    let library_count = pinned.len() + names.len();
     
-    // Generate YAML
+    // Generate YAML; JSON string literals are valid double-quoted YAML scalars
+    let quote = |s: &str| serde_json::to_string(s).expect("strings always serialize");
     let mut out = File::create(&output)?;
-    writeln!(out, "# Generated whitelist from audit logs")?;
+    writeln!(out, "# Generated whitelist from learning data and audit logs")?;
     writeln!(out, "# Generated at: {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"))?;
     writeln!(out, "# Total libraries: {}", library_count)?;
     writeln!(out, "\naudit_mode: false\n")?;
-    writeln!(out, "whitelisted_filenames:")?;
-    
-    let mut sorted: Vec<_> = libraries.into_iter().collect();
-    sorted.sort();
-    
-    for lib in sorted {
-        writeln!(out, "  - \"{}\"", lib)?;
+    if !pinned.is_empty() {
+        writeln!(out, "whitelisted_libraries:")?;
+        for (path, hashes) in &pinned {
+            if hashes.is_empty() {
+                writeln!(out, "  - path: {}  # could not be hashed; allowed by path only", quote(path))?;
+            }
+            for sha256 in hashes {
+                writeln!(out, "  - path: {}", quote(path))?;
+                writeln!(out, "    sha256: {}", quote(sha256))?;
+            }
+        }
+    }
+    writeln!(out, "whitelisted_filenames:{}", if names.is_empty() { " []" } else { "" })?;
+    for name in &names {
+        writeln!(out, "  - {}", quote(name))?;
     }
     
-    println!("✅ Generated whitelist with {} libraries", library_count);
+    println!("✅ Generated whitelist with {} libraries ({} pinned by path)", library_count, pinned.len());
     println!("📝 Output saved to: {}", output.display());
     
     Ok(())
@@ -403,6 +962,210 @@ fn generate_whitelist(input: PathBuf, output: PathBuf, include_system: bool) ->
 
 // Helper functions
 
+fn flush_alerts(config: Option<PathBuf>, url: Option<String>, spool_dir: Option<PathBuf>) -> Result<()> {
+    use hyper_processor::alerting;
+    use hyper_processor::config::Settings;
+    
+    if let Some(cfg) = config {
+        env::set_var("HYPER_RASP_CONFIG", cfg);
+    }
+    let settings = Settings::load().context("Failed to load RASP config")?;
+    
+    let url = url.or(settings.alerting.webhook_url)
+        .context("No webhook URL: pass --url or set alerting.webhook_url")?;
+    let spool_dir = spool_dir.or(settings.alerting.spool_dir.map(PathBuf::from))
+        .context("No spool directory: pass --spool-dir or set alerting.spool_dir")?;
+    if !spool_dir.exists() {
+        println!("📭 Spool directory {} does not exist; nothing to flush", spool_dir.display());
+        return Ok(());
+    }
+    
+    let timeout = Duration::from_millis(settings.alerting.timeout_ms.max(1));
+    let report = alerting::flush_spool(&spool_dir, &url, timeout)?;
+    println!("📨 Delivered {} spooled alert(s) to {}", report.sent, url);
+    if report.failed > 0 {
+        anyhow::bail!("{} alert(s) could not be delivered and remain in {}", report.failed, spool_dir.display());
+    }
+    Ok(())
+}
+
+/// Raw `events` filter arguments, before time parsing.
+struct EventFilter {
+    library: Option<String>,
+    hash: Option<String>,
+    process: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    action: Option<String>,
+}
+
+/// Accepts a duration back from now ("7d") or an absolute date/RFC 3339 time.
+fn parse_time_bound(value: &str) -> Result<u64> {
+    if let Some(ms) = hyper_processor::formats::parse_rfc3339_millis(value) {
+        return Ok(ms);
+    }
+    let ago = parse_duration(value)
+        .with_context(|| format!("'{}' is neither a duration nor a date", value))?;
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    Ok(now.saturating_sub(ago).as_millis() as u64)
+}
+
+fn query_events(config: Option<PathBuf>, store: Option<PathBuf>, filter: EventFilter, json: bool) -> Result<()> {
+    use hyper_processor::config::Settings;
+    use hyper_processor::event_store::{self, EventQuery};
+    
+    let store = match store {
+        Some(store) => store,
+        None => {
+            if let Some(cfg) = config {
+                env::set_var("HYPER_RASP_CONFIG", cfg);
+            }
+            let settings = Settings::load().context("Failed to load RASP config")?;
+            settings.event_store.dir.map(PathBuf::from)
+                .context("No event store: pass --store or set event_store.dir")?
+        }
+    };
+    if let Some(action) = &filter.action {
+        if action != "blocked" && action != "audited" {
+            anyhow::bail!("--action must be 'blocked' or 'audited'");
+        }
+    }
+    
+    let query = EventQuery {
+        library: filter.library,
+        sha256: filter.hash,
+        process: filter.process,
+        since_ms: filter.since.as_deref().map(parse_time_bound).transpose()?,
+        until_ms: filter.until.as_deref().map(parse_time_bound).transpose()?,
+        action: filter.action,
+    };
+    let events = event_store::query(&store, &query)?;
+    
+    if json {
+        println!("{}", serde_json::to_string_pretty(&events)?);
+        return Ok(());
+    }
+    println!("{:<24}  {:<16}  {:<16}  {:>7}  {:<7}  {:<28}  SHA256", "TIME", "HOST", "PROCESS", "PID", "ACTION", "LIBRARY");
+    for event in &events {
+        println!(
+            "{:<24}  {:<16}  {:<16}  {:>7}  {:<7}  {:<28}  {}",
+            event.timestamp, event.host, event.process_name, event.pid, event.action, event.library,
+            &event.sha256[..event.sha256.len().min(16)]
+        );
+    }
+    println!("{} event(s)", events.len());
+    Ok(())
+}
+
+fn show_status(url: String, json: bool, token: Option<String>) -> Result<()> {
+    use hyper_processor::monitor::StatusReport;
+    
+    let endpoint = format!("{}/status", url.trim_end_matches('/'));
+    let body = hyper_processor::http::get(&endpoint, token.as_deref(), Duration::from_secs(5))
+        .with_context(|| format!("Could not query monitor at {}", url))?;
+    let report: StatusReport = serde_json::from_slice(&body).context("Unexpected /status response")?;
+    
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    let time = |ms: u64| hyper_processor::formats::rfc3339_millis(ms);
+    println!("{:>7}  {:<16}  {:<12}  {:<8}  {:<24}  {:<24}  {:>10}  {:<7}  LIBRARIES", "PID", "PROCESS", "PROFILE", "VERDICT", "LAST CHECK", "LAST SEEN", "DETECTIONS", "RUNNING");
+    for process in &report.processes {
+        println!(
+            "{:>7}  {:<16}  {:<12}  {:<8}  {:<24}  {:<24}  {:>10}  {:<7}  {}",
+            process.pid, process.comm, process.profile,
+            process.verdict.as_deref().unwrap_or("-"),
+            process.last_check_ms.map(time).unwrap_or_else(|| "-".to_string()),
+            time(process.last_seen_ms),
+            process.detections,
+            if process.running { "yes" } else { "no" },
+            process.unauthorized_libraries.join(",")
+        );
+    }
+    println!("{} process(es)", report.processes.len());
+    Ok(())
+}
+
+fn diff_policies(old: PathBuf, new: PathBuf, json: bool, allow_hash_changes: bool) -> Result<()> {
+    use hyper_processor::policy_diff;
+    
+    let drift = policy_diff::diff(&policy_diff::load(&old)?, &policy_diff::load(&new)?);
+    let short = |sha256: &str| sha256[..sha256.len().min(16)].to_string();
+    
+    if json {
+        println!("{}", serde_json::to_string_pretty(&drift)?);
+    } else {
+        println!("🔀 Policy drift: {} -> {}", old.display(), new.display());
+        for entry in &drift.added {
+            println!("  + new dependency  {}  {}", entry.label(), entry.sha256.as_deref().map(short).unwrap_or_default());
+        }
+        for bump in &drift.version_bumps {
+            println!("  ~ version bump    {} -> {}", bump.old.label(), bump.new.label());
+        }
+        for change in &drift.hash_changes {
+            println!("  ! hash changed    {}  {} -> {}", change.path, short(&change.old_sha256), short(&change.new_sha256));
+        }
+        for entry in &drift.removed {
+            println!("  - removed         {}", entry.label());
+        }
+        println!(
+            "{} new, {} version bump(s), {} hash change(s), {} removed",
+            drift.added.len(), drift.version_bumps.len(), drift.hash_changes.len(), drift.removed.len()
+        );
+    }
+    
+    if drift.fails_gate(allow_hash_changes) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn show_forensics(bundle: PathBuf, file: Option<String>) -> Result<()> {
+    use std::io::Write;
+    use hyper_processor::forensics::Bundle;
+    
+    let bundle = Bundle::open(&bundle)?;
+    if let Some(name) = file {
+        let data = bundle.entry(&name)
+            .with_context(|| format!("No entry '{}' in bundle", name))?;
+        std::io::stdout().write_all(data)?;
+        return Ok(());
+    }
+    
+    let manifest = &bundle.manifest;
+    println!("🔎 Forensic bundle");
+    println!("  Created:  {}", manifest.created);
+    println!("  Host:     {}", manifest.host);
+    println!("  Process:  {} (pid {}, ppid {})", manifest.process_name, manifest.pid, manifest.ppid);
+    println!("  Verdict:  {}", manifest.verdict);
+    println!("  RASP:     {}", manifest.rasp_version);
+    
+    println!("\n📚 Offending libraries:");
+    for library in &manifest.libraries {
+        println!("  - {} ({} bytes)", library.path, library.size);
+        println!("    sha256: {}", library.sha256);
+        match &library.copy {
+            Some(copy) => println!("    copy:   {}", copy),
+            None => println!("    copy:   <not captured>"),
+        }
+    }
+    
+    println!("\n📦 Entries:");
+    for entry in &manifest.entries {
+        println!("  {:<32} {:>10}  {}", entry.name, entry.size, entry.sha256);
+    }
+    
+    let mismatches = bundle.mismatches();
+    if mismatches.is_empty() {
+        println!("\n✅ All entries match the manifest");
+    } else {
+        println!("\n❌ Entries not matching the manifest: {}", mismatches.join(", "));
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
 fn find_rasp_library() -> Result<PathBuf> {
     // Check common locations
     let locations = [
@@ -415,7 +1178,8 @@ fn find_rasp_library() -> Result<PathBuf> {
     for loc in &locations {
         let path = PathBuf::from(loc);
         if path.exists() {
-            return Ok(path);
+            // Absolute, so children that change directory still find it in LD_PRELOAD
+            return Ok(path.canonicalize().unwrap_or(path));
         }
     }
     
@@ -427,7 +1191,7 @@ fn find_rasp_library() -> Result<PathBuf> {
         }
     }
     
-    anyhow::bail!("Cannot find libhyper_processor.so. Set HYPER_PROCESSOR_LIB or build with 'cargo build --release'")
+    anyhow::bail!("Cannot find libhyper_processor.so. Set HYPER_PROCESSOR_LIB or build with 'cargo build --release --lib --features preload,learning'")
 }
 
 fn parse_duration(s: &str) -> Result<Duration> {
@@ -437,8 +1201,10 @@ fn parse_duration(s: &str) -> Result<Duration> {
         Ok(Duration::from_secs(num.parse::<u64>()? * 60))
     } else if let Some(num) = s.strip_suffix("h") {
         Ok(Duration::from_secs(num.parse::<u64>()? * 3600))
+    } else if let Some(num) = s.strip_suffix("d") {
+        Ok(Duration::from_secs(num.parse::<u64>()? * 86400))
     } else {
-        anyhow::bail!("Invalid duration format. Use format like '30s', '5m', or '1h'")
+        anyhow::bail!("Invalid duration format. Use format like '30s', '5m', '1h' or '7d'")
     }
 }
 
@@ -452,10 +1218,48 @@ async fn wait_for_child(child: &mut std::process::Child) -> Result<ExitStatus> {
     }
 }
 
-fn process_learning_data(input: &std::path::Path, output: &PathBuf) -> Result<()> {
-    // This will be implemented when we add learning mode to the library
-    // For now, just process as regular audit log
-    generate_whitelist(input.to_path_buf(), output.clone(), false)
+/// Folds the per-process learning files in `dir` into one run and generates the whitelist from it.
+fn process_learning_data(dir: &Path, session: Option<&Path>, output: &Path) -> Result<()> {
+    let run = hyper_processor::learning::read_run(dir)?;
+    let records = session.map(Path::to_path_buf).unwrap_or_else(|| dir.join("session.jsonl"));
+    hyper_processor::learning::write_records(&records, &run)
+        .with_context(|| format!("Could not write {}", records.display()))?;
+    if session.is_some() {
+        println!("💾 Learning session saved to: {}", records.display());
+    }
+    generate_whitelist(records, output.to_path_buf(), false, false)
+}
+
+fn merge_learning(inputs: Vec<PathBuf>, output: PathBuf, min_occurrences: usize) -> Result<()> {
+    use hyper_processor::learning::{self, LearnedLibrary};
+    
+    let runs = inputs.iter()
+        .map(|input| learning::read_run(input))
+        .collect::<Result<Vec<_>>>()?;
+    let merged = learning::merge_runs(&runs);
+    
+    let (kept, dropped): (Vec<_>, Vec<_>) = merged.iter().partition(|m| m.runs >= min_occurrences);
+    let partial: Vec<_> = kept.iter().filter(|m| m.runs < runs.len()).collect();
+    if !partial.is_empty() {
+        println!("⚠️  Seen in only some runs:");
+        for m in &partial {
+            println!("  {}/{}  {}  {}", m.runs, runs.len(), m.library.path, &m.library.sha256[..m.library.sha256.len().min(16)]);
+        }
+    }
+    if !dropped.is_empty() {
+        println!("🗑️  Dropped (fewer than {} runs):", min_occurrences);
+        for m in &dropped {
+            println!("  {}/{}  {}  {}", m.runs, runs.len(), m.library.path, &m.library.sha256[..m.library.sha256.len().min(16)]);
+        }
+    }
+    
+    let records: Vec<LearnedLibrary> = kept.iter().map(|m| m.library.clone()).collect();
+    learning::write_records(&output, &records)
+        .with_context(|| format!("Could not write {}", output.display()))?;
+    println!("✅ Merged {} run(s): {} libraries kept, {} in every run, {} dropped",
+        runs.len(), records.len(), records.len() - partial.len(), dropped.len());
+    println!("📝 Output saved to: {}", output.display());
+    Ok(())
 }
 
 fn is_system_library(name: &str) -> bool {
@@ -0,0 +1,708 @@
+// Module for controlling what child processes inherit, via interposed exec and posix_spawn calls
+//
+// Only calls that go through the dynamic linker can be interposed: execve, execv, execvp,
+// execvpe, execveat, fexecve, posix_spawn and posix_spawnp. glibc's variadic execl/execle/execlp
+// and its system() and popen() reach the kernel through internal symbols, so children started
+// that way inherit their parent's environment unchanged. The policy is best-effort and `init`
+// logs it as such.
+//
+// Exec calls normally run in a forked child of a multithreaded process, where only
+// async-signal-safe functions may be called. The environment is therefore rebuilt in memory
+// mapped with a raw syscall and real functions are resolved up front in `init`. A forked child
+// can't log either: it writes a fixed-size spawn record to a pipe instead, and a thread in the
+// process that ran `init` logs it.
+//
+// Like the W^X guard's, these interposers are only exported with the `preload` feature.
+#![cfg_attr(not(feature = "preload"), allow(dead_code))]
+
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::OnceLock;
+use libc::{c_char, c_int, c_void, pid_t};
+use tracing::{event, Level as TracingLevel};
+use crate::config::{ChildPolicy, Settings};
+
+static STATE: OnceLock<ChildState> = OnceLock::new();
+
+static REAL_POSIX_SPAWN: OnceLock<Option<PosixSpawnFn>> = OnceLock::new();
+static REAL_POSIX_SPAWNP: OnceLock<Option<PosixSpawnFn>> = OnceLock::new();
+static REAL_EXECVPE: OnceLock<Option<ExecvpeFn>> = OnceLock::new();
+static REAL_FEXECVE: OnceLock<Option<FexecveFn>> = OnceLock::new();
+
+const LD_PRELOAD: &[u8] = b"LD_PRELOAD=";
+
+// Below PIPE_BUF, so concurrent children's records never interleave. Fields are capped at
+// 32 (call), 480 (path) and 240 (each LD_PRELOAD) bytes, which with the header always fits.
+const RECORD_MAX: usize = 1024;
+
+/// Write end of the spawn record pipe, -1 until `init` creates it.
+static RECORD_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" {
+    static environ: *const *const c_char;
+}
+
+struct ChildState {
+    policy: ChildPolicy,
+    /// Our own library path, as it should appear in a child's LD_PRELOAD.
+    self_path: Option<CString>,
+    /// `LD_PRELOAD=<self_path>`, for children that should preload only us.
+    self_preload: Option<CString>,
+    /// HYPER_RASP_* variables as they were when we started, re-injected on `reinject`.
+    rasp_vars: Vec<CString>,
+    /// Process that ran `init`. Its forked children may not log: the subscriber's locks
+    /// can be held by threads that did not survive the fork.
+    pid: pid_t,
+}
+
+type PosixSpawnFn = unsafe extern "C" fn(
+    *mut pid_t,
+    *const c_char,
+    *const libc::posix_spawn_file_actions_t,
+    *const libc::posix_spawnattr_t,
+    *const *mut c_char,
+    *const *mut c_char,
+) -> c_int;
+type ExecvpeFn = unsafe extern "C" fn(*const c_char, *const *const c_char, *const *const c_char) -> c_int;
+type FexecveFn = unsafe extern "C" fn(c_int, *const *const c_char, *const *const c_char) -> c_int;
+
+/// Activates the configured policy. Until then every call passes straight through.
+pub fn init(settings: &Settings) {
+    // Never propagate environment-supplied policy out of a secure-execution process
+    let rasp_vars = if crate::config::is_secure_execution() {
+        Vec::new()
+    } else {
+        std::env::vars()
+            .filter(|(key, _)| key.starts_with("HYPER_RASP_"))
+            .filter_map(|(key, value)| CString::new(format!("{key}={value}")).ok())
+            .collect()
+    };
+    let self_path = crate::integrity::self_path()
+        .and_then(|p| CString::new(p.as_os_str().as_encoded_bytes()).ok());
+    let self_preload = self_path.as_ref()
+        .and_then(|p| CString::new([LD_PRELOAD, p.to_bytes()].concat()).ok());
+    // Resolved now: dlsym takes loader locks and must not run between fork and exec
+    real_fn(&REAL_POSIX_SPAWN, c"posix_spawn");
+    real_fn(&REAL_POSIX_SPAWNP, c"posix_spawnp");
+    real_fn(&REAL_EXECVPE, c"execvpe");
+    real_fn(&REAL_FEXECVE, c"fexecve");
+    let policy = settings.child_processes.policy;
+    let _ = STATE.set(ChildState {
+        policy,
+        self_path,
+        self_preload,
+        rasp_vars,
+        pid: unsafe { libc::getpid() },
+    });
+    if policy != ChildPolicy::Untouched && cfg!(feature = "preload") {
+        start_record_reader();
+    }
+    if policy != ChildPolicy::Untouched && !cfg!(feature = "preload") {
+        event!(TracingLevel::ERROR, "child_processes.policy is set but this build has no interposers; rebuild the library with --features preload");
+    } else if policy != ChildPolicy::Untouched {
+        event!(TracingLevel::INFO,
+            child_policy = ?policy,
+            "Child process policy is best-effort: execl*, system() and popen() bypass it"
+        );
+    }
+}
+
+/// Creates the spawn record pipe and the thread that logs what forked children send on it.
+/// Both ends are close-on-exec, and the write end never blocks: a full pipe drops the record
+/// rather than delaying the exec.
+fn start_record_reader() {
+    let mut fds = [-1; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        event!(TracingLevel::WARN, error = %std::io::Error::last_os_error(), "Could not create spawn record pipe; exec calls in forked children will not be logged");
+        return;
+    }
+    let [read_fd, write_fd] = fds;
+    unsafe { libc::fcntl(write_fd, libc::F_SETFL, libc::O_NONBLOCK) };
+    let mut reader = unsafe { File::from_raw_fd(read_fd) };
+    let started = std::thread::Builder::new()
+        .name("hyper_rasp_spawns".into())
+        .spawn(move || {
+            let mut record = SpawnRecord::empty();
+            while record.read_from(&mut reader).is_ok() {
+                record.log();
+            }
+        });
+    match started {
+        Ok(_) => RECORD_FD.store(write_fd, Ordering::Relaxed),
+        Err(e) => {
+            unsafe { libc::close(write_fd) };
+            event!(TracingLevel::WARN, error = %e, "Could not start spawn record reader; exec calls in forked children will not be logged");
+        }
+    }
+}
+
+fn real_fn<F: Copy>(cell: &OnceLock<Option<F>>, name: &CStr) -> Option<F> {
+    *cell.get_or_init(|| {
+        let sym = unsafe { libc::dlsym(libc::RTLD_NEXT, name.as_ptr()) };
+        if sym.is_null() {
+            None
+        } else {
+            Some(unsafe { std::mem::transmute_copy::<*mut c_void, F>(&sym) })
+        }
+    })
+}
+
+/// Anonymous memory from a raw mmap syscall: unlike malloc it is safe between fork and exec,
+/// and it bypasses the W^X guard's own `mmap`.
+struct Scratch {
+    base: *mut u8,
+    len: usize,
+}
+
+impl Scratch {
+    fn map(len: usize) -> Option<Scratch> {
+        let base = unsafe {
+            libc::syscall(
+                libc::SYS_mmap,
+                std::ptr::null_mut::<c_void>(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        (base != -1).then_some(Scratch { base: base as *mut u8, len })
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        unsafe { libc::syscall(libc::SYS_munmap, self.base, self.len) };
+    }
+}
+
+/// Rewritten environment, valid as long as the block lives.
+struct EnvBlock {
+    _scratch: Scratch,
+    envp: *const *const c_char,
+}
+
+/// Environment pointer array being filled in scratch memory.
+struct EnvArray {
+    base: *mut *const c_char,
+    len: usize,
+}
+
+impl EnvArray {
+    unsafe fn push(&mut self, entry: *const c_char) {
+        *self.base.add(self.len) = entry;
+        self.len += 1;
+    }
+
+    unsafe fn has_prefix(&self, prefix: &[u8]) -> bool {
+        (0..self.len).any(|i| CStr::from_ptr(*self.base.add(i)).to_bytes().starts_with(prefix))
+    }
+}
+
+/// Entries of a NULL-terminated environment array, borrowed in place.
+unsafe fn env_entries<'a>(envp: *const *const c_char) -> impl Iterator<Item = (*const c_char, &'a [u8])> {
+    let mut cursor = envp;
+    std::iter::from_fn(move || {
+        if cursor.is_null() || (*cursor).is_null() {
+            return None;
+        }
+        let entry = *cursor;
+        cursor = cursor.add(1);
+        Some((entry, CStr::from_ptr(entry).to_bytes()))
+    })
+}
+
+/// LD_PRELOAD entries; ld.so accepts both spaces and colons as separators.
+fn preload_entries(value: &[u8]) -> impl Iterator<Item = &[u8]> {
+    value.split(|&b| b == b' ' || b == b':').filter(|lib| !lib.is_empty())
+}
+
+/// Applies `state.policy` to a child environment without touching the heap. Returns None if
+/// the policy leaves it unchanged.
+unsafe fn rewrite_env(state: &ChildState, envp: *const *const c_char) -> Option<EnvBlock> {
+    if state.policy == ChildPolicy::Untouched {
+        return None;
+    }
+    let own = state.self_path.as_ref().map(|p| p.to_bytes());
+    let is_own = |lib: &[u8]| Some(lib) == own;
+
+    // One pointer per entry plus what reinject may add, then room for a rewritten LD_PRELOAD
+    let mut slots = state.rasp_vars.len() + 2;
+    let mut string_bytes = 0;
+    for (_, entry) in env_entries(envp) {
+        slots += 1;
+        if entry.starts_with(LD_PRELOAD) {
+            string_bytes += entry.len() + own.map_or(0, <[u8]>::len) + 2;
+        }
+    }
+    let pointer_bytes = slots * std::mem::size_of::<*const c_char>();
+    let scratch = Scratch::map(pointer_bytes + string_bytes)?;
+    let mut out = EnvArray { base: scratch.base as *mut *const c_char, len: 0 };
+    let mut strings = scratch.base.add(pointer_bytes);
+    let mut changed = false;
+    let mut saw_preload = false;
+
+    for (pointer, entry) in env_entries(envp) {
+        let Some(value) = entry.strip_prefix(LD_PRELOAD) else {
+            out.push(pointer);
+            continue;
+        };
+        saw_preload = true;
+        let has_own = preload_entries(value).any(is_own);
+        match (state.policy, own) {
+            (ChildPolicy::Reinject, Some(own)) if !has_own => {
+                changed = true;
+                if preload_entries(value).next().is_none() {
+                    out.push(state.self_preload.as_ref().map_or(pointer, |p| p.as_ptr()));
+                    continue;
+                }
+                // LD_PRELOAD=<own> <original value>
+                let start = strings;
+                for part in [LD_PRELOAD, own, b" ", value, b"\0"] {
+                    std::ptr::copy_nonoverlapping(part.as_ptr(), strings, part.len());
+                    strings = strings.add(part.len());
+                }
+                out.push(start as *const c_char);
+            }
+            (ChildPolicy::StripForeign, _) if !preload_entries(value).all(is_own) => {
+                changed = true;
+                if let (true, Some(own_preload)) = (has_own, &state.self_preload) {
+                    out.push(own_preload.as_ptr());
+                }
+            }
+            _ => out.push(pointer),
+        }
+    }
+
+    if state.policy == ChildPolicy::Reinject {
+        if let (false, Some(own_preload)) = (saw_preload, &state.self_preload) {
+            out.push(own_preload.as_ptr());
+            changed = true;
+        }
+        for var in &state.rasp_vars {
+            let var_bytes = var.to_bytes();
+            let key_len = var_bytes.iter().position(|&b| b == b'=').map_or(var_bytes.len(), |i| i + 1);
+            if !out.has_prefix(&var_bytes[..key_len]) {
+                out.push(var.as_ptr());
+                changed = true;
+            }
+        }
+    }
+    out.push(std::ptr::null());
+
+    changed.then_some(EnvBlock { _scratch: scratch, envp: out.base as *const *const c_char })
+}
+
+/// What is being executed, readable without allocating.
+enum ChildPath {
+    Path(*const c_char),
+    Fd(c_int),
+}
+
+/// One spawn: pid and parent pid of the calling process (the child itself after a fork), then NUL-terminated call, child path and LD_PRELOAD before
+/// and after the policy, each truncated to fit. Built on the stack so a forked child can fill it.
+struct SpawnRecord {
+    buf: [u8; RECORD_MAX],
+    len: usize,
+}
+
+impl SpawnRecord {
+    const HEADER: usize = 10;
+
+    fn empty() -> Self {
+        SpawnRecord { buf: [0; RECORD_MAX], len: Self::HEADER }
+    }
+
+    unsafe fn new(call: &str, path: &ChildPath, before: *const *const c_char, after: *const *const c_char) -> Self {
+        let mut record = Self::empty();
+        record.buf[2..6].copy_from_slice(&libc::getpid().to_le_bytes());
+        record.buf[6..10].copy_from_slice(&libc::getppid().to_le_bytes());
+        record.push(call.as_bytes());
+        match *path {
+            ChildPath::Path(path) if path.is_null() => record.push(b"<null>"),
+            ChildPath::Path(path) => record.push(CStr::from_ptr(path).to_bytes()),
+            ChildPath::Fd(fd) => {
+                let mut digits = [0u8; 12];
+                record.push_parts(&[b"/proc/self/fd/", format_fd(fd, &mut digits)]);
+            }
+        }
+        for envp in [before, after] {
+            match env_entries(envp).find_map(|(_, entry)| entry.strip_prefix(LD_PRELOAD)) {
+                Some(value) => record.push(value),
+                None => record.push(b"<not set>"),
+            }
+        }
+        let body = (record.len - 2) as u16;
+        record.buf[..2].copy_from_slice(&body.to_le_bytes());
+        record
+    }
+
+    fn push(&mut self, field: &[u8]) {
+        self.push_parts(&[field]);
+    }
+
+    /// Appends one NUL-terminated field, truncated to its share of the record.
+    fn push_parts(&mut self, parts: &[&[u8]]) {
+        let mut room = match self.buf[Self::HEADER..self.len].iter().filter(|&&b| b == 0).count() {
+            0 => 32,
+            1 => 480,
+            _ => 240,
+        };
+        for part in parts {
+            let part = &part[..part.len().min(room)];
+            self.buf[self.len..self.len + part.len()].copy_from_slice(part);
+            self.len += part.len();
+            room -= part.len();
+        }
+        self.buf[self.len] = 0;
+        self.len += 1;
+    }
+
+    /// Hands the record to the process that ran `init`. Async-signal-safe.
+    fn send(&self) {
+        let fd = RECORD_FD.load(Ordering::Relaxed);
+        if fd >= 0 {
+            unsafe { libc::write(fd, self.buf.as_ptr() as *const c_void, self.len) };
+        }
+    }
+
+    fn read_from(&mut self, reader: &mut impl Read) -> std::io::Result<()> {
+        reader.read_exact(&mut self.buf[..2])?;
+        let body = u16::from_le_bytes([self.buf[0], self.buf[1]]) as usize;
+        if !(Self::HEADER - 2..=RECORD_MAX - 2).contains(&body) {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        self.len = body + 2;
+        reader.read_exact(&mut self.buf[2..self.len])
+    }
+
+    fn pid(&self, at: usize) -> i32 {
+        i32::from_le_bytes([self.buf[at], self.buf[at + 1], self.buf[at + 2], self.buf[at + 3]])
+    }
+
+    /// call, child path, LD_PRELOAD before, LD_PRELOAD after.
+    fn fields(&self) -> Vec<String> {
+        self.buf[Self::HEADER..self.len]
+            .split(|&b| b == 0)
+            .take(4)
+            .map(|field| String::from_utf8_lossy(field).into_owned())
+            .collect()
+    }
+
+    /// Logs the spawn. Only for the process that ran `init`.
+    fn log(&self) {
+        let fields = self.fields();
+        let field = |i: usize| fields.get(i).map_or("", String::as_str);
+        let redactor = crate::redaction::active();
+        let preload = |value: &str| if value == "<not set>" { value.to_string() } else { redactor.redact_ld_preload(value) };
+        event!(TracingLevel::INFO,
+            call = field(0),
+            child_path = field(1),
+            caller_pid = self.pid(2),
+            caller_ppid = self.pid(6),
+            child_policy = ?STATE.get().map_or(ChildPolicy::Untouched, |state| state.policy),
+            ld_preload_before = preload(field(2)).as_str(),
+            ld_preload_after = preload(field(3)).as_str(),
+            "Spawning child process"
+        );
+    }
+}
+
+/// Decimal digits of `fd` in `out`, without allocating.
+fn format_fd(fd: c_int, out: &mut [u8; 12]) -> &[u8] {
+    let mut value = fd.unsigned_abs();
+    let mut start = out.len();
+    loop {
+        start -= 1;
+        out[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    &out[start..]
+}
+
+/// Whether this is the process that ran `init` rather than a child forked from it.
+fn may_log(state: &ChildState) -> bool {
+    unsafe { libc::getpid() == state.pid }
+}
+
+/// Returns a replacement environment if the policy changes it, and records the spawn: logged
+/// directly by the process that ran `init`, sent over the record pipe from a forked child.
+unsafe fn prepare(call: &str, envp: *const *const c_char, path: ChildPath) -> Option<EnvBlock> {
+    let state = STATE.get()?;
+    let block = rewrite_env(state, envp);
+    let record = SpawnRecord::new(call, &path, envp, block.as_ref().map_or(envp, |b| b.envp));
+    if may_log(state) {
+        record.log();
+    } else {
+        record.send();
+    }
+    block
+}
+
+fn set_errno(value: c_int) {
+    unsafe { *libc::__errno_location() = value };
+}
+
+unsafe fn exec_path(call: &str, path: *const c_char, argv: *const *const c_char, envp: *const *const c_char) -> c_int {
+    let block = prepare(call, envp, ChildPath::Path(path));
+    let envp = block.as_ref().map_or(envp, |b| b.envp);
+    libc::syscall(libc::SYS_execve, path, argv, envp) as c_int
+}
+
+unsafe fn exec_search(call: &str, file: *const c_char, argv: *const *const c_char, envp: *const *const c_char) -> c_int {
+    let Some(real) = real_fn(&REAL_EXECVPE, c"execvpe") else {
+        set_errno(libc::ENOSYS);
+        return -1;
+    };
+    let block = prepare(call, envp, ChildPath::Path(file));
+    real(file, argv, block.as_ref().map_or(envp, |b| b.envp))
+}
+
+/// # Safety
+/// Same contract as libc `execve`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn execve(path: *const c_char, argv: *const *const c_char, envp: *const *const c_char) -> c_int {
+    exec_path("execve", path, argv, envp)
+}
+
+/// # Safety
+/// Same contract as libc `execv`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn execv(path: *const c_char, argv: *const *const c_char) -> c_int {
+    exec_path("execv", path, argv, environ)
+}
+
+/// # Safety
+/// Same contract as libc `execvpe`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn execvpe(file: *const c_char, argv: *const *const c_char, envp: *const *const c_char) -> c_int {
+    exec_search("execvpe", file, argv, envp)
+}
+
+/// # Safety
+/// Same contract as libc `execvp`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn execvp(file: *const c_char, argv: *const *const c_char) -> c_int {
+    exec_search("execvp", file, argv, environ)
+}
+
+/// # Safety
+/// Same contract as libc `execveat`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn execveat(
+    dirfd: c_int,
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+    flags: c_int,
+) -> c_int {
+    let block = prepare("execveat", envp, ChildPath::Path(path));
+    let envp = block.as_ref().map_or(envp, |b| b.envp);
+    libc::syscall(libc::SYS_execveat, dirfd, path, argv, envp, flags) as c_int
+}
+
+/// # Safety
+/// Same contract as libc `fexecve`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn fexecve(fd: c_int, argv: *const *const c_char, envp: *const *const c_char) -> c_int {
+    let Some(real) = real_fn(&REAL_FEXECVE, c"fexecve") else {
+        set_errno(libc::ENOSYS);
+        return -1;
+    };
+    let block = prepare("fexecve", envp, ChildPath::Fd(fd));
+    real(fd, argv, block.as_ref().map_or(envp, |b| b.envp))
+}
+
+// Mirrors the posix_spawn signature, plus the symbol to forward to
+#[allow(clippy::too_many_arguments)]
+unsafe fn spawn_with(
+    call: &str,
+    real: &OnceLock<Option<PosixSpawnFn>>,
+    name: &CStr,
+    pid: *mut pid_t,
+    path: *const c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut c_char,
+    envp: *const *mut c_char,
+) -> c_int {
+    let Some(real) = real_fn(real, name) else {
+        return libc::ENOSYS;
+    };
+    let block = prepare(call, envp as *const *const c_char, ChildPath::Path(path));
+    let envp = block.as_ref().map_or(envp, |b| b.envp as *const *mut c_char);
+    let rc = real(pid, path, file_actions, attrp, argv, envp);
+    if rc == 0 && !pid.is_null() && STATE.get().is_some_and(may_log) {
+        event!(TracingLevel::INFO, call, child_pid = *pid, "Child process spawned");
+    }
+    rc
+}
+
+/// # Safety
+/// Same contract as libc `posix_spawn`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn posix_spawn(
+    pid: *mut pid_t,
+    path: *const c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut c_char,
+    envp: *const *mut c_char,
+) -> c_int {
+    spawn_with("posix_spawn", &REAL_POSIX_SPAWN, c"posix_spawn", pid, path, file_actions, attrp, argv, envp)
+}
+
+/// # Safety
+/// Same contract as libc `posix_spawnp`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn posix_spawnp(
+    pid: *mut pid_t,
+    file: *const c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut c_char,
+    envp: *const *mut c_char,
+) -> c_int {
+    spawn_with("posix_spawnp", &REAL_POSIX_SPAWNP, c"posix_spawnp", pid, file, file_actions, attrp, argv, envp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWN: &str = "/usr/lib/libhyper_processor.so";
+
+    fn state(policy: ChildPolicy) -> ChildState {
+        ChildState {
+            policy,
+            self_path: Some(CString::new(OWN).unwrap()),
+            self_preload: Some(CString::new(format!("LD_PRELOAD={OWN}")).unwrap()),
+            rasp_vars: vec![CString::new("HYPER_RASP_CONFIG=/etc/rasp.yaml").unwrap()],
+            pid: unsafe { libc::getpid() },
+        }
+    }
+
+    fn env(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rewrite(state: &ChildState, child: &[String]) -> Option<Vec<String>> {
+        let strings: Vec<CString> = child.iter().map(|e| CString::new(e.as_str()).unwrap()).collect();
+        let mut pointers: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr()).collect();
+        pointers.push(std::ptr::null());
+        let block = unsafe { rewrite_env(state, pointers.as_ptr()) }?;
+        let out = unsafe { env_entries(block.envp) }
+            .map(|(_, entry)| String::from_utf8_lossy(entry).into_owned())
+            .collect();
+        Some(out)
+    }
+
+    #[test]
+    fn test_forked_child_sends_spawn_record() {
+        let strings = [CString::new("LD_PRELOAD=/tmp/libevil.so").unwrap(), CString::new("PATH=/bin").unwrap()];
+        let before = [strings[0].as_ptr(), strings[1].as_ptr(), std::ptr::null()];
+        let after = [strings[1].as_ptr(), std::ptr::null()];
+        let path = CString::new("/usr/bin/env").unwrap();
+
+        let mut fds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        RECORD_FD.store(fds[1], Ordering::Relaxed);
+        let child = unsafe { libc::fork() };
+        if child == 0 {
+            // Only async-signal-safe work, as between fork and exec
+            unsafe {
+                SpawnRecord::new("execve", &ChildPath::Path(path.as_ptr()), before.as_ptr(), after.as_ptr()).send();
+                libc::_exit(0);
+            }
+        }
+        RECORD_FD.store(-1, Ordering::Relaxed);
+        unsafe { libc::close(fds[1]) };
+        let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+        let mut record = SpawnRecord::empty();
+        record.read_from(&mut reader).unwrap();
+        unsafe { libc::waitpid(child, std::ptr::null_mut(), 0) };
+
+        assert_eq!(record.pid(2), child);
+        assert_eq!(record.pid(6), unsafe { libc::getpid() });
+        assert_eq!(record.fields(), ["execve", "/usr/bin/env", "/tmp/libevil.so", "<not set>"]);
+    }
+
+    #[test]
+    fn test_spawn_record_truncates_long_fields() {
+        let long = CString::new(format!("/{}", "a".repeat(4000))).unwrap();
+        let empty = [std::ptr::null()];
+        let record = unsafe { SpawnRecord::new("fexecve", &ChildPath::Fd(17), empty.as_ptr(), empty.as_ptr()) };
+        assert_eq!(record.fields()[1], "/proc/self/fd/17");
+        let record = unsafe { SpawnRecord::new("execve", &ChildPath::Path(long.as_ptr()), empty.as_ptr(), empty.as_ptr()) };
+        assert_eq!(record.fields()[0], "execve");
+        assert_eq!(record.fields()[1].len(), 480);
+        assert_eq!(record.fields()[3], "<not set>");
+        assert!(record.len <= RECORD_MAX);
+    }
+
+    #[test]
+    fn test_untouched() {
+        let child = env(&["PATH=/bin", "LD_PRELOAD=/tmp/libevil.so"]);
+        assert_eq!(rewrite(&state(ChildPolicy::Untouched), &child), None);
+    }
+
+    #[test]
+    fn test_reinject_missing_preload() {
+        let child = env(&["PATH=/bin"]);
+        let out = rewrite(&state(ChildPolicy::Reinject), &child).unwrap();
+        assert!(out.contains(&format!("LD_PRELOAD={OWN}")));
+        assert!(out.contains(&"HYPER_RASP_CONFIG=/etc/rasp.yaml".to_string()));
+    }
+
+    #[test]
+    fn test_reinject_keeps_existing_vars() {
+        let child = env(&[&format!("LD_PRELOAD={OWN}"), "HYPER_RASP_CONFIG=/other.yaml"]);
+        assert_eq!(rewrite(&state(ChildPolicy::Reinject), &child), None);
+    }
+
+    #[test]
+    fn test_reinject_prepends_self() {
+        let child = env(&["LD_PRELOAD=/opt/libfoo.so", "HYPER_RASP_CONFIG=/etc/rasp.yaml"]);
+        let out = rewrite(&state(ChildPolicy::Reinject), &child).unwrap();
+        assert_eq!(out[0], format!("LD_PRELOAD={OWN} /opt/libfoo.so"));
+
+        let empty = env(&["LD_PRELOAD=", "HYPER_RASP_CONFIG=/etc/rasp.yaml"]);
+        let out = rewrite(&state(ChildPolicy::Reinject), &empty).unwrap();
+        assert_eq!(out[0], format!("LD_PRELOAD={OWN}"));
+    }
+
+    #[test]
+    fn test_strip_foreign() {
+        let child = env(&[&format!("LD_PRELOAD=/tmp/libevil.so:{OWN}"), "PATH=/bin"]);
+        let out = rewrite(&state(ChildPolicy::StripForeign), &child).unwrap();
+        assert_eq!(out, env(&[&format!("LD_PRELOAD={OWN}"), "PATH=/bin"]));
+
+        let only_foreign = env(&["LD_PRELOAD=/tmp/libevil.so", "PATH=/bin"]);
+        let out = rewrite(&state(ChildPolicy::StripForeign), &only_foreign).unwrap();
+        assert_eq!(out, env(&["PATH=/bin"]));
+    }
+
+    #[test]
+    fn test_rewrite_large_environment() {
+        let mut child: Vec<String> = (0..5000).map(|i| format!("VAR_{i}={}", "x".repeat(64))).collect();
+        child.push("LD_PRELOAD=/opt/a.so /opt/b.so".to_string());
+        let out = rewrite(&state(ChildPolicy::Reinject), &child).unwrap();
+        assert_eq!(out.len(), 5002);
+        assert_eq!(out[5000], format!("LD_PRELOAD={OWN} /opt/a.so /opt/b.so"));
+    }
+}
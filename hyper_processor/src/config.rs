@@ -1,62 +1,589 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 use std::fs;
-use std::os::unix::fs::PermissionsExt;
-use std::sync::Mutex;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct Settings {
     #[serde(default)]
     pub whitelisted_filenames: Vec<String>,
+    /// Libraries allowed by exact path, optionally pinned to a SHA256 (see `generate`).
+    #[serde(default)]
+    pub whitelisted_libraries: Vec<PinnedLibrary>,
     #[serde(default)]
     pub audit_mode: bool,
     #[serde(default)]
     pub learning_mode: bool,
     #[serde(default)]
     pub learning_output: Option<String>,
+    /// How often learning mode rescans for dlopen()ed libraries, in ms (default 500, 0 = start-up and exit only).
+    #[serde(default)]
+    pub learning_scan_interval_ms: Option<u64>,
+    #[serde(default)]
+    pub self_integrity: SelfIntegrity,
+    #[serde(default)]
+    pub seccomp: SeccompSettings,
+    #[serde(default)]
+    pub wx_guard: WxSettings,
+    #[serde(default)]
+    pub child_processes: ChildSettings,
+    /// Ignore every HYPER_RASP_* override from the environment. Only read from the config file.
+    #[serde(default)]
+    pub freeze_policy: bool,
+    /// Where log events go (see `sinks`). Empty means JSON on stderr.
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// Event layout for line-oriented sinks (stderr, syslog, file, datagram). Journald stays native.
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default)]
+    pub alerting: AlertSettings,
+    #[serde(default)]
+    pub forensics: ForensicsSettings,
+    #[serde(default)]
+    pub event_store: EventStoreSettings,
+    #[serde(default)]
+    pub redaction: RedactionSettings,
+    #[serde(default)]
+    pub dedup: DedupSettings,
+    /// OTLP export of spans, events and counters (needs the `otel` feature).
+    #[serde(default)]
+    pub otel: OtelSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    /// Reporting to `hyper-processor monitor`: check results, detections, heartbeats and counters.
+    #[serde(default)]
+    pub monitor: MonitorSettings,
+    /// HYPER_RASP_* variables that were present but ignored because the policy is frozen.
+    #[serde(skip)]
+    pub ignored_env_overrides: Vec<(String, String)>,
+}
+
+/// A library allowed only when mapped from `path` and, if `sha256` is set, with that content.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PinnedLibrary {
+    pub path: String,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// Pins the identity of libhyper_processor.so itself (see `integrity::verify_self`).
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SelfIntegrity {
+    /// Absolute path the library must be loaded from.
+    #[serde(default)]
+    pub expected_path: Option<String>,
+    /// Hex-encoded SHA256 digest the library file must match.
+    #[serde(default)]
+    pub expected_sha256: Option<String>,
+}
+
+/// Seccomp lockdown installed after a successful preload check (see `seccomp::install`).
+/// Installing a profile also sets PR_SET_NO_NEW_PRIVS for good: later execs of this process
+/// and its children no longer gain privileges from setuid/setgid bits or file capabilities.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct SeccompSettings {
+    /// Name of the profile to install; `None` installs nothing.
+    /// "post-check" selects the built-in profile unless overridden in `profiles`.
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, SeccompProfile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SeccompProfile {
+    /// Syscall names (or numbers) to deny.
+    #[serde(default)]
+    pub deny_syscalls: Vec<String>,
+    #[serde(default)]
+    pub action: SeccompAction,
+    /// errno returned when `action` is `errno`, 1..=4095.
+    #[serde(default = "default_seccomp_errno", deserialize_with = "deserialize_seccomp_errno")]
+    pub errno: i32,
+}
+
+impl Default for SeccompProfile {
+    fn default() -> Self {
+        SeccompProfile {
+            deny_syscalls: Vec::new(),
+            action: SeccompAction::default(),
+            errno: default_seccomp_errno(),
+        }
+    }
+}
+
+fn default_seccomp_errno() -> i32 {
+    libc::EPERM
+}
+
+/// Largest errno a seccomp filter can return.
+pub const MAX_SECCOMP_ERRNO: i32 = 4095;
+
+// SECCOMP_RET_DATA is 16 bits but the kernel only treats 1..=4095 as an errno; anything
+// else would be truncated into a different (or no) error
+fn deserialize_seccomp_errno<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    let errno = i32::deserialize(deserializer)?;
+    if !(1..=MAX_SECCOMP_ERRNO).contains(&errno) {
+        return Err(serde::de::Error::custom(format!(
+            "seccomp errno {} is out of range 1..={}", errno, MAX_SECCOMP_ERRNO
+        )));
+    }
+    Ok(errno)
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SeccompAction {
+    /// Fail the syscall with `errno`.
+    #[default]
+    Errno,
+    /// Kill the whole process.
+    Kill,
+    /// Allow the syscall but have the kernel log it.
+    Log,
+}
+
+/// W^X enforcement for `mmap`/`mprotect`/`pkey_mprotect` (see `wx_guard`).
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct WxSettings {
+    #[serde(default)]
+    pub mode: WxMode,
+    /// Library filenames (e.g. JIT runtimes) allowed to create executable writable or anonymous memory.
+    #[serde(default)]
+    pub allowed_libraries: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WxMode {
+    /// Calls pass straight through.
+    #[default]
+    Off,
+    /// Violations are logged but allowed.
+    Log,
+    /// Violations fail with EACCES.
+    Deny,
+}
+
+/// What exec'd and `posix_spawn`ed children inherit (see `child_policy`). Best-effort: children
+/// started through glibc's `execl*`, `system()` or `popen()` are not covered.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ChildSettings {
+    #[serde(default)]
+    pub policy: ChildPolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChildPolicy {
+    /// Pass the child environment through as-is.
+    #[default]
+    Untouched,
+    /// Make sure our library and the HYPER_RASP_* variables we started with reach the child.
+    Reinject,
+    /// Remove every LD_PRELOAD entry except our own library.
+    StripForeign,
+}
+
+/// The only config file consulted for setuid/AT_SECURE processes. Must be root-owned.
+pub const SECURE_CONFIG_PATH: &str = match option_env!("HYPER_RASP_SECURE_CONFIG") {
+    Some(path) => path,
+    None => "/etc/hyper_processor/rasp_config.yaml",
+};
+
+/// True if the loader runs us in secure-execution mode (setuid/setgid binaries, file capabilities).
+/// In that mode the environment is attacker-controlled and must not influence policy.
+pub fn is_secure_execution() -> bool {
+    unsafe { libc::getauxval(libc::AT_SECURE) != 0 }
+}
+
+/// Config file read when HYPER_RASP_CONFIG is unset, relative to the working directory.
+pub const DEFAULT_CONFIG_PATH: &str = "rasp_config.yaml";
+
+/// Path of the config file `Settings::load` reads: HYPER_RASP_CONFIG or rasp_config.yaml,
+/// unless a frozen policy pins the file (see `pinned_config_path`).
+pub fn config_path() -> String {
+    config_path_with_pins(&default_pins())
+}
+
+/// Candidates that may pin themselves with `freeze_policy`, with whether they must be
+/// root-owned and not group/other-writable to count. The cwd-relative default file is not
+/// one: whoever can write the working directory could otherwise override HYPER_RASP_CONFIG.
+fn default_pins() -> [(&'static Path, bool); 1] {
+    [(Path::new(SECURE_CONFIG_PATH), true)]
+}
+
+fn config_path_with_pins(pins: &[(&Path, bool)]) -> String {
+    pinned_config_path(pins)
+        .or_else(|| std::env::var("HYPER_RASP_CONFIG").ok())
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string())
+}
+
+/// The first of `pins` that declares `freeze_policy: true`. A frozen policy must not be
+/// escapable by pointing HYPER_RASP_CONFIG at another file.
+fn pinned_config_path(pins: &[(&Path, bool)]) -> Option<String> {
+    pins.iter().find_map(|&(path, require_root)| {
+        let metadata = fs::metadata(path).ok()?;
+        if require_root && (metadata.uid() != 0 || metadata.mode() & 0o022 != 0) {
+            return None;
+        }
+        let frozen = config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()
+            .ok()?
+            .get_bool("freeze_policy")
+            .unwrap_or(false);
+        frozen.then(|| path.to_string_lossy().into_owned())
+    })
+}
+
+/// Webhook notification for detections (see `alerting`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlertSettings {
+    /// `http://host[:port]/path` to POST the detection JSON to. Unset disables alerting.
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Upper bound for connect, send and response combined, so startup never hangs on delivery.
+    #[serde(default = "default_alert_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Directory where undelivered alerts are kept for `hyper-processor alerts flush`.
+    #[serde(default)]
+    pub spool_dir: Option<String>,
+    /// Also alert on audit-mode detections, not only on blocking ones.
+    #[serde(default)]
+    pub include_audit: bool,
+}
+
+impl Default for AlertSettings {
+    fn default() -> Self {
+        AlertSettings {
+            webhook_url: None,
+            timeout_ms: default_alert_timeout_ms(),
+            spool_dir: None,
+            include_audit: false,
+        }
+    }
+}
+
+fn default_alert_timeout_ms() -> u64 {
+    1500
+}
+
+/// Forensic bundle written on detection (see `forensics`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ForensicsSettings {
+    /// Directory for `hyper_rasp-<pid>-<millis>.tar` bundles. Unset disables snapshots.
+    #[serde(default)]
+    pub dir: Option<String>,
+    /// Offending libraries larger than this are listed in the manifest but not copied.
+    #[serde(default = "default_forensics_max_library_bytes")]
+    pub max_library_bytes: u64,
+    /// Keep environment values (after redaction rules) instead of masking every value.
+    #[serde(default)]
+    pub raw_environ: bool,
+}
+
+impl Default for ForensicsSettings {
+    fn default() -> Self {
+        ForensicsSettings {
+            dir: None,
+            max_library_bytes: default_forensics_max_library_bytes(),
+            raw_environ: false,
+        }
+    }
+}
+
+fn default_forensics_max_library_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+/// Local detection history queried by `hyper-processor events` (see `event_store`).
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct EventStoreSettings {
+    /// Directory holding `events.jsonl` and its time index. Unset disables the store.
+    #[serde(default)]
+    pub dir: Option<String>,
+}
+
+/// Rules for scrubbing secrets from cmdline, LD_PRELOAD and environ before they are logged
+/// or written to forensic bundles (see `redaction`).
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct RedactionSettings {
+    /// Regexes matched against each argv token. With capture groups only the groups are replaced.
+    #[serde(default)]
+    pub argv_patterns: Vec<String>,
+    /// Flags whose following argument is always redacted (e.g. `--token`).
+    #[serde(default)]
+    pub secret_flags: Vec<String>,
+    #[serde(default)]
+    pub mode: RedactionMode,
+    /// Longest cmdline / LD_PRELOAD / environ value kept, in characters.
+    #[serde(default)]
+    pub max_length: Option<usize>,
+}
+
+/// What a redacted value is replaced with.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionMode {
+    /// `[REDACTED]`
+    #[default]
+    Mask,
+    /// `[sha256:<16 hex>]`, so equal secrets can still be correlated.
+    Hash,
+}
+
+/// Suppression of repeated identical detections (see `dedup`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DedupSettings {
+    /// Identical (path, hash, verdict) detections within this many seconds are reported once,
+    /// followed by a "suppressed N repeats" summary. 0 disables deduplication. The window is
+    /// shared by all processes through `dedup.json` in `event_store.dir`; without an event
+    /// store every detection is reported.
+    #[serde(default = "default_dedup_window_secs")]
+    pub window_secs: u64,
+}
+
+impl Default for DedupSettings {
+    fn default() -> Self {
+        DedupSettings { window_secs: default_dedup_window_secs() }
+    }
+}
+
+fn default_dedup_window_secs() -> u64 {
+    60
+}
+
+/// Prometheus metrics of the protected process (needs the `metrics` feature).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetricsSettings {
+    /// Distinct `library` label values kept after version suffixes are stripped; further
+    /// libraries are counted under `other`.
+    #[serde(default = "default_max_library_labels")]
+    pub max_library_labels: usize,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings { max_library_labels: default_max_library_labels() }
+    }
+}
+
+fn default_max_library_labels() -> usize {
+    100
+}
+
+/// Connection to the local `hyper-processor monitor` collector (see `monitor`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MonitorSettings {
+    /// Datagram socket the monitor listens on, normally `monitor::DEFAULT_SOCKET`. Unset
+    /// (the default) sends no reports and starts no heartbeat thread.
+    #[serde(default)]
+    pub socket: Option<String>,
+    /// Value of the `profile` label the monitor attaches to this process.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Seconds between heartbeats after a passing check. 0 sends none.
+    #[serde(default = "default_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+}
+
+impl Default for MonitorSettings {
+    fn default() -> Self {
+        MonitorSettings {
+            socket: None,
+            profile: None,
+            heartbeat_secs: default_heartbeat_secs(),
+        }
+    }
+}
+
+fn default_heartbeat_secs() -> u64 {
+    30
+}
+
+/// OpenTelemetry export over OTLP/HTTP with JSON encoding (see `otel`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OtelSettings {
+    /// Collector base URL, e.g. `http://127.0.0.1:4318`. Unset disables export.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default = "default_otel_service_name")]
+    pub service_name: String,
+    /// Upper bound on each export request.
+    #[serde(default = "default_otel_timeout_ms")]
+    pub timeout_ms: u64,
+    /// How often batched records and counters are pushed.
+    #[serde(default = "default_otel_export_interval_ms")]
+    pub export_interval_ms: u64,
+}
+
+impl Default for OtelSettings {
+    fn default() -> Self {
+        OtelSettings {
+            endpoint: None,
+            service_name: default_otel_service_name(),
+            timeout_ms: default_otel_timeout_ms(),
+            export_interval_ms: default_otel_export_interval_ms(),
+        }
+    }
+}
+
+fn default_otel_service_name() -> String {
+    "hyper_processor".to_string()
+}
+
+fn default_otel_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_otel_export_interval_ms() -> u64 {
+    1000
+}
+
+/// Event layout written by line-oriented sinks.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// tracing-subscriber JSON, with span context.
+    #[default]
+    Json,
+    /// ArcSight Common Event Format.
+    Cef,
+    /// Elastic Common Schema JSON.
+    Ecs,
+}
+
+/// A log event destination, selected by `type` in the config file.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// JSON lines on stderr.
+    Stderr,
+    /// RFC 5424 over a local syslog datagram socket.
+    Syslog {
+        #[serde(default = "default_syslog_socket")]
+        socket: String,
+        /// Syslog facility number (1 = user, 4 = auth, 10 = authpriv).
+        #[serde(default = "default_syslog_facility")]
+        facility: u8,
+    },
+    /// Native journald protocol, with event and span fields as journal fields.
+    Journald {
+        #[serde(default = "default_journald_socket")]
+        socket: String,
+    },
+    /// JSON lines appended to a file, rotated at `max_bytes` keeping `max_files` old files.
+    /// Processes sharing the path coordinate rotation through an flock on `<path>.lock`.
+    File {
+        path: String,
+        #[serde(default = "default_file_max_bytes")]
+        max_bytes: u64,
+        #[serde(default = "default_file_max_files")]
+        max_files: usize,
+    },
+    /// One JSON datagram per event to a local collector socket.
+    UnixDatagram {
+        path: String,
+    },
+}
+
+fn default_syslog_socket() -> String {
+    String::from("/dev/log")
+}
+
+fn default_syslog_facility() -> u8 {
+    1
+}
+
+fn default_journald_socket() -> String {
+    String::from("/run/systemd/journal/socket")
+}
+
+fn default_file_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_file_max_files() -> usize {
+    5
+}
+
+/// HYPER_RASP_* variables that would change policy. HYPER_RASP_CONFIG only selects the file
+/// and is reported separately when a pinned file takes precedence over it.
+pub fn env_overrides() -> Vec<(String, String)> {
+    std::env::vars()
+        .filter(|(key, _)| key.starts_with("HYPER_RASP_") && key != "HYPER_RASP_CONFIG")
+        .collect()
 }
 
 impl Settings {
     /// Loads configuration from file (default: rasp_config.yaml) and environment variables.
     pub fn load() -> Result<Self, config::ConfigError> {
-        let config_path_str = std::env::var("HYPER_RASP_CONFIG")
-            .unwrap_or_else(|_| "rasp_config.yaml".to_string());
+        Self::load_with_pins(&default_pins())
+    }
+
+    fn load_with_pins(pins: &[(&Path, bool)]) -> Result<Self, config::ConfigError> {
+        let config_path_str = config_path_with_pins(pins);
         let config_path = Path::new(&config_path_str);
 
-        let builder = config::Config::builder()
+        let file_source = config::File::with_name(&config_path_str).required(false);
+
+        // freeze_policy is only ever honoured from the file, never from the environment
+        let freeze_policy = config::Config::builder()
+            .add_source(file_source.clone())
+            .build()?
+            .get_bool("freeze_policy")
+            .unwrap_or(false);
+
+        let mut builder = config::Config::builder()
             // Defaults are now handled entirely by `serde(default)` and `Default` trait
             
             // Load config file (optional)
-            .add_source(config::File::with_name(&config_path_str).required(false))
+            .add_source(file_source);
             
+        if !freeze_policy {
             // Load environment variables (HYPER_RASP_AUDIT_MODE, HYPER_RASP_WHITELISTED_FILENAMES)
-            .add_source(config::Environment::with_prefix("HYPER_RASP").separator("__"));
+            builder = builder.add_source(config::Environment::with_prefix("HYPER_RASP").separator("__"));
+        }
             
         // Build and deserialize
         let config = builder.build()?;
         let mut settings: Self = config.try_deserialize()?;
+        settings.freeze_policy = freeze_policy;
 
-        // Handle HYPER_RASP_WHITELIST environment variable explicitly
-        // The config crate expects HYPER_RASP_WHITELISTED_FILENAMES but users might use HYPER_RASP_WHITELIST
-        if let Ok(whitelist_str) = std::env::var("HYPER_RASP_WHITELIST") {
-            let whitelist_items: Vec<String> = whitelist_str
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .filter(|s| !s.is_empty())
-                .collect();
+        if freeze_policy {
+            // Remember what was attempted so init_library can report it once logging is up
+            settings.ignored_env_overrides = env_overrides();
+            if let Ok(requested) = std::env::var("HYPER_RASP_CONFIG") {
+                if requested != config_path_str {
+                    settings.ignored_env_overrides.push((String::from("HYPER_RASP_CONFIG"), requested));
+                }
+            }
+        } else {
+            // Handle HYPER_RASP_WHITELIST environment variable explicitly
+            // The config crate expects HYPER_RASP_WHITELISTED_FILENAMES but users might use HYPER_RASP_WHITELIST
+            if let Ok(whitelist_str) = std::env::var("HYPER_RASP_WHITELIST") {
+                let whitelist_items: Vec<String> = whitelist_str
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
             
-            if !whitelist_items.is_empty() {
-                settings.whitelisted_filenames = whitelist_items;
+                if !whitelist_items.is_empty() {
+                    settings.whitelisted_filenames = whitelist_items;
+                }
             }
-        }
         
-        // Handle HYPER_RASP_LEARNING_MODE environment variable
-        if let Ok(learning) = std::env::var("HYPER_RASP_LEARNING_MODE") {
-            if learning.to_lowercase() == "true" {
-                settings.learning_mode = true;
-                // If learning mode is enabled, also check for output file
-                if let Ok(output) = std::env::var("HYPER_RASP_LEARNING_OUTPUT") {
-                    settings.learning_output = Some(output);
+            // Handle HYPER_RASP_LEARNING_MODE environment variable
+            if let Ok(learning) = std::env::var("HYPER_RASP_LEARNING_MODE") {
+                if learning.to_lowercase() == "true" {
+                    settings.learning_mode = true;
+                    // If learning mode is enabled, also check for output file
+                    if let Ok(output) = std::env::var("HYPER_RASP_LEARNING_OUTPUT") {
+                        settings.learning_output = Some(output);
+                    }
                 }
             }
         }
@@ -90,6 +617,8 @@ impl Settings {
                             config_path.display(),
                             mode & 0o777 // Display standard permission bits
                          );
+                         #[cfg(feature = "metrics")]
+                         crate::metrics::record_permission_warning();
                          // We don't use log::warn here because the logger initialization
                          // might happen *after* config loading in src/lib.rs.
                          // eprintln ensures the message is seen during startup.
@@ -112,9 +641,67 @@ impl Settings {
 
         Ok(settings)
     }
+
+    /// Loads configuration for a secure-execution process: only `SECURE_CONFIG_PATH` is read,
+    /// no environment variables are consulted, and the file must be root-owned and not
+    /// writable by group or others. A missing file yields the (blocking) defaults.
+    pub fn load_secure() -> Result<Self, config::ConfigError> {
+        Self::load_trusted(Path::new(SECURE_CONFIG_PATH))
+    }
+
+    fn load_trusted(path: &Path) -> Result<Self, config::ConfigError> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Settings::default()),
+            Err(e) => {
+                return Err(config::ConfigError::Message(format!(
+                    "Could not read metadata for '{}': {}", path.display(), e
+                )));
+            }
+        };
+        if metadata.uid() != 0 || metadata.mode() & 0o022 != 0 {
+            return Err(config::ConfigError::Message(format!(
+                "Refusing '{}' in secure-execution mode: must be owned by root and not group/other-writable (uid {}, mode {:#o})",
+                path.display(),
+                metadata.uid(),
+                metadata.mode() & 0o777
+            )));
+        }
+
+        let config = config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()?;
+        let mut settings: Self = config.try_deserialize()?;
+        if settings.learning_mode {
+            settings.audit_mode = true;
+        }
+        Ok(settings)
+    }
+
+    /// The settings as JSON, safe to expose: webhook URLs often embed a token in their path
+    /// or query, so only scheme and host are kept.
+    pub fn effective_policy(&self) -> serde_json::Value {
+        let mut policy = serde_json::to_value(self).unwrap_or_default();
+        if let Some(url) = self.alerting.webhook_url.as_deref() {
+            policy["alerting"]["webhook_url"] = serde_json::Value::from(redact_url(url));
+        }
+        policy
+    }
+}
+
+/// Keeps `scheme://host[:port]` of `url` and masks the rest (including any userinfo).
+fn redact_url(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    if scheme.is_empty() {
+        return String::from("[REDACTED]");
+    }
+    format!("{scheme}://{host}/[REDACTED]")
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use std::fs;
@@ -132,14 +719,30 @@ mod tests {
         std::env::remove_var("HYPER_RASP_WHITELISTED_FILENAMES");
         std::env::remove_var("HYPER_RASP_LEARNING_MODE");
         std::env::remove_var("HYPER_RASP_LEARNING_OUTPUT");
+        std::env::remove_var("HYPER_RASP_FREEZE_POLICY");
     }
     
+    #[test]
+    fn test_effective_policy_redacts_webhook() {
+        let mut settings = Settings {
+            whitelisted_filenames: vec!["libfoo.so".into()],
+            ..Default::default()
+        };
+        settings.alerting.webhook_url = Some("http://user:pw@hooks.local:8080/T000/secret-token?x=1".into());
+        let policy = settings.effective_policy();
+        assert_eq!(policy["whitelisted_filenames"][0], "libfoo.so");
+        assert_eq!(policy["alerting"]["webhook_url"], "http://hooks.local:8080/[REDACTED]");
+        assert_eq!(policy["log_format"], "json");
+        assert!(policy.get("ignored_env_overrides").is_none());
+    }
+
     #[test]
     fn test_default_settings() {
         let _guard = TEST_MUTEX.lock().unwrap();
         let settings = Settings::default();
         assert_eq!(settings.audit_mode, false);
         assert_eq!(settings.whitelisted_filenames.len(), 0);
+        assert!(settings.monitor.socket.is_none());
     }
     
     #[test]
@@ -250,4 +853,232 @@ whitelisted_filenames:
         // Clean up
         clear_env_vars();
     }
-} 
\ No newline at end of file
+    
+    #[test]
+    fn test_self_integrity_from_yaml() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        clear_env_vars(); // Clean start
+        
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("test_config.yaml");
+        
+        let yaml_content = r#"
+self_integrity:
+  expected_path: /usr/lib64/libhyper_processor.so
+  expected_sha256: abc123
+"#;
+        
+        fs::write(&config_path, yaml_content).unwrap();
+        std::env::set_var("HYPER_RASP_CONFIG", config_path.to_str().unwrap());
+        
+        let settings = Settings::load().unwrap();
+        
+        assert_eq!(settings.self_integrity.expected_path.as_deref(), Some("/usr/lib64/libhyper_processor.so"));
+        assert_eq!(settings.self_integrity.expected_sha256.as_deref(), Some("abc123"));
+        
+        // Clean up
+        clear_env_vars();
+    }
+    
+    #[test]
+    fn test_seccomp_profiles_from_yaml() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        clear_env_vars(); // Clean start
+        
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("test_config.yaml");
+        
+        let yaml_content = r#"
+seccomp:
+  profile: strict
+  profiles:
+    strict:
+      deny_syscalls: [ptrace, memfd_create]
+      action: kill
+"#;
+        
+        fs::write(&config_path, yaml_content).unwrap();
+        std::env::set_var("HYPER_RASP_CONFIG", config_path.to_str().unwrap());
+        
+        let settings = Settings::load().unwrap();
+        
+        assert_eq!(settings.seccomp.profile.as_deref(), Some("strict"));
+        let profile = &settings.seccomp.profiles["strict"];
+        assert_eq!(profile.deny_syscalls, vec!["ptrace", "memfd_create"]);
+        assert_eq!(profile.action, SeccompAction::Kill);
+        assert_eq!(profile.errno, libc::EPERM);
+        
+        // Clean up
+        clear_env_vars();
+    }
+
+    #[test]
+    fn test_seccomp_errno_out_of_range_rejected() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        clear_env_vars();
+
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("test_config.yaml");
+        std::env::set_var("HYPER_RASP_CONFIG", config_path.to_str().unwrap());
+
+        for errno in ["0", "-1", "4096", "65537"] {
+            let yaml_content = format!("seccomp:\n  profiles:\n    strict:\n      errno: {errno}\n");
+            fs::write(&config_path, yaml_content).unwrap();
+            assert!(Settings::load().is_err(), "errno {errno} should be rejected");
+        }
+        fs::write(&config_path, "seccomp:\n  profiles:\n    strict:\n      errno: 4095\n").unwrap();
+        assert_eq!(Settings::load().unwrap().seccomp.profiles["strict"].errno, 4095);
+
+        clear_env_vars();
+    }
+    
+    #[test]
+    fn test_load_trusted_ignores_env_and_rejects_writable() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        clear_env_vars(); // Clean start
+        std::env::set_var("HYPER_RASP_WHITELIST", "libevil.so");
+        std::env::set_var("HYPER_RASP_AUDIT_MODE", "true");
+        
+        // Missing file: defaults, environment ignored
+        let dir = tempdir().unwrap();
+        let settings = Settings::load_trusted(&dir.path().join("missing.yaml")).unwrap();
+        assert!(!settings.audit_mode);
+        assert!(settings.whitelisted_filenames.is_empty());
+        
+        // World-writable file is refused regardless of owner
+        let config_path = dir.path().join("rasp_config.yaml");
+        fs::write(&config_path, "audit_mode: true\n").unwrap();
+        fs::set_permissions(&config_path, fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(Settings::load_trusted(&config_path).is_err());
+        
+        // Clean up
+        clear_env_vars();
+    }
+    
+    #[test]
+    fn test_frozen_policy_ignores_env() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        clear_env_vars(); // Clean start
+        
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("test_config.yaml");
+        
+        let yaml_content = r#"
+freeze_policy: true
+audit_mode: false
+whitelisted_filenames:
+  - from_file.so
+"#;
+        
+        fs::write(&config_path, yaml_content).unwrap();
+        std::env::set_var("HYPER_RASP_CONFIG", config_path.to_str().unwrap());
+        std::env::set_var("HYPER_RASP_WHITELIST", "libevil.so");
+        std::env::set_var("HYPER_RASP_LEARNING_MODE", "true");
+        
+        let settings = Settings::load().unwrap();
+        
+        assert!(settings.freeze_policy);
+        assert!(!settings.audit_mode);
+        assert!(!settings.learning_mode);
+        assert_eq!(settings.whitelisted_filenames, vec!["from_file.so"]);
+        let ignored: Vec<&str> = settings.ignored_env_overrides.iter().map(|(k, _)| k.as_str()).collect();
+        assert!(ignored.contains(&"HYPER_RASP_WHITELIST"));
+        assert!(ignored.contains(&"HYPER_RASP_LEARNING_MODE"));
+        assert!(!ignored.contains(&"HYPER_RASP_CONFIG"));
+        
+        // Clean up
+        clear_env_vars();
+    }
+    
+    #[test]
+    fn test_frozen_config_pins_itself() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        clear_env_vars(); // Clean start
+        
+        let dir = tempdir().unwrap();
+        let pinned = dir.path().join("rasp_config.yaml");
+        fs::write(&pinned, "freeze_policy: true\nwhitelisted_filenames:\n  - from_file.so\n").unwrap();
+        let unfrozen = dir.path().join("unfrozen.yaml");
+        fs::write(&unfrozen, "audit_mode: true\n").unwrap();
+        let attacker = dir.path().join("attacker.yaml");
+        fs::write(&attacker, "audit_mode: true\nwhitelisted_filenames:\n  - libevil.so\n").unwrap();
+        std::env::set_var("HYPER_RASP_CONFIG", attacker.to_str().unwrap());
+        
+        let pins = [(unfrozen.as_path(), false), (pinned.as_path(), false)];
+        assert_eq!(config_path_with_pins(&pins), pinned.to_str().unwrap());
+        let settings = Settings::load_with_pins(&pins).unwrap();
+        assert!(settings.freeze_policy);
+        assert!(!settings.audit_mode);
+        assert_eq!(settings.whitelisted_filenames, vec!["from_file.so"]);
+        assert_eq!(settings.ignored_env_overrides, vec![
+            (String::from("HYPER_RASP_CONFIG"), attacker.to_str().unwrap().to_string()),
+        ]);
+        
+        // Without a frozen pin HYPER_RASP_CONFIG still selects the file
+        let pins = [(unfrozen.as_path(), false)];
+        assert_eq!(config_path_with_pins(&pins), attacker.to_str().unwrap());
+
+        // A pin that must be trusted doesn't count while group/other can rewrite it
+        fs::set_permissions(&pinned, fs::Permissions::from_mode(0o664)).unwrap();
+        let pins = [(pinned.as_path(), true)];
+        assert_eq!(config_path_with_pins(&pins), attacker.to_str().unwrap());
+        
+        // Clean up
+        clear_env_vars();
+    }
+    
+    #[test]
+    fn test_freeze_policy_not_settable_from_env() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        clear_env_vars(); // Clean start
+        
+        let dir = tempdir().unwrap();
+        let non_existent = dir.path().join("nonexistent.yaml");
+        std::env::set_var("HYPER_RASP_CONFIG", non_existent.to_str().unwrap());
+        std::env::set_var("HYPER_RASP_FREEZE_POLICY", "true");
+        std::env::set_var("HYPER_RASP__FREEZE_POLICY", "true");
+        
+        let settings = Settings::load().unwrap();
+        assert!(!settings.freeze_policy);
+        
+        // Clean up
+        std::env::remove_var("HYPER_RASP__FREEZE_POLICY");
+        clear_env_vars();
+    }
+    
+    #[test]
+    fn test_sinks_from_yaml() {
+        let _guard = TEST_MUTEX.lock().unwrap();
+        clear_env_vars(); // Clean start
+        
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("test_config.yaml");
+        
+        let yaml_content = r#"
+sinks:
+  - type: syslog
+    facility: 10
+  - type: file
+    path: /var/log/rasp.log
+    max_files: 2
+  - type: unix_datagram
+    path: /run/rasp/collector.sock
+log_format: cef
+"#;
+        
+        fs::write(&config_path, yaml_content).unwrap();
+        std::env::set_var("HYPER_RASP_CONFIG", config_path.to_str().unwrap());
+        
+        let settings = Settings::load().unwrap();
+        
+        assert_eq!(settings.sinks, vec![
+            SinkConfig::Syslog { socket: "/dev/log".into(), facility: 10 },
+            SinkConfig::File { path: "/var/log/rasp.log".into(), max_bytes: 10 * 1024 * 1024, max_files: 2 },
+            SinkConfig::UnixDatagram { path: "/run/rasp/collector.sock".into() },
+        ]);
+        assert_eq!(settings.log_format, LogFormat::Cef);
+        
+        // Clean up
+        clear_env_vars();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,289 @@
+// Module for suppressing repeated identical detections within a time window
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level as TracingLevel};
+use crate::config::Settings;
+use crate::event_store::Locked;
+
+/// Window state shared by every protected process, kept next to the event store.
+pub const STATE_FILE: &str = "dedup.json";
+
+// Upper bound on tracked detections; expired ones are dropped first when it is reached
+const MAX_KEYS: usize = 4096;
+
+/// What makes two detections "the same".
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DedupKey {
+    pub path: String,
+    pub hash: String,
+    /// `blocked` or `audited`.
+    pub verdict: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Report this detection; `suppressed` repeats were swallowed since the last report.
+    Emit { suppressed: u64 },
+    /// A repeat inside the window: count it, but don't log, alert or record it.
+    Suppress,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    key: DedupKey,
+    window_start_ms: u64,
+    suppressed: u64,
+}
+
+/// Windows are measured in wall-clock milliseconds so they survive the process.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Deduper {
+    #[serde(skip)]
+    window_ms: u64,
+    #[serde(skip)]
+    entries: HashMap<DedupKey, Entry>,
+    #[serde(rename = "entries")]
+    stored: Vec<Entry>,
+    /// Pending repeat counts of entries dropped to stay under MAX_KEYS.
+    evicted: Vec<(DedupKey, u64)>,
+}
+
+impl Deduper {
+    pub fn new(window_secs: u64) -> Self {
+        Deduper { window_ms: window_secs.saturating_mul(1000), ..Deduper::default() }
+    }
+
+    /// Restores the state written by `save`; anything unreadable starts over empty.
+    fn load(raw: &[u8], window_secs: u64) -> Self {
+        let saved: Deduper = serde_json::from_slice(raw).unwrap_or_default();
+        let mut deduper = Deduper::new(window_secs);
+        deduper.entries = saved.stored.into_iter().map(|entry| (entry.key.clone(), entry)).collect();
+        deduper.evicted = saved.evicted;
+        deduper
+    }
+
+    fn save(&mut self) -> serde_json::Result<Vec<u8>> {
+        self.stored = self.entries.values().cloned().collect();
+        let raw = serde_json::to_vec(self);
+        self.stored.clear();
+        raw
+    }
+
+    fn expired(&self, entry: &Entry, now_ms: u64) -> bool {
+        now_ms.saturating_sub(entry.window_start_ms) >= self.window_ms
+    }
+
+    pub fn check(&mut self, key: &DedupKey, now_ms: u64) -> Decision {
+        if self.window_ms == 0 {
+            return Decision::Emit { suppressed: 0 };
+        }
+        let window_ms = self.window_ms;
+        if let Some(entry) = self.entries.get_mut(key) {
+            if now_ms.saturating_sub(entry.window_start_ms) < window_ms {
+                entry.suppressed += 1;
+                return Decision::Suppress;
+            }
+            let suppressed = std::mem::take(&mut entry.suppressed);
+            entry.window_start_ms = now_ms;
+            return Decision::Emit { suppressed };
+        }
+
+        if self.entries.len() >= MAX_KEYS {
+            self.evict(now_ms);
+        }
+        self.entries.insert(key.clone(), Entry { key: key.clone(), window_start_ms: now_ms, suppressed: 0 });
+        Decision::Emit { suppressed: 0 }
+    }
+
+    fn evict(&mut self, now_ms: u64) {
+        let mut expired: Vec<DedupKey> = self.entries.values()
+            .filter(|entry| self.expired(entry, now_ms))
+            .map(|entry| entry.key.clone())
+            .collect();
+        if expired.is_empty() {
+            // Everything is live: drop the oldest window
+            expired.extend(self.entries.values().min_by_key(|e| e.window_start_ms).map(|e| e.key.clone()));
+        }
+        for key in expired {
+            if let Some(entry) = self.entries.remove(&key) {
+                if entry.suppressed > 0 {
+                    self.evicted.push((key, entry.suppressed));
+                }
+            }
+        }
+    }
+
+    /// Repeat counts of windows that have closed (plus evicted ones), resetting them.
+    /// Closed windows without pending repeats are forgotten so the state file stays small.
+    fn sweep(&mut self, now_ms: u64) -> Vec<(DedupKey, u64)> {
+        let mut pending = std::mem::take(&mut self.evicted);
+        let window_ms = self.window_ms;
+        self.entries.retain(|key, entry| {
+            if now_ms.saturating_sub(entry.window_start_ms) < window_ms {
+                return true;
+            }
+            if entry.suppressed > 0 {
+                pending.push((key.clone(), entry.suppressed));
+            }
+            false
+        });
+        pending
+    }
+}
+
+/// Logs a "suppressed N repeats" summary for one detection key.
+pub fn log_summary(key: &DedupKey, suppressed: u64, window_secs: u64) {
+    event!(TracingLevel::WARN,
+        unauthorized_library_path = key.path.as_str(),
+        file_hash = key.hash.as_str(),
+        verdict = key.verdict.as_str(),
+        suppressed_count = suppressed,
+        dedup_window_secs = window_secs,
+        "Suppressed {} repeats of detection", suppressed
+    );
+}
+
+/// Checks `key` against the window state in `dir`, under an exclusive flock so concurrent
+/// processes see each other's detections. Also returns the summaries of other detections
+/// whose window has closed, so repeats are reported at the next check anywhere on the host
+/// even if that detection never recurs.
+pub fn check_in(dir: &Path, window_secs: u64, key: &DedupKey, now_ms: u64) -> Result<(Decision, Vec<(DedupKey, u64)>)> {
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+        .with_context(|| format!("Could not create dedup state directory {}", dir.display()))?;
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o600)
+        .open(dir.join(STATE_FILE))?;
+    let mut state = Locked::acquire(file)?;
+    let mut raw = Vec::new();
+    state.0.read_to_end(&mut raw)?;
+
+    let mut deduper = Deduper::load(&raw, window_secs);
+    let decision = deduper.check(key, now_ms);
+    let closed = deduper.sweep(now_ms);
+
+    let raw = deduper.save()?;
+    state.0.set_len(0)?;
+    state.0.seek(SeekFrom::Start(0))?;
+    state.0.write_all(&raw)?;
+    Ok((decision, closed))
+}
+
+/// Checks `key` against the host-wide window state kept in `event_store.dir`. Without an
+/// event store there is nothing shared between processes, so every detection is reported.
+pub fn check(settings: &Settings, key: &DedupKey) -> Decision {
+    let window_secs = settings.dedup.window_secs;
+    let Some(dir) = settings.event_store.dir.as_deref() else {
+        return Decision::Emit { suppressed: 0 };
+    };
+    if window_secs == 0 {
+        return Decision::Emit { suppressed: 0 };
+    }
+    match check_in(Path::new(dir), window_secs, key, crate::formats::now_ms()) {
+        Ok((decision, closed)) => {
+            for (key, suppressed) in closed {
+                log_summary(&key, suppressed, window_secs);
+            }
+            decision
+        }
+        Err(e) => {
+            event!(TracingLevel::WARN, error = %e, "[Dedup] Could not read or update window state; reporting detection");
+            Decision::Emit { suppressed: 0 }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(path: &str, verdict: &str) -> DedupKey {
+        DedupKey { path: path.into(), hash: "abc".into(), verdict: verdict.into() }
+    }
+
+    #[test]
+    fn test_repeats_suppressed_within_window() {
+        let mut deduper = Deduper::new(60);
+        let start = 1_000_000;
+        let evil = key("/tmp/libevil.so", "blocked");
+
+        assert_eq!(deduper.check(&evil, start), Decision::Emit { suppressed: 0 });
+        for i in 1..=3 {
+            assert_eq!(deduper.check(&evil, start + i * 1000), Decision::Suppress);
+        }
+        // Different verdict is a different detection
+        assert_eq!(deduper.check(&key("/tmp/libevil.so", "audited"), start), Decision::Emit { suppressed: 0 });
+        // After the window the next occurrence reports what was swallowed
+        assert_eq!(deduper.check(&evil, start + 61_000), Decision::Emit { suppressed: 3 });
+        assert_eq!(deduper.check(&evil, start + 62_000), Decision::Suppress);
+    }
+
+    #[test]
+    fn test_zero_window_disables() {
+        let mut deduper = Deduper::new(0);
+        let evil = key("/tmp/libevil.so", "blocked");
+        assert_eq!(deduper.check(&evil, 1000), Decision::Emit { suppressed: 0 });
+        assert_eq!(deduper.check(&evil, 1000), Decision::Emit { suppressed: 0 });
+    }
+
+    #[test]
+    fn test_sweep_reports_closed_windows() {
+        let mut deduper = Deduper::new(60);
+        let start = 1_000_000;
+        let evil = key("/tmp/libevil.so", "blocked");
+        deduper.check(&evil, start);
+        deduper.check(&evil, start);
+        deduper.check(&evil, start);
+        assert!(deduper.sweep(start + 30_000).is_empty());
+        assert_eq!(deduper.sweep(start + 60_000), vec![(evil.clone(), 2)]);
+        assert!(deduper.sweep(start + 90_000).is_empty());
+        assert!(deduper.entries.is_empty());
+    }
+
+    #[test]
+    fn test_eviction_keeps_pending_counts() {
+        let mut deduper = Deduper::new(60);
+        let start = 1_000_000;
+        let first = key("/tmp/lib0.so", "blocked");
+        deduper.check(&first, start);
+        deduper.check(&first, start);
+        for i in 1..MAX_KEYS {
+            deduper.check(&key(&format!("/tmp/lib{i}.so"), "blocked"), start + 1);
+        }
+        deduper.check(&key("/tmp/overflow.so", "blocked"), start + 1000);
+        assert_eq!(deduper.entries.len(), MAX_KEYS);
+        assert_eq!(deduper.sweep(start + 1000), vec![(first, 1)]);
+    }
+
+    #[test]
+    fn test_window_spans_processes() {
+        // Every check reloads the state file, as a freshly started process would
+        let dir = tempfile::tempdir().unwrap();
+        let evil = key("/tmp/libevil.so", "blocked");
+        let start = 1_000_000;
+
+        let (first, _) = check_in(dir.path(), 60, &evil, start).unwrap();
+        assert_eq!(first, Decision::Emit { suppressed: 0 });
+        for i in 1..=2 {
+            let (repeat, _) = check_in(dir.path(), 60, &evil, start + i * 1000).unwrap();
+            assert_eq!(repeat, Decision::Suppress);
+        }
+        // Another detection after the window reports the pending repeats
+        let (other, closed) = check_in(dir.path(), 60, &key("/tmp/libother.so", "blocked"), start + 61_000).unwrap();
+        assert_eq!(other, Decision::Emit { suppressed: 0 });
+        assert_eq!(closed, vec![(evil, 2)]);
+    }
+
+    #[test]
+    fn test_corrupt_state_starts_over() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(STATE_FILE), b"{not json").unwrap();
+        let (decision, closed) = check_in(dir.path(), 60, &key("/tmp/libevil.so", "blocked"), 1000).unwrap();
+        assert_eq!(decision, Decision::Emit { suppressed: 0 });
+        assert!(closed.is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+// Module for the local append-only detection store queried by `hyper-processor events`
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::alerting::Detection;
+use crate::config::Settings;
+
+pub const EVENTS_FILE: &str = "events.jsonl";
+pub const INDEX_FILE: &str = "events.idx";
+
+// Index entry: recorded_ms (u64 LE) + byte offset of the line in EVENTS_FILE (u64 LE)
+const INDEX_ENTRY: usize = 16;
+
+/// One stored detection of one library.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StoredEvent {
+    pub recorded_ms: u64,
+    pub timestamp: String,
+    pub host: String,
+    pub pid: u32,
+    pub ppid: i32,
+    pub process_name: String,
+    pub cmdline: String,
+    /// `blocked` or `audited`.
+    pub action: String,
+    pub library: String,
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Filters for `query`. Every set field must match.
+#[derive(Debug, Default, Clone)]
+pub struct EventQuery {
+    /// Substring of the library filename.
+    pub library: Option<String>,
+    /// Prefix of the SHA256.
+    pub sha256: Option<String>,
+    /// Substring of the process name.
+    pub process: Option<String>,
+    pub since_ms: Option<u64>,
+    pub until_ms: Option<u64>,
+    pub action: Option<String>,
+}
+
+impl EventQuery {
+    fn matches(&self, event: &StoredEvent) -> bool {
+        self.library.as_ref().is_none_or(|l| event.library.contains(l.as_str()))
+            && self.sha256.as_ref().is_none_or(|h| event.sha256.starts_with(&h.to_lowercase()))
+            && self.process.as_ref().is_none_or(|p| event.process_name.contains(p.as_str()))
+            && self.since_ms.is_none_or(|since| event.recorded_ms >= since)
+            && self.until_ms.is_none_or(|until| event.recorded_ms < until)
+            && self.action.as_ref().is_none_or(|a| &event.action == a)
+    }
+}
+
+/// Holds an exclusive flock on a file shared between processes until dropped.
+pub(crate) struct Locked(pub(crate) File);
+
+impl Locked {
+    pub(crate) fn acquire(file: File) -> Result<Self> {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            bail!("flock failed: {}", std::io::Error::last_os_error());
+        }
+        Ok(Locked(file))
+    }
+}
+
+impl Drop for Locked {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).mode(0o600).open(path)
+}
+
+/// Appends one event per library in `detection` to `dir`. Safe across concurrent processes.
+pub fn append(dir: &Path, detection: &Detection) -> Result<()> {
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+        .with_context(|| format!("Could not create event store {}", dir.display()))?;
+    let mut events = Locked::acquire(open_append(&dir.join(EVENTS_FILE))?)?;
+    let mut index = open_append(&dir.join(INDEX_FILE))?;
+
+    // Taken under the lock so the index stays sorted by time
+    let recorded_ms = crate::formats::now_ms();
+    let mut offset = events.0.seek(SeekFrom::End(0))?;
+    let mut lines = Vec::new();
+    let mut entries = Vec::with_capacity(detection.libraries.len() * INDEX_ENTRY);
+    for library in &detection.libraries {
+        let event = StoredEvent {
+            recorded_ms,
+            timestamp: detection.timestamp.clone(),
+            host: detection.host.clone(),
+            pid: detection.pid,
+            ppid: detection.ppid,
+            process_name: detection.process_name.clone(),
+            cmdline: detection.cmdline.clone(),
+            action: detection.verdict.to_string(),
+            library: library.filename.clone(),
+            path: library.path.clone(),
+            size: library.size,
+            sha256: library.sha256.clone(),
+        };
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+        entries.extend_from_slice(&recorded_ms.to_le_bytes());
+        entries.extend_from_slice(&offset.to_le_bytes());
+        offset += line.len() as u64;
+        lines.extend_from_slice(&line);
+    }
+    events.0.write_all(&lines)?;
+    index.write_all(&entries)?;
+    Ok(())
+}
+
+/// Records `detection` if `event_store.dir` is configured. Failures are logged, never fatal.
+pub fn record(settings: &Settings, detection: &Detection) {
+    let Some(dir) = settings.event_store.dir.as_deref() else {
+        return;
+    };
+    if let Err(e) = append(Path::new(dir), detection) {
+        tracing::event!(tracing::Level::WARN, error = %e, "[EventStore] Could not record detection");
+    }
+}
+
+/// Byte offset of the first event recorded at or after `since_ms`, using the index.
+/// Falls back to the start of the file if the index is missing or unreadable.
+fn start_offset(dir: &Path, since_ms: u64) -> u64 {
+    let mut raw = Vec::new();
+    if File::open(dir.join(INDEX_FILE)).and_then(|mut f| f.read_to_end(&mut raw)).is_err() {
+        return 0;
+    }
+    let entries: Vec<(u64, u64)> = raw
+        .chunks_exact(INDEX_ENTRY)
+        .map(|chunk| {
+            let ts = u64::from_le_bytes(chunk[..8].try_into().unwrap());
+            let offset = u64::from_le_bytes(chunk[8..].try_into().unwrap());
+            (ts, offset)
+        })
+        .collect();
+    let first = entries.partition_point(|(ts, _)| *ts < since_ms);
+    match entries.get(first) {
+        Some((_, offset)) => *offset,
+        // Everything indexed is older; start after the last indexed line
+        None => entries.last().map_or(0, |(_, offset)| *offset),
+    }
+}
+
+/// Returns the events in `dir` matching `query`, oldest first.
+pub fn query(dir: &Path, query: &EventQuery) -> Result<Vec<StoredEvent>> {
+    let path = dir.join(EVENTS_FILE);
+    let mut file = File::open(&path).with_context(|| format!("Could not open {}", path.display()))?;
+    if let Some(since) = query.since_ms {
+        file.seek(SeekFrom::Start(start_offset(dir, since)))?;
+    }
+    let mut found = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        // Skip anything torn or hand-edited rather than failing the whole query
+        let Ok(event) = serde_json::from_str::<StoredEvent>(&line) else {
+            continue;
+        };
+        if query.matches(&event) {
+            found.push(event);
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerting::DetectedLibrary;
+    use tempfile::tempdir;
+
+    fn detection(blocked: bool, libraries: &[(&str, &str)]) -> Detection {
+        Detection::new(blocked, libraries.iter().map(|(name, hash)| DetectedLibrary {
+            filename: name.to_string(),
+            path: format!("/tmp/{name}"),
+            size: 1,
+            sha256: hash.to_string(),
+        }).collect())
+    }
+
+    #[test]
+    fn test_append_and_filter() {
+        let dir = tempdir().unwrap();
+        append(dir.path(), &detection(true, &[("libfoo.so.1", "aa11"), ("libbar.so", "bb22")])).unwrap();
+        append(dir.path(), &detection(false, &[("libfoo.so.2", "cc33")])).unwrap();
+
+        let all = query(dir.path(), &EventQuery::default()).unwrap();
+        assert_eq!(all.len(), 3);
+
+        let foo = query(dir.path(), &EventQuery { library: Some("libfoo".into()), ..Default::default() }).unwrap();
+        assert_eq!(foo.iter().map(|e| e.library.as_str()).collect::<Vec<_>>(), ["libfoo.so.1", "libfoo.so.2"]);
+
+        let blocked = query(dir.path(), &EventQuery { action: Some("blocked".into()), ..Default::default() }).unwrap();
+        assert_eq!(blocked.len(), 2);
+
+        let by_hash = query(dir.path(), &EventQuery { sha256: Some("CC".into()), ..Default::default() }).unwrap();
+        assert_eq!(by_hash[0].library, "libfoo.so.2");
+        assert_eq!(by_hash[0].action, "audited");
+    }
+
+    #[test]
+    fn test_time_window_uses_index() {
+        let dir = tempdir().unwrap();
+        append(dir.path(), &detection(true, &[("libold.so", "00")])).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let cutoff = crate::formats::now_ms();
+        append(dir.path(), &detection(true, &[("libnew.so", "11")])).unwrap();
+
+        let offset = start_offset(dir.path(), cutoff);
+        assert!(offset > 0);
+        let recent = query(dir.path(), &EventQuery { since_ms: Some(cutoff), ..Default::default() }).unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].library, "libnew.so");
+
+        let older = query(dir.path(), &EventQuery { until_ms: Some(cutoff), ..Default::default() }).unwrap();
+        assert_eq!(older[0].library, "libold.so");
+    }
+
+    #[test]
+    fn test_missing_index_falls_back_to_scan() {
+        let dir = tempdir().unwrap();
+        append(dir.path(), &detection(true, &[("libfoo.so", "00")])).unwrap();
+        fs::remove_file(dir.path().join(INDEX_FILE)).unwrap();
+        let found = query(dir.path(), &EventQuery { since_ms: Some(0), ..Default::default() }).unwrap();
+        assert_eq!(found.len(), 1);
+    }
+}
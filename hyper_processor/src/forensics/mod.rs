@@ -0,0 +1,350 @@
+// Module for writing a per-process forensic bundle when unauthorized libraries are found
+
+mod tar;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::alerting::DetectedLibrary;
+use crate::config::{ForensicsSettings, Settings};
+
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// One file in the bundle, as listed in the manifest.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// An offending library and whether a copy of it is in the bundle.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ManifestLibrary {
+    pub filename: String,
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Archive entry holding the copy; None if it was unreadable or over `max_library_bytes`.
+    pub copy: Option<String>,
+}
+
+/// First entry of every bundle; describes the process and every other entry.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Manifest {
+    pub created: String,
+    pub host: String,
+    pub rasp_version: String,
+    /// `blocked` or `audited`.
+    pub verdict: String,
+    pub pid: u32,
+    pub ppid: i32,
+    pub process_name: String,
+    pub libraries: Vec<ManifestLibrary>,
+    pub entries: Vec<ManifestEntry>,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Turns a NUL-separated /proc file into one redacted item per line.
+fn nul_to_lines(raw: &[u8], redact: impl Fn(&[&str]) -> Vec<String>) -> Vec<u8> {
+    let text = String::from_utf8_lossy(raw);
+    let items: Vec<&str> = text.split('\0').filter(|s| !s.is_empty()).collect();
+    let mut out = redact(&items).join("\n").into_bytes();
+    out.push(b'\n');
+    out
+}
+
+fn argv_lines(raw: Vec<u8>) -> Vec<u8> {
+    nul_to_lines(&raw, |argv| crate::redaction::active().redact_argv(argv))
+}
+
+fn environ_lines(raw: Vec<u8>) -> Vec<u8> {
+    let redactor = crate::redaction::active();
+    nul_to_lines(&raw, |entries| entries.iter().map(|e| redactor.redact_env_entry(e)).collect())
+}
+
+/// Environment keys only: values routinely hold credentials no redaction pattern anticipates.
+fn environ_keys(raw: Vec<u8>) -> Vec<u8> {
+    let redactor = crate::redaction::active();
+    nul_to_lines(&raw, |entries| entries.iter().map(|e| redactor.mask_env_entry(e)).collect())
+}
+
+/// The longest prefix of `text` that fits in `max_bytes` without splitting a character.
+fn truncate_bytes(text: &str, max_bytes: usize) -> &str {
+    let mut end = text.len().min(max_bytes);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Reads at most `max_bytes` of `path`; None if it is unreadable or has grown past that.
+fn read_capped(path: &Path, max_bytes: u64) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path).ok()?.take(max_bytes.saturating_add(1)).read_to_end(&mut data).ok()?;
+    (data.len() as u64 <= max_bytes).then_some(data)
+}
+
+fn read_link_text(path: &Path) -> String {
+    fs::read_link(path)
+        .map(|target| target.to_string_lossy().into_owned())
+        .unwrap_or_else(|e| format!("<{}>", e))
+}
+
+/// `fd -> target` for every descriptor open in `proc_dir`.
+fn open_fds(proc_dir: &Path) -> Vec<u8> {
+    let Ok(dir) = fs::read_dir(proc_dir.join("fd")) else {
+        return b"<unreadable>\n".to_vec();
+    };
+    let mut fds: Vec<(u64, String)> = dir
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let fd = entry.file_name().to_str()?.parse().ok()?;
+            Some((fd, read_link_text(&entry.path())))
+        })
+        .collect();
+    fds.sort();
+    fds.iter().map(|(fd, target)| format!("{fd} -> {target}\n")).collect::<String>().into_bytes()
+}
+
+/// Collects the bundle contents (without the manifest) for the process at `proc_dir`.
+fn collect(proc_dir: &Path, ppid: i32, libraries: &[DetectedLibrary], settings: &ForensicsSettings)
+    -> (Vec<(String, Vec<u8>)>, Vec<ManifestLibrary>)
+{
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut add_file = |name: &str, path: PathBuf, transform: fn(Vec<u8>) -> Vec<u8>| {
+        let data = fs::read(&path)
+            .map(transform)
+            .unwrap_or_else(|e| format!("<{}: {}>\n", path.display(), e).into_bytes());
+        entries.push((name.to_string(), data));
+    };
+    add_file("maps", proc_dir.join("maps"), |d| d);
+    add_file("environ", proc_dir.join("environ"), if settings.raw_environ { environ_lines } else { environ_keys });
+    add_file("cmdline", proc_dir.join("cmdline"), argv_lines);
+    add_file("status", proc_dir.join("status"), |d| d);
+
+    let parent_dir = PathBuf::from(format!("/proc/{ppid}"));
+    add_file("parent/status", parent_dir.join("status"), |d| d);
+    add_file("parent/cmdline", parent_dir.join("cmdline"), argv_lines);
+
+    entries.push(("cwd".into(), format!("{}\n", read_link_text(&proc_dir.join("cwd"))).into_bytes()));
+    entries.push(("exe".into(), format!("{}\n", read_link_text(&proc_dir.join("exe"))).into_bytes()));
+    entries.push(("fds".into(), open_fds(proc_dir)));
+    entries.push(("parent/exe".into(), format!("{}\n", read_link_text(&parent_dir.join("exe"))).into_bytes()));
+
+    let mut manifest_libraries = Vec::with_capacity(libraries.len());
+    for (index, library) in libraries.iter().enumerate() {
+        // The size was measured earlier; the file may have grown since
+        let copy = (library.size <= settings.max_library_bytes)
+            .then(|| read_capped(Path::new(&library.path), settings.max_library_bytes))
+            .flatten()
+            .map(|data| {
+                // Index prefix keeps same-named libraries from different paths apart;
+                // the filename is cut to fit the 100-byte ustar name field
+                let name = format!("libraries/{index}-{}", truncate_bytes(&library.filename, 80));
+                entries.push((name.clone(), data));
+                name
+            });
+        manifest_libraries.push(ManifestLibrary {
+            filename: library.filename.clone(),
+            path: library.path.clone(),
+            size: library.size,
+            sha256: library.sha256.clone(),
+            copy,
+        });
+    }
+    (entries, manifest_libraries)
+}
+
+/// Writes a bundle for the current process into `forensics.dir`, if configured.
+/// Returns the bundle path.
+pub fn snapshot(settings: &Settings, blocked: bool, libraries: &[DetectedLibrary]) -> Result<Option<PathBuf>> {
+    let Some(dir) = settings.forensics.dir.as_deref() else {
+        return Ok(None);
+    };
+    let pid = std::process::id();
+    let ppid = nix::unistd::getppid().as_raw();
+    let now_ms = crate::formats::now_ms();
+    let (entries, libraries) = collect(Path::new("/proc/self"), ppid, libraries, &settings.forensics);
+
+    let manifest = Manifest {
+        created: crate::formats::rfc3339_millis(now_ms),
+        host: crate::sinks::hostname(),
+        rasp_version: env!("CARGO_PKG_VERSION").to_string(),
+        verdict: String::from(if blocked { "blocked" } else { "audited" }),
+        pid,
+        ppid,
+        process_name: fs::read_to_string("/proc/self/comm").map(|s| s.trim().to_string()).unwrap_or_default(),
+        libraries,
+        entries: entries.iter()
+            .map(|(name, data)| ManifestEntry { name: name.clone(), size: data.len() as u64, sha256: sha256_hex(data) })
+            .collect(),
+    };
+
+    let dir = Path::new(dir);
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+        .with_context(|| format!("Could not create forensics directory {}", dir.display()))?;
+    let path = dir.join(format!("hyper_rasp-{}-{}.tar", pid, now_ms));
+    let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path)
+        .with_context(|| format!("Could not create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+
+    let mut archive = Vec::with_capacity(entries.len() + 1);
+    archive.push((MANIFEST_NAME.to_string(), serde_json::to_vec_pretty(&manifest)?));
+    archive.extend(entries);
+    tar::write(&mut out, &archive, now_ms / 1000)?;
+    out.flush()?;
+    Ok(Some(path))
+}
+
+/// A bundle read back from disk.
+pub struct Bundle {
+    pub manifest: Manifest,
+    pub entries: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+        let mut entries = tar::read(&mut BufReader::new(file))?;
+        let position = entries.iter().position(|(name, _)| name == MANIFEST_NAME)
+            .ok_or_else(|| anyhow!("{} has no {}", path.display(), MANIFEST_NAME))?;
+        let (_, raw) = entries.remove(position);
+        let manifest = serde_json::from_slice(&raw).context("Invalid manifest")?;
+        Ok(Bundle { manifest, entries })
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&[u8]> {
+        self.entries.iter().find(|(n, _)| n == name).map(|(_, data)| data.as_slice())
+    }
+
+    /// Entries whose contents no longer match the manifest (or are missing from it).
+    pub fn mismatches(&self) -> Vec<String> {
+        let mut bad: Vec<String> = self.entries.iter()
+            .filter(|(name, data)| {
+                !self.manifest.entries.iter().any(|e| &e.name == name && e.sha256 == sha256_hex(data))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for expected in &self.manifest.entries {
+            if self.entry(&expected.name).is_none() {
+                bad.push(expected.name.clone());
+            }
+        }
+        bad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn offending(dir: &Path, size: usize) -> DetectedLibrary {
+        let path = dir.join("libevil.so");
+        fs::write(&path, vec![0x7fu8; size]).unwrap();
+        let (size, sha256) = crate::preload_check::get_file_info(&path);
+        DetectedLibrary {
+            filename: "libevil.so".into(),
+            path: path.to_string_lossy().into_owned(),
+            size,
+            sha256,
+        }
+    }
+
+    fn limits(max_library_bytes: u64) -> ForensicsSettings {
+        ForensicsSettings { max_library_bytes, ..Default::default() }
+    }
+
+    #[test]
+    fn test_collect_includes_process_state() {
+        let dir = tempdir().unwrap();
+        let library = offending(dir.path(), 100);
+        let ppid = nix::unistd::getppid().as_raw();
+        let (entries, libraries) = collect(Path::new("/proc/self"), ppid, &[library], &limits(1024));
+
+        let names: Vec<&str> = entries.iter().map(|(n, _)| n.as_str()).collect();
+        for expected in ["maps", "environ", "cmdline", "cwd", "fds", "parent/status", "libraries/0-libevil.so"] {
+            assert!(names.contains(&expected), "missing {expected}");
+        }
+        assert_eq!(libraries[0].copy.as_deref(), Some("libraries/0-libevil.so"));
+        let cwd = std::env::current_dir().unwrap();
+        let (_, recorded) = entries.iter().find(|(n, _)| n == "cwd").unwrap();
+        assert_eq!(String::from_utf8_lossy(recorded).trim(), cwd.to_string_lossy());
+    }
+
+    #[test]
+    fn test_nul_to_lines_redacts() {
+        let redactor = crate::redaction::Redactor::new(&crate::config::RedactionSettings {
+            argv_patterns: vec!["^--password=(.+)$".into()],
+            ..Default::default()
+        }).unwrap();
+        let lines = nul_to_lines(b"app\0--password=hunter2\0", |argv| redactor.redact_argv(argv));
+        assert_eq!(lines, b"app\n--password=[REDACTED]\n");
+    }
+
+    #[test]
+    fn test_large_library_not_copied() {
+        let dir = tempdir().unwrap();
+        let library = offending(dir.path(), 4096);
+        let (entries, libraries) = collect(Path::new("/proc/self"), 1, &[library], &limits(1024));
+        assert_eq!(libraries[0].copy, None);
+        assert!(!entries.iter().any(|(n, _)| n.starts_with("libraries/")));
+
+        // Measured small, but grown by the time it is copied
+        let library = offending(dir.path(), 100);
+        fs::write(&library.path, vec![0x7fu8; 4096]).unwrap();
+        let (_, libraries) = collect(Path::new("/proc/self"), 1, &[library], &limits(1024));
+        assert_eq!(libraries[0].copy, None);
+    }
+
+    #[test]
+    fn test_environ_values_masked_by_default() {
+        assert_eq!(environ_keys(b"AWS_SECRET_ACCESS_KEY=abc\0PATH=/bin\0".to_vec()), b"AWS_SECRET_ACCESS_KEY=[REDACTED]\nPATH=[REDACTED]\n");
+        assert_eq!(environ_lines(b"PATH=/bin\0".to_vec()), b"PATH=/bin\n");
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let dir = tempdir().unwrap();
+        let library = offending(dir.path(), 100);
+        let mut settings = Settings::default();
+        settings.forensics.dir = Some(dir.path().join("bundles").to_string_lossy().into_owned());
+
+        let path = snapshot(&settings, true, std::slice::from_ref(&library)).unwrap().unwrap();
+        let bundle = Bundle::open(&path).unwrap();
+        assert_eq!(bundle.manifest.verdict, "blocked");
+        assert_eq!(bundle.manifest.pid, std::process::id());
+        assert_eq!(bundle.manifest.libraries[0].sha256, library.sha256);
+        assert_eq!(bundle.entry("libraries/0-libevil.so").unwrap(), fs::read(&library.path).unwrap());
+        assert!(bundle.mismatches().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_with_multibyte_library_name() {
+        let dir = tempdir().unwrap();
+        let mut library = offending(dir.path(), 100);
+        // 80 characters, 240 bytes
+        library.filename = format!("{}.so", "字".repeat(77));
+        let mut settings = Settings::default();
+        settings.forensics.dir = Some(dir.path().join("bundles").to_string_lossy().into_owned());
+
+        let path = snapshot(&settings, true, std::slice::from_ref(&library)).unwrap().unwrap();
+        let bundle = Bundle::open(&path).unwrap();
+        let copy = bundle.manifest.libraries[0].copy.clone().unwrap();
+        assert_eq!(copy, format!("libraries/0-{}", "字".repeat(26)));
+        assert_eq!(bundle.entry(&copy).unwrap(), fs::read(&library.path).unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_disabled_without_dir() {
+        assert!(snapshot(&Settings::default(), true, &[]).unwrap().is_none());
+    }
+}
@@ -0,0 +1,137 @@
+// Minimal ustar writer/reader for forensic bundles (regular files only)
+
+use std::io::{self, Read, Write};
+use anyhow::{bail, Result};
+
+const BLOCK: usize = 512;
+
+fn octal(field: &mut [u8], value: u64) {
+    // Zero-padded octal followed by a NUL terminator
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&text.as_bytes()[text.len() - digits..]);
+    field[digits] = 0;
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64> {
+    let text: String = field.iter()
+        .take_while(|&&b| b != 0 && b != b' ')
+        .map(|&b| b as char)
+        .collect();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    Ok(u64::from_str_radix(text.trim_start(), 8)?)
+}
+
+fn checksum(header: &[u8; BLOCK]) -> u64 {
+    header.iter().enumerate()
+        // The checksum field itself counts as spaces
+        .map(|(i, &b)| if (148..156).contains(&i) { u64::from(b' ') } else { u64::from(b) })
+        .sum()
+}
+
+fn header(name: &str, size: u64, mtime: u64) -> Result<[u8; BLOCK]> {
+    if name.len() > 100 {
+        bail!("Archive entry name too long: {}", name);
+    }
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut header[100..108], 0o600);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size);
+    octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let sum = checksum(&header);
+    octal(&mut header[148..155], sum);
+    header[155] = b' ';
+    Ok(header)
+}
+
+/// Writes `entries` as a ustar archive, terminated by the two zero blocks.
+pub fn write<W: Write>(out: &mut W, entries: &[(String, Vec<u8>)], mtime: u64) -> Result<()> {
+    for (name, data) in entries {
+        out.write_all(&header(name, data.len() as u64, mtime)?)?;
+        out.write_all(data)?;
+        let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+        out.write_all(&[0u8; BLOCK][..padding])?;
+    }
+    out.write_all(&[0u8; BLOCK * 2])?;
+    Ok(())
+}
+
+/// Reads every regular-file entry of a ustar archive as (name, contents).
+pub fn read<R: Read>(input: &mut R) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    loop {
+        let mut header = [0u8; BLOCK];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if parse_octal(&header[148..156])? != checksum(&header) {
+            bail!("Corrupt archive: bad header checksum");
+        }
+        let name_len = header[..100].iter().position(|&b| b == 0).unwrap_or(100);
+        let name = String::from_utf8_lossy(&header[..name_len]).into_owned();
+        let size = parse_octal(&header[124..136])?;
+        // The header is untrusted: grow the buffer with the data actually present instead of
+        // allocating the claimed size up front
+        let mut data = Vec::new();
+        input.by_ref().take(size).read_to_end(&mut data)?;
+        if data.len() as u64 != size {
+            bail!("Corrupt archive: {} claims {} bytes but only {} remain", name, size, data.len());
+        }
+        let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+        input.read_exact(&mut [0u8; BLOCK][..padding])?;
+        if matches!(header[156], b'0' | 0) {
+            entries.push((name, data));
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let entries = vec![
+            ("manifest.json".to_string(), b"{}".to_vec()),
+            ("empty".to_string(), Vec::new()),
+            ("libraries/libevil.so".to_string(), vec![7u8; 1000]),
+        ];
+        let mut archive = Vec::new();
+        write(&mut archive, &entries, 1_700_000_000).unwrap();
+        assert_eq!(archive.len() % BLOCK, 0);
+        assert_eq!(read(&mut archive.as_slice()).unwrap(), entries);
+    }
+
+    #[test]
+    fn test_corrupt_header_rejected() {
+        let mut archive = Vec::new();
+        write(&mut archive, &[("a".to_string(), b"x".to_vec())], 0).unwrap();
+        archive[0] = b'b';
+        assert!(read(&mut archive.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_oversized_entry_rejected() {
+        let mut archive = Vec::new();
+        write(&mut archive, &[("a".to_string(), b"x".to_vec())], 0).unwrap();
+        // Claim 8 GiB of data with a valid checksum
+        archive[124..136].copy_from_slice(b"77777777777\0");
+        let sum = format!("{:06o}\0 ", checksum(&archive[..BLOCK].try_into().unwrap()));
+        archive[148..156].copy_from_slice(sum.as_bytes());
+        let error = read(&mut archive.as_slice()).unwrap_err();
+        assert!(error.to_string().contains("claims"), "{error}");
+    }
+}
@@ -0,0 +1,430 @@
+// Module for SIEM-oriented event layouts (ArcSight CEF and Elastic Common Schema)
+
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::{json, Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use crate::config::LogFormat;
+
+const CEF_VENDOR: &str = "HyperProcessor";
+const CEF_PRODUCT: &str = "RASP";
+const ECS_VERSION: &str = "8.11.0";
+
+/// Field values of a span or event, in recording order, keeping numbers and booleans typed.
+#[derive(Default, Debug, Clone)]
+pub struct FieldValues(pub Vec<(String, Value)>);
+
+impl FieldValues {
+    fn set(&mut self, name: &str, value: Value) {
+        match self.0.iter_mut().find(|(n, _)| n == name) {
+            Some(slot) => slot.1 = value,
+            None => self.0.push((name.to_string(), value)),
+        }
+    }
+}
+
+impl Visit for FieldValues {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field.name(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field.name(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set(field.name(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field.name(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.set(field.name(), Value::from(format!("{:?}", value)));
+    }
+}
+
+/// Renders a field value without JSON quoting.
+pub fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Stores every span's fields in its extensions so event layers other than `fmt` can read them.
+/// Must be registered once, ahead of the layers that call `span_fields`.
+pub struct SpanFieldsLayer;
+
+impl<S> Layer<S> for SpanFieldsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = FieldValues::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<FieldValues>() {
+                values.record(fields);
+            }
+        }
+    }
+}
+
+/// Fields of all spans enclosing `event`, outermost first.
+pub fn span_fields<S>(event: &Event<'_>, ctx: &Context<'_, S>) -> Vec<(String, Value)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let mut out = Vec::new();
+    if let Some(scope) = ctx.event_scope(event) {
+        for span in scope.from_root() {
+            if let Some(fields) = span.extensions().get::<FieldValues>() {
+                out.extend(fields.0.iter().cloned());
+            }
+        }
+    }
+    out
+}
+
+/// Everything a formatter needs to know about one event.
+pub struct EventRecord {
+    pub level: Level,
+    pub target: String,
+    pub timestamp_ms: u64,
+    pub span_fields: Vec<(String, Value)>,
+    pub fields: Vec<(String, Value)>,
+}
+
+impl EventRecord {
+    /// Looks a field up on the event first, then on the enclosing spans (innermost wins).
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter()
+            .chain(self.span_fields.iter().rev())
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    fn text(&self, name: &str) -> Option<String> {
+        self.get(name).map(value_text)
+    }
+
+    fn message(&self) -> String {
+        self.text("message").unwrap_or_default()
+    }
+}
+
+/// Formats milliseconds since the epoch as RFC 3339 UTC with millisecond precision.
+pub fn rfc3339_millis(timestamp_ms: u64) -> String {
+    let secs = timestamp_ms / 1000;
+    let millis = timestamp_ms % 1000;
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil-from-days (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, rem / 3600, (rem % 3600) / 60, rem % 60, millis
+    )
+}
+
+/// Parses `YYYY-MM-DD` or RFC 3339 UTC (`YYYY-MM-DDTHH:MM:SS[.fff]Z`) into milliseconds since the epoch.
+pub fn parse_rfc3339_millis(text: &str) -> Option<u64> {
+    let (date, time) = match text.split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z')?)),
+        None => (text, None),
+    };
+    let mut date_parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date_parts.next()??, date_parts.next()??, date_parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut millis = 0u64;
+    if let Some(time) = time {
+        let (hms, fraction) = time.split_once('.').unwrap_or((time, ""));
+        let mut hms_parts = hms.splitn(3, ':').map(|p| p.parse::<u64>().ok());
+        let (h, m, s) = (hms_parts.next()??, hms_parts.next()??, hms_parts.next()??);
+        if h > 23 || m > 59 || s > 60 {
+            return None;
+        }
+        let fraction_ms = if fraction.is_empty() {
+            0
+        } else {
+            format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse::<u64>().ok()?
+        };
+        millis = ((h * 60 + m) * 60 + s) * 1000 + fraction_ms;
+    }
+
+    // Days-from-civil (inverse of the algorithm in rfc3339_millis)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days).ok().map(|d| d * 86_400_000 + millis)
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn cef_severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 8,
+        Level::WARN => 5,
+        Level::INFO => 3,
+        Level::DEBUG | Level::TRACE => 1,
+    }
+}
+
+fn cef_escape_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+fn cef_escape_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+/// ArcSight CEF:0 line. The signature ID is the alert type (`SECURITY`, `AUDIT`) or the level.
+pub fn format_cef(record: &EventRecord) -> String {
+    let signature = record.text("alert_type").unwrap_or_else(|| record.level.to_string());
+    let mut line = format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|",
+        CEF_VENDOR,
+        CEF_PRODUCT,
+        env!("CARGO_PKG_VERSION"),
+        cef_escape_header(&signature),
+        cef_escape_header(&record.message()),
+        cef_severity(&record.level)
+    );
+
+    let mut extensions: Vec<(&str, String)> = vec![("rt", record.timestamp_ms.to_string())];
+    let mapped = [
+        ("unauthorized_library_filename", "fname"),
+        ("unauthorized_library_path", "filePath"),
+        ("file_size", "fsize"),
+        ("file_hash", "fileHash"),
+        ("pid", "dvcpid"),
+        ("process_name", "dproc"),
+        ("alert_type", "cat"),
+    ];
+    for (field, key) in mapped {
+        if let Some(value) = record.text(field) {
+            extensions.push((key, value));
+        }
+    }
+    // No standard CEF keys exist for these; use labelled custom fields
+    if let Some(ppid) = record.text("ppid") {
+        extensions.push(("cn1", ppid));
+        extensions.push(("cn1Label", String::from("ppid")));
+    }
+    if let Some(cmdline) = record.text("cmdline") {
+        extensions.push(("cs1", cmdline));
+        extensions.push(("cs1Label", String::from("cmdline")));
+    }
+    extensions.push(("msg", record.message()));
+
+    let rendered: Vec<String> = extensions
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, cef_escape_extension(&value)))
+        .collect();
+    line.push_str(&rendered.join(" "));
+    line
+}
+
+fn insert_path(root: &mut Map<String, Value>, path: &[&str], value: Value) {
+    let (last, parents) = path.split_last().expect("non-empty ECS path");
+    let mut node = root;
+    for key in parents {
+        node = node
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .expect("ECS path collides with a scalar field");
+    }
+    node.insert(last.to_string(), value);
+}
+
+/// Elastic Common Schema document. Fields without an ECS equivalent go under `hyper_processor.*`.
+pub fn format_ecs(record: &EventRecord) -> String {
+    let alert_type = record.text("alert_type");
+    let mut doc = Map::new();
+    doc.insert("@timestamp".into(), Value::from(rfc3339_millis(record.timestamp_ms)));
+    doc.insert("message".into(), Value::from(record.message()));
+    insert_path(&mut doc, &["log", "level"], Value::from(record.level.to_string().to_lowercase()));
+    insert_path(&mut doc, &["log", "logger"], Value::from(record.target.clone()));
+    insert_path(&mut doc, &["ecs", "version"], Value::from(ECS_VERSION));
+    insert_path(&mut doc, &["event", "module"], Value::from("hyper_processor"));
+    insert_path(&mut doc, &["event", "kind"], Value::from(if alert_type.is_some() { "alert" } else { "event" }));
+    if let Some(alert_type) = &alert_type {
+        insert_path(&mut doc, &["event", "category"], json!(["intrusion_detection"]));
+        let outcome = if alert_type == "SECURITY" { "denied" } else { "allowed" };
+        insert_path(&mut doc, &["event", "type"], json!([outcome]));
+        insert_path(&mut doc, &["labels", "alert_type"], Value::from(alert_type.as_str()));
+    }
+
+    let mapped: [(&str, &[&str]); 8] = [
+        ("unauthorized_library_filename", &["file", "name"]),
+        ("unauthorized_library_path", &["file", "path"]),
+        ("file_size", &["file", "size"]),
+        ("file_hash", &["file", "hash", "sha256"]),
+        ("pid", &["process", "pid"]),
+        ("ppid", &["process", "parent", "pid"]),
+        ("process_name", &["process", "name"]),
+        ("cmdline", &["process", "command_line"]),
+    ];
+    let mut consumed = vec!["message", "alert_type"];
+    for (field, path) in mapped {
+        if let Some(value) = record.get(field) {
+            insert_path(&mut doc, path, value.clone());
+        }
+        consumed.push(field);
+    }
+
+    let mut extra = Map::new();
+    for (name, value) in record.span_fields.iter().chain(record.fields.iter()) {
+        if !consumed.contains(&name.as_str()) {
+            extra.insert(name.clone(), value.clone());
+        }
+    }
+    if !extra.is_empty() {
+        doc.insert("hyper_processor".into(), Value::Object(extra));
+    }
+
+    Value::Object(doc).to_string()
+}
+
+/// Writes each event as one CEF or ECS line to `writer`.
+pub struct FormattedLayer<W> {
+    format: LogFormat,
+    writer: W,
+}
+
+impl<W> FormattedLayer<W> {
+    pub fn new(format: LogFormat, writer: W) -> Self {
+        FormattedLayer { format, writer }
+    }
+}
+
+impl<S, W> Layer<S> for FormattedLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut fields = FieldValues::default();
+        event.record(&mut fields);
+        let record = EventRecord {
+            level: *meta.level(),
+            target: meta.target().to_string(),
+            timestamp_ms: now_ms(),
+            span_fields: span_fields(event, &ctx),
+            fields: fields.0,
+        };
+        let mut line = match self.format {
+            LogFormat::Cef => format_cef(&record),
+            LogFormat::Ecs => format_ecs(&record),
+            LogFormat::Json => return, // handled by the fmt layer
+        };
+        line.push('\n');
+        let _ = self.writer.make_writer_for(meta).write_all(line.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_record() -> EventRecord {
+        EventRecord {
+            level: Level::ERROR,
+            target: "hyper_processor::preload_check".into(),
+            timestamp_ms: 1_717_243_200_123,
+            span_fields: vec![
+                ("pid".into(), json!(4242)),
+                ("ppid".into(), json!(1)),
+                ("process_name".into(), json!("nginx")),
+                ("ld_preload".into(), json!("/tmp/libevil.so")),
+                ("cmdline".into(), json!("nginx -g daemon off;")),
+                ("version".into(), json!("2.3.0")),
+            ],
+            fields: vec![
+                ("message".into(), json!("Unauthorized library detected (Blocking Mode)")),
+                ("unauthorized_library_filename".into(), json!("libevil.so")),
+                ("unauthorized_library_path".into(), json!("/tmp/libevil.so")),
+                ("file_size".into(), json!(16384)),
+                ("file_hash".into(), json!("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")),
+                ("alert_type".into(), json!("SECURITY")),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_rfc3339_millis() {
+        assert_eq!(rfc3339_millis(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339_millis(1_717_243_200_123), "2024-06-01T12:00:00.123Z");
+        assert_eq!(rfc3339_millis(951_782_400_000), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_parse_rfc3339_millis() {
+        assert_eq!(parse_rfc3339_millis("2024-06-01T12:00:00.123Z"), Some(1_717_243_200_123));
+        assert_eq!(parse_rfc3339_millis("2000-02-29"), Some(951_782_400_000));
+        assert_eq!(parse_rfc3339_millis("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339_millis("2024-13-01"), None);
+        assert_eq!(parse_rfc3339_millis("yesterday"), None);
+    }
+
+    #[test]
+    fn test_cef_golden() {
+        let expected = include_str!("testdata/unauthorized.cef")
+            .replace("{version}", env!("CARGO_PKG_VERSION"));
+        assert_eq!(format_cef(&sample_record()), expected.trim_end());
+    }
+
+    #[test]
+    fn test_ecs_golden() {
+        let expected: Value = serde_json::from_str(include_str!("testdata/unauthorized.ecs.json")).unwrap();
+        let actual: Value = serde_json::from_str(&format_ecs(&sample_record())).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_cef_escaping() {
+        assert_eq!(cef_escape_header("a|b\\c"), "a\\|b\\\\c");
+        assert_eq!(cef_escape_extension("k=v\nnext"), "k\\=v\\nnext");
+    }
+}
@@ -0,0 +1,257 @@
+// Module with the minimal HTTP/1.1 client shared by alerting, the OTLP exporter and the CLI
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, Context, Result};
+
+/// Splits `http://host[:port]/path` into (`host:port`, host, path).
+fn parse_url(url: &str) -> Result<(String, String, String)> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None if url.starts_with("https://") => bail!("https URLs are not supported; use a local relay"),
+        None => bail!("URL must start with http://: {}", url),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        bail!("URL has no host: {}", url);
+    }
+    let has_port = match authority.rfind(':') {
+        Some(i) => !authority[i..].contains(']'),
+        None => false,
+    };
+    let address = if has_port { authority.to_string() } else { format!("{authority}:80") };
+    Ok((address, authority.to_string(), path.to_string()))
+}
+
+/// POSTs `body` as JSON and requires a 2xx status, all within `timeout`.
+pub fn post(url: &str, body: &[u8], timeout: Duration) -> Result<()> {
+    request("POST", url, Some(body), None, timeout, false).map(|_| ())
+}
+
+/// GETs `url` and returns the response body, requiring a 2xx status, all within `timeout`.
+/// `bearer` is sent as an `Authorization: Bearer` token when set.
+pub fn get(url: &str, bearer: Option<&str>, timeout: Duration) -> Result<Vec<u8>> {
+    request("GET", url, None, bearer, timeout, true)
+}
+
+/// Resolves `address` within `timeout`. getaddrinfo can't be cancelled, so names are looked up
+/// on a helper thread that is abandoned if the resolver hangs; IP literals skip it.
+fn resolve(address: &str, timeout: Duration) -> Result<SocketAddr> {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let (tx, rx) = mpsc::channel();
+    let name = address.to_string();
+    thread::Builder::new()
+        .name("hyper_rasp_resolve".into())
+        .spawn(move || {
+            let _ = tx.send(name.to_socket_addrs().map(|mut addrs| addrs.next()));
+        })
+        .context("Could not start resolver thread")?;
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(Some(addr))) => Ok(addr),
+        Ok(Ok(None)) => bail!("No address for {}", address),
+        Ok(Err(e)) => Err(e).with_context(|| format!("Could not resolve {}", address)),
+        Err(_) => bail!("Resolving {} timed out after {:?}", address, timeout),
+    }
+}
+
+/// One HTTP/1.1 request over a fresh connection. The body is only read when `read_body` is set;
+/// otherwise only the status line matters.
+fn request(
+    method: &str,
+    url: &str,
+    body: Option<&[u8]>,
+    bearer: Option<&str>,
+    timeout: Duration,
+    read_body: bool,
+) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let remaining = || {
+        deadline
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+            .ok_or_else(|| anyhow!("Timed out after {:?}", timeout))
+    };
+
+    let (address, host, path) = parse_url(url)?;
+    let addr = resolve(&address, remaining()?)?;
+    let mut stream = TcpStream::connect_timeout(&addr, remaining()?)
+        .with_context(|| format!("Could not connect to {}", addr))?;
+
+    stream.set_write_timeout(Some(remaining()?))?;
+    let mut head = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: hyper_processor/{}\r\n",
+        env!("CARGO_PKG_VERSION"),
+    );
+    if let Some(token) = bearer {
+        head.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    if let Some(body) = body {
+        head.push_str(&format!("Content-Type: application/json\r\nContent-Length: {}\r\n", body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    stream.write_all(head.as_bytes())?;
+    if let Some(body) = body {
+        stream.write_all(body)?;
+    }
+
+    let mut response = Vec::with_capacity(64);
+    let mut chunk = [0u8; 4096];
+    // Offset of the body and its Content-Length, once the header block is complete.
+    let mut framing: Option<(usize, Option<usize>)> = None;
+    loop {
+        let have_status = response.windows(2).any(|w| w == b"\r\n");
+        if have_status && !read_body {
+            break;
+        }
+        if framing.is_none() {
+            if let Some(i) = response.windows(4).position(|w| w == b"\r\n\r\n") {
+                framing = Some((i + 4, body_length(&response[..i])?));
+            }
+        }
+        if let Some((start, Some(length))) = framing {
+            if response.len() - start >= length {
+                response.truncate(start + length);
+                break;
+            }
+        }
+        stream.set_read_timeout(Some(remaining()?))?;
+        let n = stream.read(&mut chunk).context("Reading HTTP response")?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&chunk[..n]);
+    }
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("Malformed HTTP response"))?;
+    if !(200..300).contains(&status) {
+        bail!("{} returned HTTP {}", url, status);
+    }
+    if !read_body {
+        return Ok(Vec::new());
+    }
+    match framing {
+        Some((start, Some(length))) if response.len() - start < length => {
+            bail!("{} closed the connection after {} of {} body bytes", url, response.len() - start, length)
+        }
+        Some((start, _)) => Ok(response.split_off(start)),
+        None => bail!("{} closed the connection before the end of the headers", url),
+    }
+}
+
+/// Reads the body length from a response header block. Chunked responses are refused rather
+/// than decoded, since nothing we talk to needs them; without Content-Length the body runs to EOF.
+fn body_length(head: &[u8]) -> Result<Option<usize>> {
+    let head = String::from_utf8_lossy(head);
+    let mut length = None;
+    for line in head.split("\r\n").skip(1) {
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("transfer-encoding") && !value.eq_ignore_ascii_case("identity") {
+            bail!("Unsupported Transfer-Encoding: {}", value);
+        }
+        if name.eq_ignore_ascii_case("content-length") {
+            length = Some(value.parse::<usize>().map_err(|_| anyhow!("Invalid Content-Length: {}", value))?);
+        }
+    }
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(parse_url("http://127.0.0.1:8080/a/b").unwrap(),
+            ("127.0.0.1:8080".into(), "127.0.0.1:8080".into(), "/a/b".into()));
+        assert_eq!(parse_url("http://alerts.local").unwrap(),
+            ("alerts.local:80".into(), "alerts.local".into(), "/".into()));
+        assert!(parse_url("https://example.com/").is_err());
+        assert!(parse_url("ftp://example.com/").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("127.0.0.1:8080", Duration::ZERO).unwrap(), "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(resolve("[::1]:80", Duration::ZERO).unwrap(), "[::1]:80".parse().unwrap());
+        assert!(resolve("localhost:80", Duration::from_secs(5)).unwrap().ip().is_loopback());
+        assert!(resolve("no-such-host.invalid:80", Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn test_get_returns_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/status", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let n = conn.read(&mut request).unwrap();
+            assert!(request[..n].starts_with(b"GET /status HTTP/1.1\r\n"));
+            assert!(String::from_utf8_lossy(&request[..n]).contains("Authorization: Bearer s3cret\r\n"));
+            conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 16\r\n\r\n{\"processes\":[]}").unwrap();
+        });
+        assert_eq!(get(&url, Some("s3cret"), Duration::from_secs(2)).unwrap(), b"{\"processes\":[]}");
+        server.join().unwrap();
+    }
+
+    /// Serves `response` to one request on a fresh listener and returns its URL.
+    fn serve_once(response: &'static [u8]) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/status", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = conn.read(&mut request).unwrap();
+            conn.write_all(response).unwrap();
+            // Keep the connection open so only Content-Length can end the body.
+            thread::sleep(Duration::from_millis(500));
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_get_stops_at_content_length() {
+        let (url, server) = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}");
+        let started = Instant::now();
+        assert_eq!(get(&url, None, Duration::from_secs(2)).unwrap(), b"{}");
+        assert!(started.elapsed() < Duration::from_millis(400));
+        server.join().unwrap();
+
+        let (url, server) = serve_once(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}trailing");
+        assert_eq!(get(&url, None, Duration::from_secs(2)).unwrap(), b"{}");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_get_rejects_bad_framing() {
+        let (url, server) = serve_once(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n");
+        let err = get(&url, None, Duration::from_secs(2)).unwrap_err();
+        assert!(err.to_string().contains("Transfer-Encoding"), "{err}");
+        server.join().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/status", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = conn.read(&mut request).unwrap();
+            conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n{}").unwrap();
+        });
+        let err = get(&url, None, Duration::from_secs(2)).unwrap_err();
+        assert!(err.to_string().contains("2 of 10"), "{err}");
+        server.join().unwrap();
+    }
+}
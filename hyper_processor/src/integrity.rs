@@ -0,0 +1,135 @@
+// Module for verifying the integrity of our own shared object
+
+use std::ffi::CStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use anyhow::{bail, Result};
+use tracing::debug;
+use crate::config::Settings;
+use crate::preload_check::get_file_info;
+
+/// Expected install location baked in at build time (e.g. by a distro package build).
+/// `self_integrity.expected_path` in the config file takes precedence.
+const BUILD_EXPECTED_PATH: Option<&str> = option_env!("HYPER_RASP_EXPECTED_PATH");
+
+static SELF_PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Returns the path of the object this code was loaded from, resolved via `dladdr`.
+/// The result is cached; `None` means the loader could not attribute our address to a file.
+pub fn self_path() -> Option<&'static Path> {
+    SELF_PATH.get_or_init(resolve_self_path).as_deref()
+}
+
+fn resolve_self_path() -> Option<PathBuf> {
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    // Any symbol that lives in this object works as an anchor.
+    let anchor = resolve_self_path as *const libc::c_void;
+    let found = unsafe { libc::dladdr(anchor, &mut info) };
+    if found == 0 || info.dli_fname.is_null() {
+        return None;
+    }
+    let fname = unsafe { CStr::from_ptr(info.dli_fname) };
+    let path = PathBuf::from(fname.to_str().ok()?);
+    // Resolve symlinks so the result matches the path the kernel reports in /proc/self/maps.
+    Some(fs::canonicalize(&path).unwrap_or(path))
+}
+
+/// Result of a successful self-integrity check, logged by the caller.
+#[derive(Debug)]
+pub struct SelfIdentity {
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+/// Locates our own mapping and compares it against the expected path and pinned digest.
+/// Returns Err on any mismatch, or when a check is configured but our path cannot be resolved.
+/// Returns Ok(None) if the path cannot be resolved and nothing is pinned.
+pub fn verify_self(settings: &Settings) -> Result<Option<SelfIdentity>> {
+    let expected_path = settings.self_integrity.expected_path.as_deref().or(BUILD_EXPECTED_PATH);
+    let expected_sha256 = settings.self_integrity.expected_sha256.as_deref();
+
+    match self_path() {
+        Some(path) => verify_file(path, expected_path, expected_sha256).map(Some),
+        None if expected_path.is_none() && expected_sha256.is_none() => Ok(None),
+        None => bail!("Could not resolve own library path via dladdr"),
+    }
+}
+
+fn verify_file(path: &Path, expected_path: Option<&str>, expected_sha256: Option<&str>) -> Result<SelfIdentity> {
+    if let Some(expected) = expected_path {
+        let expected_canon = fs::canonicalize(expected).unwrap_or_else(|_| PathBuf::from(expected));
+        if path != expected_canon {
+            bail!(
+                "Library loaded from unexpected location '{}' (expected '{}')",
+                path.display(),
+                expected_canon.display()
+            );
+        }
+    }
+
+    let (_, sha256) = get_file_info(path);
+    if let Some(expected) = expected_sha256 {
+        if !sha256.eq_ignore_ascii_case(expected.trim()) {
+            bail!(
+                "Library hash mismatch for '{}': got {} (expected {})",
+                path.display(),
+                sha256,
+                expected.trim()
+            );
+        }
+    }
+
+    debug!("[Integrity] Verified own library at '{}' ({})", path.display(), sha256);
+    Ok(SelfIdentity { path: path.to_path_buf(), sha256 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn write_lib(dir: &Path) -> PathBuf {
+        let path = dir.join("libhyper_processor.so");
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(b"not really an ELF").unwrap();
+        fs::canonicalize(path).unwrap()
+    }
+
+    #[test]
+    fn test_self_path_resolves() {
+        // In unit tests the anchor symbol lives in the test binary itself.
+        assert!(self_path().is_some());
+    }
+
+    #[test]
+    fn test_verify_file_no_expectations() {
+        let dir = tempdir().unwrap();
+        let lib = write_lib(dir.path());
+        let identity = verify_file(&lib, None, None).unwrap();
+        assert_eq!(identity.path, lib);
+        assert_ne!(identity.sha256, "<error>");
+    }
+
+    #[test]
+    fn test_verify_file_path_mismatch() {
+        let dir = tempdir().unwrap();
+        let lib = write_lib(dir.path());
+        let result = verify_file(&lib, Some("/usr/lib/libhyper_processor.so"), None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_verify_file_hash() {
+        let dir = tempdir().unwrap();
+        let lib = write_lib(dir.path());
+        let (_, actual) = get_file_info(&lib);
+
+        let ok = verify_file(&lib, Some(lib.to_str().unwrap()), Some(&actual.to_uppercase()));
+        assert!(ok.is_ok());
+
+        let bad = verify_file(&lib, None, Some("deadbeef"));
+        assert!(bad.is_err());
+    }
+}
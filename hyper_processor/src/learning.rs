@@ -1,48 +1,531 @@
-use std::fs::{File, OpenOptions};
+//! Learning mode: records every shared library the process maps, with enough identity
+//! (path, inode, size, SHA256) for `hyper-processor generate` to pin it.
+//!
+//! `/proc/self/maps` is rescanned periodically, and, in the LD_PRELOAD library (`preload`
+//! feature), right after every successful `dlopen`, so libraries loaded after start-up are
+//! learned too. Forked children inherit no scanner thread: one that execs starts over from the
+//! library constructor, one that doesn't (a prefork worker) switches to its own file and
+//! restarts the scanner at its first dlopen or scan, whichever comes first. A library loaded
+//! and unloaded again between two scans can still be missed.
+//!
+//! The `dlopen` interposer makes our library the caller glibc sees, so a dlopen that relies on
+//! the calling object's DT_RUNPATH resolves against ours instead. That is why it is only part
+//! of builds with both `preload` and `learning`, not of the protecting library.
+//!
+//! When the output is a directory each process writes its own file there, so a learned
+//! process tree doesn't overwrite itself; `read_run` and `merge_runs` combine them again.
+
+use std::collections::HashSet;
+use std::fs;
 use std::io::Write;
-use std::sync::Mutex;
-use chrono::Local;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::formats::{now_ms, rfc3339_millis};
+use crate::preload_check::get_file_info;
+
+/// Rescan period when `learning_scan_interval_ms` is not set.
+pub const DEFAULT_SCAN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// One library seen in learning mode. The output file holds one of these per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LearnedLibrary {
+    /// File name, as matched by `whitelisted_filenames`.
+    pub library: String,
+    pub path: String,
+    pub inode: u64,
+    pub size: u64,
+    pub sha256: String,
+    pub first_seen: String,
+    pub last_seen: String,
+    /// How many times it was found newly mapped (once per process, plus reloads after dlclose).
+    pub count: u64,
+}
+
+struct Session {
+    output: PathBuf,
+    /// Set when the output is per-process files in this directory.
+    dir: Option<PathBuf>,
+    libraries: Vec<LearnedLibrary>,
+    /// (path, inode) mapped at the previous scan.
+    mapped: HashSet<(String, u64)>,
+    interval: Option<Duration>,
+}
 
-static LEARNING_FILE: Mutex<Option<File>> = Mutex::new(None);
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+/// Set in a forked child until its first scan has moved the session to the child's own file.
+static FORKED: AtomicBool = AtomicBool::new(false);
+/// Set when a forked child could not take over the session; it learns nothing from then on.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+/// Set once `start` has run, so dlopen only pays for a scan when learning.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
 
 pub fn init(output_path: String) -> Result<(), String> {
-    let mut file_guard = LEARNING_FILE.lock()
-        .map_err(|e| format!("Failed to lock learning file: {}", e))?;
-    
-    // Create or truncate the file
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&output_path)
-        .map_err(|e| format!("Failed to open learning output file {}: {}", output_path, e))?;
-    
-    // Write header
-    writeln!(file, "# HyperProcessor Learning Mode Results").map_err(|e| e.to_string())?;
-    writeln!(file, "# Started: {}", Local::now().format("%Y-%m-%d %H:%M:%S")).map_err(|e| e.to_string())?;
-    writeln!(file).map_err(|e| e.to_string())?;
-    
-    *file_guard = Some(file);
+    let mut session = SESSION.lock()
+        .map_err(|e| format!("Failed to lock learning session: {}", e))?;
+
+    let path = PathBuf::from(&output_path);
+    let dir = path.is_dir().then(|| path.clone());
+    let output = match &dir {
+        Some(dir) => process_file(dir),
+        None => path,
+    };
+
+    // Fail early if the output is not writable
+    fs::File::create(&output)
+        .map_err(|e| format!("Failed to open learning output file {}: {}", output.display(), e))?;
+
+    *session = Some(Session { output, dir, libraries: Vec::new(), mapped: HashSet::new(), interval: None });
     Ok(())
 }
 
-pub fn record_library(library_name: &str) {
-    if let Ok(mut file_guard) = LEARNING_FILE.lock() {
-        if let Some(ref mut file) = *file_guard {
-            // Write as JSON line for easy parsing
-            let _ = writeln!(file, r#"{{"library": "{}"}}"#, library_name);
-            let _ = file.flush(); // Ensure it's written immediately
+/// This process's file in a per-process output directory. The timestamp keeps an exec'd
+/// image (same pid) from replacing its predecessor's file.
+fn process_file(dir: &Path) -> PathBuf {
+    dir.join(format!("learning-{}-{}.jsonl", std::process::id(), now_ms()))
+}
+
+/// Scans now, then every `interval` (`None` scans only now and at exit) for as long as the
+/// process lives, including in forked children.
+pub fn start(interval: Option<Duration>) {
+    if let Ok(mut session) = SESSION.lock() {
+        match session.as_mut() {
+            Some(session) => session.interval = interval,
+            None => return,
+        }
+    }
+    ACTIVE.store(true, Ordering::SeqCst);
+    scan();
+    static AT_FORK: Once = Once::new();
+    AT_FORK.call_once(|| unsafe {
+        libc::pthread_atfork(None, None, Some(mark_forked));
+    });
+    spawn_scanner(interval);
+}
+
+fn spawn_scanner(interval: Option<Duration>) {
+    let Some(interval) = interval else {
+        return;
+    };
+    let _ = std::thread::Builder::new()
+        .name("hyper-rasp-learn".into())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            scan();
+        });
+}
+
+/// Runs in the child right after fork(), where only async-signal-safe calls are allowed: the
+/// session is moved to the child's own file lazily, by `scan`.
+extern "C" fn mark_forked() {
+    FORKED.store(true, Ordering::SeqCst);
+}
+
+/// First scan in a forked child: the child must not write its parent's file, so it gets its
+/// own and starts over, with a scanner thread of its own. With a single output file (not a
+/// directory) the child stops learning. Returns whether learning goes on.
+fn restart_after_fork() -> bool {
+    // A lock held by another parent thread at fork time can never be released here
+    let Ok(mut guard) = SESSION.try_lock() else {
+        return false;
+    };
+    let Some(session) = guard.as_mut() else {
+        return false;
+    };
+    let Some(dir) = session.dir.clone() else {
+        *guard = None;
+        return false;
+    };
+    session.output = process_file(&dir);
+    session.libraries.clear();
+    session.mapped.clear();
+    let interval = session.interval;
+    drop(guard);
+    spawn_scanner(interval);
+    true
+}
+
+/// Records what is mapped right now and rewrites the output.
+fn scan() {
+    if STOPPED.load(Ordering::SeqCst) {
+        return;
+    }
+    if FORKED.swap(false, Ordering::SeqCst) && !restart_after_fork() {
+        STOPPED.store(true, Ordering::SeqCst);
+        return;
+    }
+    let Ok(maps) = fs::read_to_string("/proc/self/maps") else {
+        return;
+    };
+    let mapped = mapped_libraries(&maps, crate::integrity::self_path());
+    if let Ok(mut session) = SESSION.lock() {
+        if let Some(session) = session.as_mut() {
+            let session = &mut *session;
+            merge(&mut session.libraries, &mut session.mapped, &mapped, &rfc3339_millis(now_ms()));
+            // Rewritten on every scan so a killed process still leaves a complete file
+            let _ = write_records(&session.output, &session.libraries);
+        }
+    }
+}
+
+/// Called after a successful dlopen: records the new library at once, and is the first
+/// chance a forked worker without a scanner thread gets to restart one.
+#[cfg_attr(not(feature = "preload"), allow(dead_code))]
+pub(crate) fn after_dlopen() {
+    if ACTIVE.load(Ordering::SeqCst) {
+        scan();
+    }
+}
+
+#[cfg(feature = "preload")]
+type DlopenFn = unsafe extern "C" fn(*const libc::c_char, libc::c_int) -> *mut libc::c_void;
+
+/// # Safety
+/// Same contract as libc `dlopen`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn dlopen(filename: *const libc::c_char, flags: libc::c_int) -> *mut libc::c_void {
+    static REAL_DLOPEN: std::sync::OnceLock<Option<DlopenFn>> = std::sync::OnceLock::new();
+    let real = REAL_DLOPEN.get_or_init(|| {
+        let sym = libc::dlsym(libc::RTLD_NEXT, c"dlopen".as_ptr());
+        (!sym.is_null()).then(|| std::mem::transmute::<*mut libc::c_void, DlopenFn>(sym))
+    });
+    let Some(real) = real else {
+        return std::ptr::null_mut();
+    };
+    let handle = real(filename, flags);
+    if !handle.is_null() {
+        after_dlopen();
+    }
+    handle
+}
+
+/// Executable shared-object mappings as (path, inode), skipping our own library
+/// (it is covered by self_integrity).
+fn mapped_libraries(maps: &str, self_path: Option<&Path>) -> Vec<(String, u64)> {
+    let mut found: Vec<(String, u64)> = Vec::new();
+    for line in maps.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 6 || !parts[1].contains('x') {
+            continue;
+        }
+        let Some(path) = parts[5..].iter().find(|p| p.starts_with('/')) else {
+            continue;
+        };
+        let is_library = Path::new(path).file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.contains(".so"));
+        if !is_library || self_path == Some(Path::new(path)) {
+            continue;
+        }
+        let inode = parts[4].parse().unwrap_or(0);
+        if !found.iter().any(|(p, i)| p == path && *i == inode) {
+            found.push((path.to_string(), inode));
         }
     }
+    found
 }
 
 pub fn save_and_cleanup() {
-    if let Ok(mut file_guard) = LEARNING_FILE.lock() {
-        if let Some(mut file) = file_guard.take() {
-            // Write footer
-            let _ = writeln!(file);
-            let _ = writeln!(file, "# Ended: {}", Local::now().format("%Y-%m-%d %H:%M:%S"));
-            let _ = file.flush();
+    // Catch anything loaded since the last scan
+    scan();
+    if STOPPED.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Ok(mut session) = SESSION.lock() {
+        if let Some(session) = session.take() {
+            let _ = write_records(&session.output, &session.libraries);
+        }
+    }
+}
+
+/// Updates `libraries` with one scan. A library counts again only when it was not mapped at
+/// the previous scan; new ones are hashed once.
+fn merge(libraries: &mut Vec<LearnedLibrary>, previous: &mut HashSet<(String, u64)>, mapped: &[(String, u64)], now: &str) {
+    for (path, inode) in mapped {
+        let newly_mapped = !previous.contains(&(path.clone(), *inode));
+        if let Some(known) = libraries.iter_mut().find(|l| l.path == *path && l.inode == *inode) {
+            known.last_seen = now.to_string();
+            if newly_mapped {
+                known.count += 1;
+            }
+            continue;
+        }
+        let (size, sha256) = get_file_info(Path::new(path));
+        libraries.push(LearnedLibrary {
+            library: Path::new(path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            path: path.clone(),
+            inode: *inode,
+            size,
+            sha256,
+            first_seen: now.to_string(),
+            last_seen: now.to_string(),
+            count: 1,
+        });
+    }
+    *previous = mapped.iter().cloned().collect();
+}
+
+/// A library after merging runs, with the number of runs it appeared in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergedLibrary {
+    pub library: LearnedLibrary,
+    pub runs: usize,
+}
+
+/// Reads one learning run: a learning file, or a directory of per-process `*.jsonl` files.
+/// Records of the same library (path and hash) are folded together.
+pub fn read_run(path: &Path) -> Result<Vec<LearnedLibrary>> {
+    let files = if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)
+            .with_context(|| format!("Could not list {}", path.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "jsonl"))
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut libraries = Vec::new();
+    for file in files {
+        let content = fs::read_to_string(&file)
+            .with_context(|| format!("Could not read {}", file.display()))?;
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let record = serde_json::from_str(line)
+                .with_context(|| format!("{}:{}: not a learning record", file.display(), number + 1))?;
+            fold(&mut libraries, record);
+        }
+    }
+    Ok(libraries)
+}
+
+/// Unions several runs. Counts add up, first/last seen widen, and `runs` says how many
+/// of the inputs contained each library.
+pub fn merge_runs(runs: &[Vec<LearnedLibrary>]) -> Vec<MergedLibrary> {
+    let mut merged: Vec<MergedLibrary> = Vec::new();
+    for run in runs {
+        for record in run {
+            match merged.iter_mut().find(|m| same_library(&m.library, record)) {
+                Some(known) => {
+                    widen(&mut known.library, record);
+                    known.runs += 1;
+                }
+                None => merged.push(MergedLibrary { library: record.clone(), runs: 1 }),
+            }
+        }
+    }
+    merged.sort_by(|a, b| (&a.library.path, &a.library.sha256).cmp(&(&b.library.path, &b.library.sha256)));
+    merged
+}
+
+fn same_library(a: &LearnedLibrary, b: &LearnedLibrary) -> bool {
+    a.path == b.path && a.sha256 == b.sha256
+}
+
+fn fold(libraries: &mut Vec<LearnedLibrary>, record: LearnedLibrary) {
+    match libraries.iter_mut().find(|l| same_library(l, &record)) {
+        Some(known) => widen(known, &record),
+        None => libraries.push(record),
+    }
+}
+
+/// Adds `other`'s occurrences to `into`. Timestamps are fixed-width RFC 3339, so they compare as strings.
+fn widen(into: &mut LearnedLibrary, other: &LearnedLibrary) {
+    into.count += other.count;
+    if other.first_seen < into.first_seen {
+        into.first_seen = other.first_seen.clone();
+    }
+    if other.last_seen > into.last_seen {
+        into.last_seen = other.last_seen.clone();
+        into.inode = other.inode;
+        into.size = other.size;
+    }
+}
+
+/// Replaces `output` with one JSON object per library, via a temporary file and rename.
+pub fn write_records(output: &Path, libraries: &[LearnedLibrary]) -> std::io::Result<()> {
+    let mut staging = output.as_os_str().to_owned();
+    staging.push(format!(".{}.tmp", std::process::id()));
+    let staging = PathBuf::from(staging);
+
+    let mut file = fs::File::create(&staging)?;
+    for library in libraries {
+        serde_json::to_writer(&mut file, library)?;
+        file.write_all(b"\n")?;
+    }
+    file.sync_all()?;
+    fs::rename(&staging, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefork_worker_learns_dlopen_before_kill() {
+        let dir = tempfile::tempdir().unwrap();
+        init(dir.path().to_string_lossy().into_owned()).unwrap();
+        start(None);
+        let parent_files: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().path()).collect();
+
+        let worker = unsafe { libc::fork() };
+        if worker == 0 {
+            // A prefork worker: no exec, no scanner thread, killed without running destructors
+            let handle = unsafe { libc::dlopen(c"libbz2.so.1".as_ptr(), libc::RTLD_NOW) };
+            if !handle.is_null() {
+                after_dlopen();
+            }
+            loop {
+                unsafe { libc::pause() };
+            }
+        }
+
+        let mut learned = String::new();
+        for _ in 0..100 {
+            learned = fs::read_dir(dir.path()).unwrap()
+                .map(|e| e.unwrap().path())
+                .filter(|path| !parent_files.contains(path) && path.extension().is_some_and(|ext| ext == "jsonl"))
+                .filter_map(|path| fs::read_to_string(path).ok())
+                .collect();
+            if learned.contains("libbz2.so") {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        unsafe {
+            libc::kill(worker, libc::SIGKILL);
+            libc::waitpid(worker, std::ptr::null_mut(), 0);
         }
+        ACTIVE.store(false, Ordering::SeqCst);
+        *SESSION.lock().unwrap() = None;
+
+        assert!(learned.contains("libbz2.so"), "worker file: {learned}");
+        let parent: String = parent_files.iter().map(|path| fs::read_to_string(path).unwrap()).collect();
+        assert!(!parent.contains("libbz2.so"));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_merge_dedups_and_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("libplugin.so.2");
+        fs::write(&lib, b"plugin").unwrap();
+        let lib = lib.to_str().unwrap().to_string();
+        let gone = "/nonexistent/libgone.so".to_string();
+
+        let mut libraries = Vec::new();
+        let mut previous = HashSet::new();
+        merge(&mut libraries, &mut previous, &[(lib.clone(), 7)], "2026-01-01T00:00:00.000Z");
+        // Still mapped: seen again, not loaded again
+        merge(&mut libraries, &mut previous, &[(lib.clone(), 7), (gone.clone(), 9)], "2026-01-02T00:00:00.000Z");
+        assert_eq!(libraries[0].count, 1);
+        // Unloaded, then dlopen()ed again
+        merge(&mut libraries, &mut previous, &[(gone, 9)], "2026-01-03T00:00:00.000Z");
+        merge(&mut libraries, &mut previous, &[(lib, 7)], "2026-01-04T00:00:00.000Z");
+
+        assert_eq!(libraries.len(), 2);
+        assert_eq!(libraries[0].library, "libplugin.so.2");
+        assert_eq!(libraries[0].size, 6);
+        assert_eq!(libraries[0].count, 2);
+        assert_eq!(libraries[0].first_seen, "2026-01-01T00:00:00.000Z");
+        assert_eq!(libraries[0].last_seen, "2026-01-04T00:00:00.000Z");
+        assert_eq!(libraries[1].sha256, "<error>");
+        assert_eq!(libraries[1].count, 1);
+
+        // Names with quotes or backslashes must still come out as valid JSON
+        let odd = dir.path().join("lib\"odd\\.so");
+        fs::write(&odd, b"x").unwrap();
+        merge(&mut libraries, &mut previous, &[(odd.to_str().unwrap().to_string(), 8)], "2026-01-05T00:00:00.000Z");
+        let output = dir.path().join("learned.jsonl");
+        write_records(&output, &libraries).unwrap();
+        let parsed: Vec<LearnedLibrary> = fs::read_to_string(&output).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed, libraries);
+    }
+
+    #[test]
+    fn test_mapped_libraries() {
+        let maps = "\
+7f0000000000-7f0000001000 r--p 00000000 fd:01 11 /usr/lib64/libc.so.6
+7f0000001000-7f0000002000 r-xp 00001000 fd:01 11 /usr/lib64/libc.so.6
+7f0000002000-7f0000003000 r-xp 00002000 fd:01 11 /usr/lib64/libc.so.6
+7f0000003000-7f0000004000 r-xp 00000000 fd:01 12 /opt/app/plugins/libplugin.so (deleted)
+7f0000004000-7f0000005000 r-xp 00000000 fd:01 13 /opt/hyper/libhyper_processor.so
+7f0000005000-7f0000006000 r-xp 00000000 fd:01 14 /usr/bin/app
+7f0000006000-7f0000007000 rwxp 00000000 00:00 0
+";
+        let found = mapped_libraries(maps, Some(Path::new("/opt/hyper/libhyper_processor.so")));
+        assert_eq!(found, vec![
+            ("/usr/lib64/libc.so.6".to_string(), 11),
+            ("/opt/app/plugins/libplugin.so".to_string(), 12),
+        ]);
+    }
+
+    fn record(path: &str, sha256: &str, count: u64, first: &str, last: &str) -> LearnedLibrary {
+        LearnedLibrary {
+            library: Path::new(path).file_name().unwrap().to_string_lossy().into_owned(),
+            path: path.to_string(),
+            inode: 1,
+            size: 1,
+            sha256: sha256.to_string(),
+            first_seen: first.to_string(),
+            last_seen: last.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_read_run_folds_per_process_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_records(&dir.path().join("learning-1-1.jsonl"), &[
+            record("/lib/liba.so", "aa", 1, "2026-01-01T00:00:00.000Z", "2026-01-01T00:00:00.000Z"),
+        ]).unwrap();
+        write_records(&dir.path().join("learning-2-1.jsonl"), &[
+            record("/lib/liba.so", "aa", 2, "2026-01-02T00:00:00.000Z", "2026-01-03T00:00:00.000Z"),
+            record("/lib/libb.so", "bb", 1, "2026-01-02T00:00:00.000Z", "2026-01-02T00:00:00.000Z"),
+        ]).unwrap();
+        fs::write(dir.path().join("learning-3-1.jsonl.3.tmp"), "garbage").unwrap();
+
+        let run = read_run(dir.path()).unwrap();
+        assert_eq!(run.len(), 2);
+        assert_eq!(run[0].count, 3);
+        assert_eq!(run[0].first_seen, "2026-01-01T00:00:00.000Z");
+        assert_eq!(run[0].last_seen, "2026-01-03T00:00:00.000Z");
+
+        fs::write(dir.path().join("bad.jsonl"), "{\"library\": \"liba.so\"}\n").unwrap();
+        assert!(read_run(dir.path()).unwrap_err().to_string().contains("bad.jsonl:1"));
+    }
+
+    #[test]
+    fn test_merge_runs_counts_runs() {
+        let day = |d: u32| format!("2026-01-0{d}T00:00:00.000Z");
+        let first = vec![
+            record("/lib/liba.so", "aa", 1, &day(1), &day(1)),
+            record("/lib/libb.so", "bb", 1, &day(1), &day(1)),
+        ];
+        let second = vec![
+            record("/lib/liba.so", "aa", 4, &day(2), &day(2)),
+            // Same path, new content: a different library as far as pinning goes
+            record("/lib/libb.so", "b2", 1, &day(2), &day(2)),
+        ];
+        let merged = merge_runs(&[first, second]);
+        let summary: Vec<_> = merged.iter()
+            .map(|m| (m.library.path.as_str(), m.library.sha256.as_str(), m.runs, m.library.count))
+            .collect();
+        assert_eq!(summary, vec![
+            ("/lib/liba.so", "aa", 2, 5),
+            ("/lib/libb.so", "b2", 1, 1),
+            ("/lib/libb.so", "bb", 1, 1),
+        ]);
+        assert_eq!(merged[0].library.last_seen, day(2));
+    }
+}
@@ -12,24 +12,43 @@ use std::env;
 use crate::preload_check::{perform_check};
 use std::fs;
 use std::process;
-use tracing::{span, Level as TracingLevel, debug, info, error};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, fmt};
+use tracing::{span, event, Level as TracingLevel, debug, info, error};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 #[cfg(feature = "learning")]
 use ctor::dtor;
 
 // Main modules
 pub mod config;
 pub mod preload_check;
+pub mod alerting;
+pub mod http;
+pub mod forensics;
+pub mod event_store;
+pub mod monitor;
+mod integrity;
+mod seccomp;
+mod wx_guard;
+mod child_policy;
+mod sinks;
+mod redaction;
+mod dedup;
+#[cfg(any(feature = "metrics", feature = "otel"))]
+mod library_labels;
+pub mod formats;
+pub mod policy_diff;
 
 #[cfg(feature = "metrics")]
-mod metrics;
+pub mod metrics;
 
 #[cfg(feature = "learning")]
-mod learning;
+pub mod learning;
 
 #[cfg(feature = "ebpf")]
 pub mod ebpf;
 
+#[cfg(feature = "otel")]
+pub mod otel;
+
 
 /// Library constructor function called when the shared library is loaded.
 #[ctor]
@@ -43,16 +62,8 @@ fn init_library() {
         .map(|s| s.trim().to_string())
         .unwrap_or_else(|_| String::from("<unknown>"));
     
-    let cmdline_result = fs::read_to_string("/proc/self/cmdline");
-    let cmdline_val = cmdline_result
-        .as_ref()
-        .map(|s| s.replace('\0', " ").trim().to_string())
-        .unwrap_or_else(|_| String::from("<unknown>"));
-    
     let ppid_val = nix::unistd::getppid().as_raw() as u32;
     
-    let ld_preload_val = env::var("LD_PRELOAD").unwrap_or_else(|_| String::from("<not set>"));
-    
     let version_val = env!("CARGO_PKG_VERSION");
     
     // Early logging fallback (before tracing is initialized)
@@ -67,32 +78,31 @@ fn init_library() {
         }
     }
 
-    // Create a root span that will carry these fields for all log events within its scope.
-    let root_span = span!(TracingLevel::INFO, "hyper_rasp_init", 
-        pid = pid_val,
-        ppid = ppid_val,
-        process_name = comm_val.as_str(),
-        ld_preload = ld_preload_val.as_str(),
-        cmdline = cmdline_val.as_str(),
-        version = version_val
-    );
-    let _enter = root_span.enter(); // Enter the span, fields will be attached to subsequent events
-
     // --- Load Configuration First ---
-    let mut settings = match Settings::load() { 
-        Ok(s) => s,
+    // In secure-execution mode (setuid etc.) the environment is untrusted: policy comes
+    // only from the root-owned SECURE_CONFIG_PATH and HYPER_RASP_* overrides are ignored.
+    let secure_execution = config::is_secure_execution();
+    let (load_result, config_source) = if secure_execution {
+        (Settings::load_secure(), config::SECURE_CONFIG_PATH.to_string())
+    } else {
+        (Settings::load(), config::config_path())
+    };
+    let (mut settings, config_source) = match load_result { 
+        Ok(s) => (s, config_source),
         Err(e) => {
+            #[cfg(feature = "metrics")]
+            metrics::record_config_load_failure();
             eprintln!(
                 "[pid:{} ppid:{} process_name:'{}'] [HYPER_RASP PRE-LOGGING ERROR] Failed to load configuration: {}. Using default settings.", 
                 pid_val, ppid_val, comm_val, e // Use pre-span values for pre-logging
             );
-            Settings::default()
+            (Settings::default(), String::from("<defaults>"))
         }
     }; 
 
-    // --- Override audit_mode from environment variable (highest priority) ---
+    // --- Override audit_mode from environment variable (highest priority, unless secure-execution or frozen) ---
     match env::var("HYPER_RASP_AUDIT_MODE") {
-        Ok(val) => {
+        Ok(val) if !secure_execution && !settings.freeze_policy => {
             let val_lower = val.to_lowercase();
             if val_lower == "true" || val_lower == "1" || val_lower == "yes" {
                  if !settings.audit_mode { 
@@ -106,40 +116,69 @@ fn init_library() {
                 settings.audit_mode = false;
             }
         }
-        Err(_) => {}
+        _ => {}
     }
 
+    // One cardinality cap for the Prometheus and OTLP `library` labels
+    #[cfg(any(feature = "metrics", feature = "otel"))]
+    library_labels::configure(&settings.metrics);
+    monitor::init(&settings.monitor, &comm_val);
+
     // Initialize learning mode if enabled
     #[cfg(feature = "learning")]
     {
         if settings.learning_mode {
             if let Some(ref output_path) = settings.learning_output {
-                if let Err(e) = learning::init(output_path.clone()) {
-                    eprintln!("{} [Init] Failed to initialize learning mode: {}", _log_prefix, e);
+                match learning::init(output_path.clone()) {
+                    Ok(()) => learning::start(match settings.learning_scan_interval_ms {
+                        Some(0) => None,
+                        Some(ms) => Some(std::time::Duration::from_millis(ms)),
+                        None => Some(learning::DEFAULT_SCAN_INTERVAL),
+                    }),
+                    Err(e) => eprintln!("{} [Init] Failed to initialize learning mode: {}", _log_prefix, e),
                 }
                 debug!("Learning mode initialized, output: {}", output_path);
             }
         }
     }
 
+    // Secrets in argv or LD_PRELOAD must be scrubbed before they reach the root span
+    let redaction_result = redaction::init(&settings.redaction);
+    let redactor = redaction::active();
+    let cmdline_val = fs::read("/proc/self/cmdline")
+        .map(|raw| redactor.redact_cmdline(&raw))
+        .unwrap_or_else(|_| String::from("<unknown>"));
+    let ld_preload_val = env::var("LD_PRELOAD")
+        .map(|value| redactor.redact_ld_preload(&value))
+        .unwrap_or_else(|_| String::from("<not set>"));
+
     // --- Initialize Logger ---
     // base_log_fields array is no longer needed as fields are in the root_span
 
-    let log_filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new(if cfg!(debug_assertions) { "debug" } else { "info" }));
+    // RUST_LOG is as untrusted as HYPER_RASP_* in secure-execution mode: it could silence detections
+    let default_filter = || EnvFilter::new(if cfg!(debug_assertions) { "debug" } else { "info" });
+    let log_filter = if secure_execution {
+        default_filter()
+    } else {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| default_filter())
+    };
 
-    let json_layer = fmt::layer()
-        .json()
-        .with_current_span(true) // Enable to see span fields in logs
-        .with_span_list(true)   // Include span context in logs
-        .with_target(true)
-        .with_file(true)
-        .with_line_number(true);
+    // One layer per configured sink (JSON on stderr unless `sinks` says otherwise)
+    let sink_layers = sinks::build_layers(&settings.sinks, settings.log_format);
         
     // Initialize the global subscriber
     let subscriber = tracing_subscriber::registry()
         .with(log_filter)
-        .with(json_layer);
+        .with(sink_layers);
+
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(otel::layer(&settings.otel, &[
+        ("process.pid", pid_val.into()),
+        ("process.parent_pid", ppid_val.into()),
+        ("process.executable.name", comm_val.as_str().into()),
+        ("process.command_line", cmdline_val.as_str().into()),
+        ("host.name", sinks::hostname().into()),
+    ]));
 
     if subscriber.try_init().is_err() {
          eprintln!(
@@ -148,11 +187,72 @@ fn init_library() {
         );
     }
     
+    // Create a root span that will carry these fields for all log events within its scope.
+    // It must be created after the subscriber is installed, otherwise it is a disabled no-op span.
+    let root_span = span!(TracingLevel::INFO, "hyper_rasp_init", 
+        pid = pid_val,
+        ppid = ppid_val,
+        process_name = comm_val.as_str(),
+        ld_preload = ld_preload_val.as_str(),
+        cmdline = cmdline_val.as_str(),
+        version = version_val,
+        secure_execution = secure_execution,
+        config_source = config_source.as_str(),
+        seccomp_profile = tracing::field::Empty
+    );
+    let _enter = root_span.enter(); // Enter the span, fields will be attached to subsequent events
+
     // Log final status using the initialized logger
     info!(audit_mode = settings.audit_mode, "HyperProcessor RASP library loaded.");
 
+    if let Err(e) = redaction_result {
+        event!(TracingLevel::ERROR, error = %e, "Invalid redaction rules; redacting all cmdline and LD_PRELOAD values");
+    }
+
+    if secure_execution {
+        if let Ok(value) = env::var(EnvFilter::DEFAULT_ENV) {
+            event!(TracingLevel::WARN,
+                ignored_variable = EnvFilter::DEFAULT_ENV,
+                ignored_value = value.as_str(),
+                "Ignored log filter override in secure-execution mode"
+            );
+        }
+    }
+
+    // With a frozen policy, any HYPER_RASP_* override is treated as a tampering attempt
+    for (variable, value) in &settings.ignored_env_overrides {
+        event!(TracingLevel::WARN,
+            ignored_variable = variable.as_str(),
+            ignored_value = value.as_str(),
+            alert_type = "SECURITY",
+            "Ignored environment override: policy is frozen by config file"
+        );
+    }
+
     // --- Perform Check only if NOT running tests ---
     if !cfg!(test) {
+        // Verify our own library before trusting anything it decides
+        match integrity::verify_self(&settings) {
+            Ok(Some(identity)) => {
+                info!(self_path = %identity.path.display(), self_sha256 = identity.sha256.as_str(), "Self-integrity check passed.");
+            }
+            Ok(None) => {
+                debug!("Own library path could not be resolved; no self-integrity policy configured.");
+            }
+            Err(e) if settings.audit_mode => {
+                event!(TracingLevel::WARN, error = %e, alert_type = "AUDIT", "Self-integrity check failed (Audit Mode)");
+            }
+            Err(e) => {
+                event!(TracingLevel::ERROR, error = %e, alert_type = "SECURITY", "Self-integrity check failed. Terminating.");
+                terminate();
+            }
+        }
+
+        // Start enforcing W^X (no-op unless wx_guard.mode is set)
+        wx_guard::init(&settings);
+        // Apply child_processes.policy to anything we execve/posix_spawn from here on
+        child_policy::init(&settings);
+
         info!("Running preload check...");
         match std::fs::read_to_string("/proc/self/maps") {
             Ok(maps_content) => {
@@ -161,21 +261,31 @@ fn init_library() {
                 match perform_check(&settings, &maps_content) {
                     Ok((found_unauthorized, audit_mode_used)) => {
                         if found_unauthorized && !audit_mode_used {
+                            report_check("blocked");
                             error!("Terminating process due to unauthorized library detection.");
-                            std::process::exit(1);
+                            terminate();
                         } else {
+                             report_check(if found_unauthorized { "audited" } else { "clean" });
+                             monitor::start_heartbeat(std::time::Duration::from_secs(settings.monitor.heartbeat_secs));
                              info!("Preload check completed.");
+                             if !found_unauthorized {
+                                 install_seccomp_profile(&settings, &root_span);
+                             }
                         }
                     }
                     Err(e) => {
+                        report_check("error");
                         error!(error = %e, "FATAL: Preload check function failed internally. Terminating.");
-                         std::process::exit(1); 
+                         terminate(); 
                     }
                 }
             }
             Err(e) => {
+                 #[cfg(feature = "metrics")]
+                 metrics::record_check_error();
+                 report_check("error");
                  error!(error = %e, "FATAL: Could not read /proc/self/maps. Terminating.");
-                 std::process::exit(1); 
+                 terminate(); 
             }
         }
     } else {
@@ -183,6 +293,45 @@ fn init_library() {
     }
 }
 
+/// Tells `hyper-processor monitor` how the preload check ended, with the counters it produced.
+fn report_check(verdict: &str) {
+    monitor::report(monitor::Report::Check { verdict: verdict.to_string() });
+    #[cfg(feature = "metrics")]
+    monitor::report(monitor::Report::Metrics { families: metrics::families() });
+}
+
+/// Exits with status 1 after pushing pending telemetry out.
+fn terminate() -> ! {
+    #[cfg(feature = "otel")]
+    otel::shutdown(std::time::Duration::from_secs(2));
+    process::exit(1);
+}
+
+/// Installs the configured seccomp profile once the process has been verified clean.
+fn install_seccomp_profile(settings: &Settings, root_span: &tracing::Span) {
+    let Some(requested) = settings.seccomp.profile.as_deref() else {
+        return;
+    };
+    let Some((name, profile)) = seccomp::selected_profile(settings) else {
+        error!(seccomp_profile = requested, "Seccomp profile is not defined; no filter installed.");
+        return;
+    };
+    match seccomp::install(&profile) {
+        Ok(()) => {
+            root_span.record("seccomp_profile", name.as_str());
+            info!(
+                seccomp_profile = name.as_str(),
+                deny_syscalls = ?profile.deny_syscalls,
+                action = ?profile.action,
+                "Seccomp lockdown profile installed."
+            );
+        }
+        Err(e) => {
+            error!(seccomp_profile = name.as_str(), error = %e, "Failed to install seccomp profile.");
+        }
+    }
+}
+
 /// Library destructor - called when the library is unloaded
 #[cfg(feature = "learning")]
 #[dtor]
@@ -0,0 +1,60 @@
+// Module bounding the `library` label values shared by the Prometheus and OTLP exporters
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use crate::config::MetricsSettings;
+
+/// Label value used once `metrics.max_library_labels` distinct libraries have been seen.
+pub const OTHER_LIBRARY: &str = "other";
+
+static SHARED: OnceLock<Arc<LibraryLabels>> = OnceLock::new();
+
+/// The distinct `library` label values handed out so far, up to a limit.
+pub struct LibraryLabels {
+    max: AtomicUsize,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl LibraryLabels {
+    pub fn new(max: usize) -> Self {
+        LibraryLabels { max: AtomicUsize::new(max), seen: Mutex::new(HashSet::new()) }
+    }
+
+    /// Changes how many distinct labels are admitted from now on.
+    pub fn set_limit(&self, max: usize) {
+        self.max.store(max, Ordering::Relaxed);
+    }
+
+    /// The label for `library`, or None when it would exceed the limit.
+    pub fn admit(&self, library: &str) -> Option<String> {
+        let normalized = normalize_library(library);
+        let mut seen = self.seen.lock().ok()?;
+        if seen.contains(&normalized) {
+            return Some(normalized);
+        }
+        if seen.len() >= self.max.load(Ordering::Relaxed) {
+            return None;
+        }
+        seen.insert(normalized.clone());
+        Some(normalized)
+    }
+}
+
+/// The set every exporter of this process labels with, so one cap bounds them all.
+pub fn shared() -> Arc<LibraryLabels> {
+    Arc::clone(SHARED.get_or_init(|| Arc::new(LibraryLabels::new(MetricsSettings::default().max_library_labels))))
+}
+
+/// Applies `metrics.max_library_labels` to the shared set.
+pub fn configure(settings: &MetricsSettings) {
+    shared().set_limit(settings.max_library_labels);
+}
+
+/// Strips the version suffix after `.so` (`libssl.so.3` -> `libssl.so`).
+pub fn normalize_library(library: &str) -> String {
+    match library.find(".so.") {
+        Some(end) => library[..end + 3].to_string(),
+        None => library.to_string(),
+    }
+}
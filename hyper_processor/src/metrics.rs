@@ -1,72 +1,451 @@
-use prometheus::{IntCounter, IntCounterVec, Opts, register_int_counter, register_int_counter_vec};
-use std::sync::OnceLock;
+use prometheus::{Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+use prometheus::core::Collector;
+use prometheus::proto::{MetricFamily, MetricType};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use crate::library_labels::LibraryLabels;
+use crate::monitor::{Family, Sample};
+
+pub use crate::library_labels::{normalize_library, OTHER_LIBRARY};
 
 static METRICS: OnceLock<Metrics> = OnceLock::new();
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+// Serializes `init` so concurrent callers can't register the same metrics twice
+static INIT: Mutex<()> = Mutex::new(());
+
+// Checks and hashes are normally well under a second; the top buckets catch huge libraries
+const DURATION_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
 
+/// Every metric the library exports.
+///
+/// | Name | Type | Labels |
+/// |------|------|--------|
+/// | `hyper_processor_blocks_total` | counter | |
+/// | `hyper_processor_audits_total` | counter | |
+/// | `hyper_processor_library_loads` | counter | `library`, `status` |
+/// | `hyper_processor_unauthorized_loads` | counter | `library`, `action` |
+/// | `hyper_processor_suppressed_detections` | counter | `library`, `action` |
+/// | `hyper_processor_check_duration_seconds` | histogram | |
+/// | `hyper_processor_library_hash_duration_seconds` | histogram | |
+/// | `hyper_processor_libraries_inspected` | gauge | |
+/// | `hyper_processor_config_load_failures_total` | counter | |
+/// | `hyper_processor_config_permission_warnings_total` | counter | |
+/// | `hyper_processor_check_errors_total` | counter | |
+/// | `hyper_processor_library_labels_dropped_total` | counter | |
+/// | `hyper_processor_build_info` | gauge (always 1) | `version`, `features` |
+///
+/// `library` values are normalized (see `normalize_library`) and capped, so randomized
+/// payload names can't grow the series count without bound. The metrics from `init` share
+/// their cap with the OTLP exporter.
 pub struct Metrics {
     pub blocks_total: IntCounter,
     pub audits_total: IntCounter,
     pub library_loads: IntCounterVec,
     pub unauthorized_loads: IntCounterVec,
+    pub suppressed_detections: IntCounterVec,
+    /// Wall time of one `perform_check` over `/proc/self/maps`.
+    pub check_duration: Histogram,
+    /// Time to hash one unauthorized library.
+    pub hash_duration: Histogram,
+    /// Shared-object mappings looked at by the latest check.
+    pub libraries_inspected: IntGauge,
+    /// Config file present but unreadable or invalid; defaults were used.
+    pub config_load_failures: IntCounter,
+    /// Config file writable by group or others.
+    pub permission_warnings: IntCounter,
+    /// Checks that could not complete (maps unreadable, internal failure).
+    pub check_errors: IntCounter,
+    /// Library label values replaced by `other` because the cap was reached.
+    pub labels_dropped: IntCounter,
+    library_labels: Arc<LibraryLabels>,
+}
+
+fn register<C: Collector + Clone + 'static>(registry: &Registry, collector: C) -> prometheus::Result<C> {
+    registry.register(Box::new(collector.clone()))?;
+    Ok(collector)
+}
+
+/// Cargo features this library was built with, comma-separated.
+pub fn enabled_features() -> String {
+    let features: [(&str, bool); 5] = [
+        ("cli", cfg!(feature = "cli")),
+        ("metrics", cfg!(feature = "metrics")),
+        ("learning", cfg!(feature = "learning")),
+        ("ebpf", cfg!(feature = "ebpf")),
+        ("otel", cfg!(feature = "otel")),
+    ];
+    features.iter().filter(|(_, on)| *on).map(|(name, _)| *name).collect::<Vec<_>>().join(",")
 }
 
 impl Metrics {
-    fn new() -> prometheus::Result<Self> {
-        let blocks_total = register_int_counter!(
+    /// Creates every metric and registers it in `registry`.
+    pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+        let labels = LibraryLabels::new(crate::config::MetricsSettings::default().max_library_labels);
+        Self::with_library_labels(registry, Arc::new(labels))
+    }
+
+    fn with_library_labels(registry: &Registry, library_labels: Arc<LibraryLabels>) -> prometheus::Result<Self> {
+        let blocks_total = register(registry, IntCounter::with_opts(
             Opts::new("hyper_processor_blocks_total", "Total number of blocked library loads")
-        )?;
+        )?)?;
         
-        let audits_total = register_int_counter!(
+        let audits_total = register(registry, IntCounter::with_opts(
             Opts::new("hyper_processor_audits_total", "Total number of audited library loads")
-        )?;
+        )?)?;
         
-        let library_loads = register_int_counter_vec!(
+        let library_loads = register(registry, IntCounterVec::new(
             Opts::new("hyper_processor_library_loads", "Total library load attempts"),
             &["library", "status"]
-        )?;
+        )?)?;
         
-        let unauthorized_loads = register_int_counter_vec!(
+        let unauthorized_loads = register(registry, IntCounterVec::new(
             Opts::new("hyper_processor_unauthorized_loads", "Unauthorized library load attempts"),
             &["library", "action"]
-        )?;
+        )?)?;
+        
+        let suppressed_detections = register(registry, IntCounterVec::new(
+            Opts::new("hyper_processor_suppressed_detections", "Repeated detections suppressed by deduplication"),
+            &["library", "action"]
+        )?)?;
+
+        let check_duration = register(registry, Histogram::with_opts(
+            HistogramOpts::new("hyper_processor_check_duration_seconds", "Duration of the preload check")
+                .buckets(DURATION_BUCKETS.to_vec())
+        )?)?;
+
+        let hash_duration = register(registry, Histogram::with_opts(
+            HistogramOpts::new("hyper_processor_library_hash_duration_seconds", "Time spent hashing one library")
+                .buckets(DURATION_BUCKETS.to_vec())
+        )?)?;
+
+        let libraries_inspected = register(registry, IntGauge::with_opts(
+            Opts::new("hyper_processor_libraries_inspected", "Shared libraries inspected by the last preload check")
+        )?)?;
+
+        let config_load_failures = register(registry, IntCounter::with_opts(
+            Opts::new("hyper_processor_config_load_failures_total", "Configuration loads that failed and fell back to defaults")
+        )?)?;
+
+        let permission_warnings = register(registry, IntCounter::with_opts(
+            Opts::new("hyper_processor_config_permission_warnings_total", "Configuration files found writable by group or others")
+        )?)?;
+
+        let check_errors = register(registry, IntCounter::with_opts(
+            Opts::new("hyper_processor_check_errors_total", "Preload checks that failed internally")
+        )?)?;
+
+        let labels_dropped = register(registry, IntCounter::with_opts(
+            Opts::new("hyper_processor_library_labels_dropped_total", "Library label values recorded as 'other' because of the cardinality cap")
+        )?)?;
+
+        // Registered for its constant labels only, so it isn't kept as a field
+        let build_info = register(registry, IntGaugeVec::new(
+            Opts::new("hyper_processor_build_info", "Build information; the value is always 1"),
+            &["version", "features"]
+        )?)?;
+        build_info.with_label_values(&[env!("CARGO_PKG_VERSION"), &enabled_features()]).set(1);
         
         Ok(Metrics {
             blocks_total,
             audits_total,
             library_loads,
             unauthorized_loads,
+            suppressed_detections,
+            check_duration,
+            hash_duration,
+            libraries_inspected,
+            config_load_failures,
+            permission_warnings,
+            check_errors,
+            labels_dropped,
+            library_labels,
+        })
+    }
+
+    /// Changes how many distinct library labels are admitted from now on.
+    pub fn set_library_label_limit(&self, max: usize) {
+        self.library_labels.set_limit(max);
+    }
+
+    fn library_label(&self, library_name: &str) -> String {
+        self.library_labels.admit(library_name).unwrap_or_else(|| {
+            self.labels_dropped.inc();
+            OTHER_LIBRARY.to_string()
         })
     }
+
+    fn unauthorized_library(&self, library_name: &str, audit_mode: bool) {
+        let library = self.library_label(library_name);
+        let library_name = library.as_str();
+        if audit_mode {
+            self.audits_total.inc();
+            self.unauthorized_loads.with_label_values(&[library_name, "audit"]).inc();
+        } else {
+            self.blocks_total.inc();
+            self.unauthorized_loads.with_label_values(&[library_name, "block"]).inc();
+        }
+        self.library_loads.with_label_values(&[library_name, "unauthorized"]).inc();
+    }
+
+    fn suppressed_detection(&self, library_name: &str, audit_mode: bool) {
+        let library = self.library_label(library_name);
+        let library_name = library.as_str();
+        let action = if audit_mode { "audit" } else { "block" };
+        self.suppressed_detections.with_label_values(&[library_name, action]).inc();
+    }
+
+    fn authorized_library(&self, library_name: &str) {
+        let library = self.library_label(library_name);
+        let library_name = library.as_str();
+        self.library_loads.with_label_values(&[library_name, "authorized"]).inc();
+    }
+
+    fn check_completed(&self, elapsed: Duration, libraries_inspected: usize) {
+        self.check_duration.observe(elapsed.as_secs_f64());
+        self.libraries_inspected.set(libraries_inspected as i64);
+    }
+}
+
+/// The registry holding every hyper_processor metric. It is private to this library (not
+/// `prometheus::default_registry()`), so a host application using the `prometheus` crate
+/// can't collide with it; embedders merge it into their own exporter via `registry().gather()`.
+pub fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::new)
 }
 
+/// Creates the metrics in `registry()`. Calling it again is a no-op.
 pub fn init() -> prometheus::Result<()> {
-    let metrics = Metrics::new()?;
-    METRICS.set(metrics).map_err(|_| {
-        prometheus::Error::AlreadyReg
-    })?;
+    let _guard = INIT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if METRICS.get().is_some() {
+        return Ok(());
+    }
+    let metrics = Metrics::with_library_labels(registry(), crate::library_labels::shared())?;
+    let _ = METRICS.set(metrics);
     Ok(())
 }
 
-// Uncomment if needed to access metrics directly
-// pub fn get() -> &'static Metrics {
-//     METRICS.get().expect("Metrics not initialized")
-// }
+/// The metrics created by `init`, if it has run.
+pub fn get() -> Option<&'static Metrics> {
+    METRICS.get()
+}
 
 pub fn record_unauthorized_library(library_name: &str, audit_mode: bool) {
     if let Some(metrics) = METRICS.get() {
-        if audit_mode {
-            metrics.audits_total.inc();
-            metrics.unauthorized_loads.with_label_values(&[library_name, "audit"]).inc();
-        } else {
-            metrics.blocks_total.inc();
-            metrics.unauthorized_loads.with_label_values(&[library_name, "block"]).inc();
-        }
-        metrics.library_loads.with_label_values(&[library_name, "unauthorized"]).inc();
+        metrics.unauthorized_library(library_name, audit_mode);
+    }
+}
+
+/// Counts a repeat that dedup kept out of the logs and the other unauthorized-load counters.
+pub fn record_suppressed_detection(library_name: &str, audit_mode: bool) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.suppressed_detection(library_name, audit_mode);
     }
 }
 
 pub fn record_authorized_library(library_name: &str) {
     if let Some(metrics) = METRICS.get() {
-        metrics.library_loads.with_label_values(&[library_name, "authorized"]).inc();
+        metrics.authorized_library(library_name);
+    }
+}
+
+/// Records a finished preload check: its duration and how many libraries it looked at.
+pub fn record_check(elapsed: Duration, libraries_inspected: usize) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.check_completed(elapsed, libraries_inspected);
+    }
+}
+
+pub fn record_hash_duration(elapsed: Duration) {
+    if let Some(metrics) = METRICS.get() {
+        metrics.hash_duration.observe(elapsed.as_secs_f64());
+    }
+}
+
+pub fn record_check_error() {
+    if let Some(metrics) = METRICS.get() {
+        metrics.check_errors.inc();
+    }
+}
+
+pub fn record_config_load_failure() {
+    if let Some(metrics) = METRICS.get() {
+        metrics.config_load_failures.inc();
+    }
+}
+
+pub fn record_permission_warning() {
+    if let Some(metrics) = METRICS.get() {
+        metrics.permission_warnings.inc();
+    }
+}
+
+/// Current values of every registered metric, in the monitor's wire format.
+pub fn families() -> Vec<Family> {
+    convert(&registry().gather())
+}
+
+fn convert(gathered: &[MetricFamily]) -> Vec<Family> {
+    gathered.iter().map(|family| {
+        let name = family.get_name();
+        let mut samples = Vec::new();
+        let kind = match family.get_field_type() {
+            MetricType::GAUGE => {
+                for m in family.get_metric() {
+                    samples.push(sample(name, labels(m.get_label()), m.get_gauge().get_value()));
+                }
+                "gauge"
+            }
+            MetricType::HISTOGRAM => {
+                for m in family.get_metric() {
+                    let h = m.get_histogram();
+                    let base = labels(m.get_label());
+                    let bucket = format!("{name}_bucket");
+                    for b in h.get_bucket() {
+                        let mut with_le = base.clone();
+                        with_le.push(("le".to_string(), b.get_upper_bound().to_string()));
+                        samples.push(sample(&bucket, with_le, b.get_cumulative_count() as f64));
+                    }
+                    let mut inf = base.clone();
+                    inf.push(("le".to_string(), "+Inf".to_string()));
+                    samples.push(sample(&bucket, inf, h.get_sample_count() as f64));
+                    samples.push(sample(&format!("{name}_sum"), base.clone(), h.get_sample_sum()));
+                    samples.push(sample(&format!("{name}_count"), base, h.get_sample_count() as f64));
+                }
+                "histogram"
+            }
+            _ => {
+                for m in family.get_metric() {
+                    samples.push(sample(name, labels(m.get_label()), m.get_counter().get_value()));
+                }
+                "counter"
+            }
+        };
+        Family {
+            name: name.to_string(),
+            help: family.get_help().to_string(),
+            kind: kind.to_string(),
+            samples,
+        }
+    }).collect()
+}
+
+fn labels(pairs: &[prometheus::proto::LabelPair]) -> Vec<(String, String)> {
+    pairs.iter().map(|l| (l.get_name().to_string(), l.get_value().to_string())).collect()
+}
+
+fn sample(name: &str, labels: Vec<(String, String)>, value: f64) -> Sample {
+    Sample { name: name.to_string(), labels, value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn family<'a>(families: &'a [Family], name: &str) -> &'a Family {
+        families.iter().find(|f| f.name == name).unwrap_or_else(|| panic!("{name} not registered"))
     }
-} 
\ No newline at end of file
+
+    fn value(family: &Family, series: &str, labels: &[(&str, &str)]) -> f64 {
+        family.samples.iter()
+            .find(|s| s.name == series && labels.iter().all(|(k, v)| s.labels.iter().any(|(lk, lv)| lk == k && lv == v)))
+            .unwrap_or_else(|| panic!("no {series} sample with {labels:?}"))
+            .value
+    }
+
+    #[test]
+    fn test_counters_and_build_info() {
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry).unwrap();
+        metrics.unauthorized_library("libevil.so", false);
+        metrics.unauthorized_library("libevil.so", true);
+        metrics.authorized_library("libc.so.6");
+        metrics.suppressed_detection("libevil.so", false);
+        metrics.config_load_failures.inc();
+        metrics.permission_warnings.inc();
+        metrics.check_errors.inc();
+
+        let families = convert(&registry.gather());
+        assert_eq!(value(family(&families, "hyper_processor_blocks_total"), "hyper_processor_blocks_total", &[]), 1.0);
+        assert_eq!(value(family(&families, "hyper_processor_audits_total"), "hyper_processor_audits_total", &[]), 1.0);
+        let loads = family(&families, "hyper_processor_library_loads");
+        assert_eq!(value(loads, "hyper_processor_library_loads", &[("library", "libevil.so"), ("status", "unauthorized")]), 2.0);
+        assert_eq!(value(loads, "hyper_processor_library_loads", &[("library", "libc.so"), ("status", "authorized")]), 1.0);
+        let suppressed = family(&families, "hyper_processor_suppressed_detections");
+        assert_eq!(value(suppressed, "hyper_processor_suppressed_detections", &[("action", "block")]), 1.0);
+        for name in ["hyper_processor_config_load_failures_total", "hyper_processor_config_permission_warnings_total", "hyper_processor_check_errors_total"] {
+            assert_eq!(value(family(&families, name), name, &[]), 1.0);
+        }
+
+        let build_info = family(&families, "hyper_processor_build_info");
+        assert_eq!(build_info.kind, "gauge");
+        assert_eq!(value(build_info, "hyper_processor_build_info", &[("version", env!("CARGO_PKG_VERSION"))]), 1.0);
+        assert!(build_info.samples[0].labels.iter().any(|(k, v)| k == "features" && v.split(',').any(|f| f == "metrics")));
+    }
+
+    #[test]
+    fn test_check_histograms_and_gauge() {
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry).unwrap();
+        metrics.check_completed(Duration::from_millis(3), 42);
+        metrics.check_completed(Duration::from_millis(30), 40);
+        metrics.hash_duration.observe(0.0002);
+
+        let families = convert(&registry.gather());
+        let check = family(&families, "hyper_processor_check_duration_seconds");
+        assert_eq!(check.kind, "histogram");
+        assert_eq!(value(check, "hyper_processor_check_duration_seconds_count", &[]), 2.0);
+        assert_eq!(value(check, "hyper_processor_check_duration_seconds_bucket", &[("le", "0.005")]), 1.0);
+        assert_eq!(value(check, "hyper_processor_check_duration_seconds_bucket", &[("le", "+Inf")]), 2.0);
+        assert!((value(check, "hyper_processor_check_duration_seconds_sum", &[]) - 0.033).abs() < 1e-9);
+
+        let hash = family(&families, "hyper_processor_library_hash_duration_seconds");
+        assert_eq!(value(hash, "hyper_processor_library_hash_duration_seconds_bucket", &[("le", "0.0005")]), 1.0);
+
+        // The gauge holds the latest check only
+        let inspected = family(&families, "hyper_processor_libraries_inspected");
+        assert_eq!(value(inspected, "hyper_processor_libraries_inspected", &[]), 40.0);
+    }
+
+    #[test]
+    fn test_library_labels_normalized_and_capped() {
+        assert_eq!(normalize_library("libssl.so.3.0.2"), "libssl.so");
+        assert_eq!(normalize_library("libfoo.so"), "libfoo.so");
+
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry).unwrap();
+        metrics.set_library_label_limit(2);
+        metrics.authorized_library("libc.so.6");
+        metrics.unauthorized_library("lib8f3a1.so", false);
+        // Same library at another version reuses its label
+        metrics.authorized_library("libc.so.7");
+        metrics.unauthorized_library("lib0c9d2.so", false);
+        metrics.suppressed_detection("libe71b4.so", false);
+
+        let families = convert(&registry.gather());
+        let loads = family(&families, "hyper_processor_library_loads");
+        assert_eq!(value(loads, "hyper_processor_library_loads", &[("library", "libc.so"), ("status", "authorized")]), 2.0);
+        assert_eq!(value(loads, "hyper_processor_library_loads", &[("library", "other"), ("status", "unauthorized")]), 1.0);
+        let unauthorized = family(&families, "hyper_processor_unauthorized_loads");
+        assert_eq!(unauthorized.samples.len(), 2);
+        assert_eq!(value(family(&families, "hyper_processor_library_labels_dropped_total"), "hyper_processor_library_labels_dropped_total", &[]), 2.0);
+    }
+
+    #[test]
+    fn test_init_is_idempotent_and_private() {
+        init().unwrap();
+        init().unwrap();
+        let metrics = get().unwrap();
+        metrics.check_errors.inc();
+
+        let ours = convert(&registry().gather());
+        assert!(value(family(&ours, "hyper_processor_check_errors_total"), "hyper_processor_check_errors_total", &[]) >= 1.0);
+        assert!(!prometheus::gather().iter().any(|f| f.get_name().starts_with("hyper_processor_")));
+    }
+
+    #[test]
+    fn test_duplicate_registration_rejected() {
+        let registry = Registry::new();
+        Metrics::new(&registry).unwrap();
+        assert!(Metrics::new(&registry).is_err());
+    }
+}
@@ -0,0 +1,529 @@
+// Module for the datagram protocol between protected processes and `hyper-processor monitor`
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::IoSliceMut;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use nix::sys::socket::{recvmsg, setsockopt, sockopt, ControlMessageOwned, MsgFlags, UnixCredentials};
+use serde::{Deserialize, Serialize};
+use crate::alerting::DetectedLibrary;
+use crate::config::MonitorSettings;
+
+/// Socket `hyper-processor monitor` listens on unless configured otherwise.
+pub const DEFAULT_SOCKET: &str = "/run/hyper_processor/monitor.sock";
+
+// Processes whose state is kept; the least recently updated is dropped beyond this
+const MAX_PROCESSES: usize = 1024;
+// Distinct unauthorized library names remembered per process
+const MAX_LIBRARIES: usize = 32;
+// Labels the monitor attaches to every forwarded sample itself
+const RESERVED_LABELS: [&str; 3] = ["pid", "comm", "profile"];
+// Families the monitor renders from its own state
+const MONITOR_FAMILIES: [&str; 2] = ["hyper_processor_monitor_processes", "hyper_processor_process_last_seen_timestamp_seconds"];
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+/// One series of a metric family, e.g. `hyper_processor_library_loads{library="libc.so",status="authorized"}`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Sample {
+    /// Full series name (histograms use the `_bucket`, `_sum` and `_count` suffixes).
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Family {
+    pub name: String,
+    pub help: String,
+    /// Prometheus type: `counter`, `gauge` or `histogram`.
+    pub kind: String,
+    pub samples: Vec<Sample>,
+}
+
+/// What a protected process sends to the monitor, one JSON object per datagram. The
+/// sender's pid is not part of it: the monitor takes it from the kernel (see `recv_report`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub comm: String,
+    pub profile: String,
+    pub sent_ms: u64,
+    #[serde(flatten)]
+    pub report: Report,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Report {
+    /// The preload check finished with `verdict`: `clean`, `audited`, `blocked` or `error`.
+    Check { verdict: String },
+    /// Unauthorized libraries reported in one detection (after deduplication).
+    Detection { verdict: String, libraries: Vec<DetectedLibrary> },
+    /// The process is still running.
+    Heartbeat,
+    /// Cumulative values of every metric in the sending process.
+    Metrics { families: Vec<Family> },
+}
+
+/// Sends one datagram to the monitor at `socket` without blocking. A missing monitor is an
+/// error the caller is expected to ignore.
+pub fn send(socket: &Path, message: &Message) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    let sender = UnixDatagram::unbound()?;
+    sender.set_nonblocking(true)?;
+    sender.send_to(&body, socket)
+        .with_context(|| format!("Could not reach monitor at {}", socket.display()))?;
+    Ok(())
+}
+
+/// Sender of a datagram as vouched for by the kernel (`SCM_CREDENTIALS`), not as claimed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    pub pid: u32,
+    pub uid: u32,
+}
+
+/// Makes the kernel attach the sender's credentials to every datagram `socket` receives.
+pub fn enable_credentials(socket: &UnixDatagram) -> Result<()> {
+    setsockopt(socket, sockopt::PassCred, &true).context("Could not enable SO_PASSCRED")?;
+    Ok(())
+}
+
+/// Receives one datagram into `buf` with the credentials attached by the kernel. The peer is
+/// None only if `enable_credentials` was not called on `socket`.
+pub fn recv_report(socket: &UnixDatagram, buf: &mut [u8]) -> std::io::Result<(usize, Option<Peer>)> {
+    let mut iov = [IoSliceMut::new(buf)];
+    let mut space = nix::cmsg_space!(UnixCredentials);
+    let received = recvmsg::<()>(socket.as_raw_fd(), &mut iov, Some(&mut space), MsgFlags::empty())?;
+    let peer = received.cmsgs().find_map(|cmsg| match cmsg {
+        ControlMessageOwned::ScmCredentials(credentials) => Some(Peer {
+            pid: credentials.pid() as u32,
+            uid: credentials.uid(),
+        }),
+        _ => None,
+    });
+    Ok((received.bytes, peer))
+}
+
+/// Identity this process reports under.
+struct Reporter {
+    socket: PathBuf,
+    comm: String,
+    profile: String,
+}
+
+/// Enables `report` for this process. Does nothing when `monitor.socket` is unset.
+pub fn init(settings: &MonitorSettings, comm: &str) {
+    let Some(socket) = settings.socket.as_deref() else {
+        return;
+    };
+    let _ = REPORTER.set(Reporter {
+        socket: PathBuf::from(socket),
+        comm: comm.to_string(),
+        profile: settings.profile.clone().unwrap_or_else(|| String::from("default")),
+    });
+}
+
+/// Sends `report` to the monitor if `init` enabled reporting. Failures are only debug-logged:
+/// most hosts run without a monitor.
+pub fn report(report: Report) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    let message = Message {
+        comm: reporter.comm.clone(),
+        profile: reporter.profile.clone(),
+        sent_ms: crate::formats::now_ms(),
+        report,
+    };
+    if let Err(e) = send(&reporter.socket, &message) {
+        tracing::debug!(error = %e, "Report not delivered to monitor");
+    }
+}
+
+/// Sends a heartbeat (and, with the `metrics` feature, fresh counters) every `interval`
+/// from a background thread.
+pub fn start_heartbeat(interval: Duration) {
+    if REPORTER.get().is_none() || interval.is_zero() {
+        return;
+    }
+    let spawned = thread::Builder::new()
+        .name("hyper-rasp-heartbeat".into())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            report(Report::Heartbeat);
+            #[cfg(feature = "metrics")]
+            report(Report::Metrics { families: crate::metrics::families() });
+        });
+    if let Err(e) = spawned {
+        tracing::debug!(error = %e, "Heartbeat thread not started");
+    }
+}
+
+struct ProcessState {
+    comm: String,
+    profile: String,
+    last_seen_ms: u64,
+    last_check_ms: Option<u64>,
+    verdict: Option<String>,
+    detections: u64,
+    libraries: Vec<String>,
+    families: Vec<Family>,
+    updated: Instant,
+}
+
+impl ProcessState {
+    fn new(comm: &str) -> Self {
+        ProcessState {
+            comm: comm.to_string(),
+            profile: String::new(),
+            last_seen_ms: 0,
+            last_check_ms: None,
+            verdict: None,
+            detections: 0,
+            libraries: Vec::new(),
+            families: Vec::new(),
+            updated: Instant::now(),
+        }
+    }
+}
+
+/// One process as shown by `/status` and `hyper-processor status`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProcessStatus {
+    pub pid: u32,
+    pub comm: String,
+    pub profile: String,
+    /// Verdict of the last check; None until one is reported.
+    pub verdict: Option<String>,
+    pub last_check_ms: Option<u64>,
+    pub last_seen_ms: u64,
+    pub detections: u64,
+    pub unauthorized_libraries: Vec<String>,
+    /// Whether `/proc/<pid>` still exists on the monitor's host.
+    pub running: bool,
+}
+
+/// Body of the monitor's `/status` endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StatusReport {
+    pub processes: Vec<ProcessStatus>,
+}
+
+/// Latest state of every process that reported. Processes are kept after they exit so
+/// blocked launches stay visible.
+#[derive(Default)]
+pub struct Collector {
+    processes: HashMap<u32, ProcessState>,
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Collector::default()
+    }
+
+    /// Applies one datagram received from process `pid`, which must come from the kernel
+    /// (`recv_report`), never from the datagram itself. Malformed input is rejected without
+    /// touching existing state.
+    pub fn ingest(&mut self, pid: u32, datagram: &[u8]) -> Result<()> {
+        let message: Message = serde_json::from_slice(datagram).context("Malformed monitor message")?;
+        if let Report::Metrics { families } = &message.report {
+            validate_families(families)?;
+        }
+        if self.processes.len() >= MAX_PROCESSES && !self.processes.contains_key(&pid) {
+            let oldest = self.processes.iter().min_by_key(|(_, p)| p.updated).map(|(pid, _)| *pid);
+            if let Some(oldest) = oldest {
+                self.processes.remove(&oldest);
+            }
+        }
+
+        let state = self.processes.entry(pid).or_insert_with(|| ProcessState::new(&message.comm));
+        if state.comm != message.comm {
+            // The pid was reused by another program
+            *state = ProcessState::new(&message.comm);
+        }
+        state.profile = message.profile;
+        state.last_seen_ms = state.last_seen_ms.max(message.sent_ms);
+        state.updated = Instant::now();
+        match message.report {
+            Report::Check { verdict } => {
+                state.last_check_ms = Some(message.sent_ms);
+                state.verdict = Some(verdict);
+            }
+            Report::Detection { verdict, libraries } => {
+                state.detections += 1;
+                state.verdict = Some(verdict);
+                for library in libraries {
+                    if state.libraries.len() < MAX_LIBRARIES && !state.libraries.contains(&library.filename) {
+                        state.libraries.push(library.filename);
+                    }
+                }
+            }
+            Report::Heartbeat => {}
+            Report::Metrics { families } => state.families = families,
+        }
+        Ok(())
+    }
+
+    pub fn process_count(&self) -> usize {
+        self.processes.len()
+    }
+
+    /// Every known process, by pid.
+    pub fn status(&self) -> StatusReport {
+        let mut status: Vec<ProcessStatus> = self.processes.iter().map(|(pid, state)| ProcessStatus {
+            pid: *pid,
+            comm: state.comm.clone(),
+            profile: state.profile.clone(),
+            verdict: state.verdict.clone(),
+            last_check_ms: state.last_check_ms,
+            last_seen_ms: state.last_seen_ms,
+            detections: state.detections,
+            unauthorized_libraries: state.libraries.clone(),
+            running: Path::new(&format!("/proc/{pid}")).exists(),
+        }).collect();
+        status.sort_by_key(|s| s.pid);
+        StatusReport { processes: status }
+    }
+
+    /// Prometheus text exposition: every process's own metrics labelled with `pid`, `comm`
+    /// and `profile`, then the monitor's per-process state.
+    pub fn render(&self) -> String {
+        // name -> (help, kind, lines), in first-seen order so output is stable
+        let mut order: Vec<&str> = Vec::new();
+        let mut families: HashMap<&str, (&str, &str, Vec<String>)> = HashMap::new();
+        let mut pids: Vec<&u32> = self.processes.keys().collect();
+        pids.sort();
+        for pid in &pids {
+            let process = &self.processes[*pid];
+            let pid_label = pid.to_string();
+            let base = [("pid", pid_label.as_str()), ("comm", process.comm.as_str()), ("profile", process.profile.as_str())];
+            for family in &process.families {
+                let entry = families.entry(family.name.as_str()).or_insert_with(|| {
+                    order.push(family.name.as_str());
+                    (family.help.as_str(), family.kind.as_str(), Vec::new())
+                });
+                for sample in &family.samples {
+                    let labels = label_set(base.iter().copied()
+                        .chain(sample.labels.iter().map(|(k, v)| (k.as_str(), v.as_str()))));
+                    entry.2.push(format!("{}{{{}}} {}", sample.name, labels, sample.value));
+                }
+            }
+        }
+        let mut out = String::new();
+        for name in order {
+            let (help, kind, lines) = &families[name];
+            write_header(&mut out, name, help, kind);
+            for line in lines {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+
+        let mut by_verdict: Vec<(&str, usize)> = Vec::new();
+        for process in self.processes.values() {
+            let verdict = process.verdict.as_deref().unwrap_or("unknown");
+            match by_verdict.iter_mut().find(|(v, _)| *v == verdict) {
+                Some(entry) => entry.1 += 1,
+                None => by_verdict.push((verdict, 1)),
+            }
+        }
+        by_verdict.sort();
+        write_header(&mut out, "hyper_processor_monitor_processes", "Processes known to the monitor, by verdict of their last check", "gauge");
+        for (verdict, count) in by_verdict {
+            let _ = writeln!(out, "hyper_processor_monitor_processes{{{}}} {}", label_set([("verdict", verdict)]), count);
+        }
+        write_header(&mut out, "hyper_processor_process_last_seen_timestamp_seconds", "Time of the last report from each process", "gauge");
+        for pid in &pids {
+            let process = &self.processes[*pid];
+            let pid_label = pid.to_string();
+            let labels = label_set([
+                ("pid", pid_label.as_str()),
+                ("comm", process.comm.as_str()),
+                ("profile", process.profile.as_str()),
+                ("verdict", process.verdict.as_deref().unwrap_or("unknown")),
+            ]);
+            let _ = writeln!(out, "hyper_processor_process_last_seen_timestamp_seconds{{{}}} {}", labels, process.last_seen_ms as f64 / 1000.0);
+        }
+        out
+    }
+}
+
+/// Rejects metric families that would not render as valid exposition: names and label keys
+/// are written verbatim, so they must be well-formed and must not clash with the monitor's own.
+fn validate_families(families: &[Family]) -> Result<()> {
+    for family in families {
+        if !is_metric_name(&family.name) || MONITOR_FAMILIES.contains(&family.name.as_str()) {
+            anyhow::bail!("Invalid metric name {:?}", family.name);
+        }
+        if !matches!(family.kind.as_str(), "counter" | "gauge" | "histogram") {
+            anyhow::bail!("Invalid type {:?} for metric {}", family.kind, family.name);
+        }
+        for sample in &family.samples {
+            if !is_metric_name(&sample.name) || !sample.name.starts_with(family.name.as_str()) {
+                anyhow::bail!("Invalid series name {:?} in metric {}", sample.name, family.name);
+            }
+            for (key, _) in &sample.labels {
+                if !is_label_name(key) || RESERVED_LABELS.contains(&key.as_str()) {
+                    anyhow::bail!("Invalid label {:?} in metric {}", key, family.name);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `[a-zA-Z_:][a-zA-Z0-9_:]*`
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// `[a-zA-Z_][a-zA-Z0-9_]*`
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help.replace('\\', "\\\\").replace('\n', "\\n"));
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn label_set<'a>(labels: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    labels.into_iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn message(sent_ms: u64, report: Report) -> Vec<u8> {
+        serde_json::to_vec(&Message { comm: "nginx".into(), profile: "web".into(), sent_ms, report }).unwrap()
+    }
+
+    fn metrics(library: &str, value: f64) -> Report {
+        Report::Metrics {
+            families: vec![Family {
+                name: "hyper_processor_unauthorized_loads".into(),
+                help: "Unauthorized library load attempts".into(),
+                kind: "counter".into(),
+                samples: vec![Sample {
+                    name: "hyper_processor_unauthorized_loads".into(),
+                    labels: vec![("library".into(), library.into()), ("action".into(), "block".into())],
+                    value,
+                }],
+            }],
+        }
+    }
+
+    fn detection(library: &str) -> Report {
+        Report::Detection {
+            verdict: "audited".into(),
+            libraries: vec![DetectedLibrary { filename: library.into(), path: format!("/tmp/{library}"), size: 1, sha256: "00".into() }],
+        }
+    }
+
+    #[test]
+    fn test_render_labels_each_process() {
+        let mut collector = Collector::new();
+        collector.ingest(20, &message(1_000, metrics("libevil.so", 1.0))).unwrap();
+        collector.ingest(10, &message(2_000, metrics("lib\"odd\".so", 2.0))).unwrap();
+        // A later report from the same pid replaces the earlier one
+        collector.ingest(20, &message(3_000, metrics("libevil.so", 3.0))).unwrap();
+
+        let rendered = collector.render();
+        assert!(rendered.starts_with("\
+# HELP hyper_processor_unauthorized_loads Unauthorized library load attempts
+# TYPE hyper_processor_unauthorized_loads counter
+hyper_processor_unauthorized_loads{pid=\"10\",comm=\"nginx\",profile=\"web\",library=\"lib\\\"odd\\\".so\",action=\"block\"} 2
+hyper_processor_unauthorized_loads{pid=\"20\",comm=\"nginx\",profile=\"web\",library=\"libevil.so\",action=\"block\"} 3
+"));
+        assert!(rendered.contains("hyper_processor_monitor_processes{verdict=\"unknown\"} 2\n"));
+        assert!(rendered.contains("hyper_processor_process_last_seen_timestamp_seconds{pid=\"20\",comm=\"nginx\",profile=\"web\",verdict=\"unknown\"} 3\n"));
+    }
+
+    #[test]
+    fn test_process_state_from_reports() {
+        let mut collector = Collector::new();
+        collector.ingest(42, &message(1_000, detection("libevil.so"))).unwrap();
+        collector.ingest(42, &message(1_001, detection("libevil.so"))).unwrap();
+        collector.ingest(42, &message(1_002, Report::Check { verdict: "audited".into() })).unwrap();
+        collector.ingest(42, &message(9_000, Report::Heartbeat)).unwrap();
+
+        let status = collector.status().processes;
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].verdict.as_deref(), Some("audited"));
+        assert_eq!(status[0].last_check_ms, Some(1_002));
+        assert_eq!(status[0].last_seen_ms, 9_000);
+        assert_eq!(status[0].detections, 2);
+        assert_eq!(status[0].unauthorized_libraries, ["libevil.so"]);
+        assert!(collector.render().contains("hyper_processor_monitor_processes{verdict=\"audited\"} 1\n"));
+    }
+
+    #[test]
+    fn test_malformed_datagram_rejected() {
+        let mut collector = Collector::new();
+        assert!(collector.ingest(1, b"{\"type\":\"metrics\"").is_err());
+        assert!(collector.ingest(1, b"{\"comm\":\"a\",\"profile\":\"b\",\"sent_ms\":0,\"type\":\"unknown\"}").is_err());
+        assert_eq!(collector.process_count(), 0);
+    }
+
+    #[test]
+    fn test_untrusted_metric_names_rejected() {
+        let with = |edit: fn(&mut Family)| {
+            let Report::Metrics { mut families } = metrics("libevil.so", 1.0) else { unreachable!() };
+            edit(&mut families[0]);
+            message(1_000, Report::Metrics { families })
+        };
+        let mut collector = Collector::new();
+        assert!(collector.ingest(5, &with(|f| f.name = "x 1\nfake_metric".into())).is_err());
+        assert!(collector.ingest(5, &with(|f| f.name = "hyper_processor_monitor_processes".into())).is_err());
+        assert!(collector.ingest(5, &with(|f| f.kind = "counter\n# TYPE x gauge".into())).is_err());
+        assert!(collector.ingest(5, &with(|f| f.samples[0].name = "other_metric".into())).is_err());
+        assert!(collector.ingest(5, &with(|f| f.samples[0].labels[0].0 = "lib-rary".into())).is_err());
+        assert!(collector.ingest(5, &with(|f| f.samples[0].labels[0].0 = "pid".into())).is_err());
+        assert_eq!(collector.process_count(), 0);
+
+        collector.ingest(5, &with(|f| f.samples[0].name = "hyper_processor_unauthorized_loads_total".into())).unwrap();
+        assert_eq!(collector.process_count(), 1);
+    }
+
+    #[test]
+    fn test_send_over_socket() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("monitor.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+        enable_credentials(&server).unwrap();
+        let heartbeat = Message { comm: "app".into(), profile: "default".into(), sent_ms: 5, report: Report::Heartbeat };
+        send(&path, &heartbeat).unwrap();
+
+        let mut buf = vec![0u8; 65536];
+        let (n, peer) = recv_report(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"{\"comm\":\"app\",\"profile\":\"default\",\"sent_ms\":5,\"type\":\"heartbeat\"}");
+        let peer = peer.expect("kernel credentials");
+        assert_eq!(peer.pid, std::process::id());
+        assert_eq!(peer.uid, nix::unistd::getuid().as_raw());
+        let mut collector = Collector::new();
+        collector.ingest(peer.pid, &buf[..n]).unwrap();
+        assert_eq!(collector.status().processes[0].pid, std::process::id());
+
+        assert!(send(&dir.path().join("absent.sock"), &heartbeat).is_err());
+    }
+}
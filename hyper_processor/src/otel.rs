@@ -0,0 +1,575 @@
+// Module for exporting spans, events and detection counters over OTLP/HTTP (JSON encoding)
+//
+// Deliberately free of an async runtime: a single background thread batches records and
+// POSTs them with the same bounded-time client used for webhooks.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde_json::{json, Value};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use crate::config::OtelSettings;
+use crate::formats::FieldValues;
+
+const SCOPE_NAME: &str = "hyper_processor";
+// Records buffered before the exporter thread starts dropping them
+const QUEUE_CAPACITY: usize = 4096;
+const MAX_BATCH: usize = 512;
+
+static EXPORTER: OnceLock<ExporterHandle> = OnceLock::new();
+// Spans of the installed layer, so `shutdown` can export ones that never closed
+static OPEN_SPANS: OnceLock<OpenSpans> = OnceLock::new();
+static COUNTERS: Mutex<Counters> = Mutex::new(Counters::new());
+
+enum Item {
+    Span(Value),
+    Log(Value),
+    /// Export everything queued so far, then acknowledge.
+    Flush(SyncSender<()>),
+}
+
+/// Sending side of a running exporter thread.
+#[derive(Clone)]
+pub struct ExporterHandle {
+    sender: SyncSender<Item>,
+}
+
+impl ExporterHandle {
+    fn send(&self, item: Item) {
+        // Never block the instrumented process: drop when the collector can't keep up
+        let _ = self.sender.try_send(item);
+    }
+
+    /// Exports everything queued so far, waiting at most `timeout`.
+    pub fn flush(&self, timeout: Duration) {
+        let (ack, done) = mpsc::sync_channel(1);
+        if self.sender.try_send(Item::Flush(ack)).is_ok() {
+            let _ = done.recv_timeout(timeout);
+        }
+    }
+}
+
+struct Series {
+    name: &'static str,
+    description: &'static str,
+    labels: Vec<(&'static str, String)>,
+    value: u64,
+}
+
+/// Per-series counter values mirrored from `metrics.rs`.
+struct Counters {
+    start_ns: u64,
+    values: Vec<Series>,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters { start_ns: 0, values: Vec::new() }
+    }
+
+    fn add(&mut self, name: &'static str, description: &'static str, labels: Vec<(&'static str, String)>) {
+        if self.start_ns == 0 {
+            self.start_ns = now_ns();
+        }
+        match self.values.iter_mut().find(|s| s.name == name && s.labels == labels) {
+            Some(series) => series.value += 1,
+            None => self.values.push(Series { name, description, labels, value: 1 }),
+        }
+    }
+}
+
+fn count(name: &'static str, description: &'static str, labels: Vec<(&'static str, String)>) {
+    if let Ok(mut counters) = COUNTERS.lock() {
+        counters.add(name, description, labels);
+    }
+}
+
+/// The capped `library` label shared with the Prometheus exporter.
+fn library_label(library_name: &str) -> String {
+    crate::library_labels::shared().admit(library_name)
+        .unwrap_or_else(|| crate::library_labels::OTHER_LIBRARY.to_string())
+}
+
+// Same series, and the same capped `library` values, as the Prometheus exporter in metrics.rs
+pub fn record_unauthorized_library(library_name: &str, audit_mode: bool) {
+    let library = library_label(library_name);
+    let action = if audit_mode { "audit" } else { "block" };
+    if audit_mode {
+        count("hyper_processor_audits_total", "Total number of audited library loads", Vec::new());
+    } else {
+        count("hyper_processor_blocks_total", "Total number of blocked library loads", Vec::new());
+    }
+    count("hyper_processor_unauthorized_loads", "Unauthorized library load attempts",
+        vec![("library", library.clone()), ("action", action.to_string())]);
+    count("hyper_processor_library_loads", "Total library load attempts",
+        vec![("library", library), ("status", "unauthorized".to_string())]);
+}
+
+pub fn record_authorized_library(library_name: &str) {
+    let library = library_label(library_name);
+    count("hyper_processor_library_loads", "Total library load attempts",
+        vec![("library", library), ("status", "authorized".to_string())]);
+}
+
+pub fn record_suppressed_detection(library_name: &str, audit_mode: bool) {
+    let library = library_label(library_name);
+    let action = if audit_mode { "audit" } else { "block" };
+    count("hyper_processor_suppressed_detections", "Repeated detections suppressed by deduplication",
+        vec![("library", library), ("action", action.to_string())]);
+}
+
+fn now_ns() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    let filled = unsafe { libc::getrandom(buf.as_mut_ptr().cast(), bytes, 0) };
+    if filled != bytes as isize || buf.iter().all(|&b| b == 0) {
+        // Fall back to something unique enough for correlation
+        let seed = now_ns() ^ (u64::from(std::process::id()) << 32);
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = (seed.rotate_left(i as u32 * 8) as u8) | 1;
+        }
+    }
+    buf.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n.as_f64() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+fn attributes<'a>(fields: impl IntoIterator<Item = (&'a str, &'a Value)>) -> Vec<Value> {
+    fields.into_iter()
+        .map(|(key, value)| json!({ "key": key, "value": any_value(value) }))
+        .collect()
+}
+
+fn severity_number(level: &Level) -> u8 {
+    match *level {
+        Level::TRACE => 1,
+        Level::DEBUG => 5,
+        Level::INFO => 9,
+        Level::WARN => 13,
+        Level::ERROR => 17,
+    }
+}
+
+struct OpenSpan {
+    name: &'static str,
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    start_ns: u64,
+    fields: FieldValues,
+}
+
+impl OpenSpan {
+    fn to_otlp(&self, end_ns: u64) -> Value {
+        let mut span = json!({
+            "traceId": self.trace_id,
+            "spanId": self.span_id,
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": self.start_ns.to_string(),
+            "endTimeUnixNano": end_ns.to_string(),
+            "attributes": attributes(self.fields.0.iter().map(|(k, v)| (k.as_str(), v))),
+        });
+        if let Some(parent) = &self.parent_span_id {
+            span["parentSpanId"] = json!(parent);
+        }
+        span
+    }
+}
+
+type OpenSpans = Arc<Mutex<HashMap<u64, OpenSpan>>>;
+
+/// Exports spans that are still open (e.g. the root span when the process is terminating).
+fn export_open_spans(open: &OpenSpans, exporter: &ExporterHandle) {
+    let end_ns = now_ns();
+    if let Ok(open) = open.lock() {
+        for span in open.values() {
+            exporter.send(Item::Span(span.to_otlp(end_ns)));
+        }
+    }
+}
+
+/// Turns spans and events into OTLP records for the exporter thread.
+pub struct OtelLayer {
+    exporter: ExporterHandle,
+    open: OpenSpans,
+}
+
+impl OtelLayer {
+    pub fn new(exporter: ExporterHandle) -> Self {
+        OtelLayer { exporter, open: Arc::default() }
+    }
+}
+
+impl<S> Layer<S> for OtelLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let parent_id = match attrs.parent() {
+            Some(parent) => Some(parent.clone()),
+            None if attrs.is_contextual() => ctx.current_span().id().cloned(),
+            None => None,
+        };
+        let Ok(mut open) = self.open.lock() else {
+            return;
+        };
+        let parent = parent_id.and_then(|p| open.get(&p.into_u64()).map(|s| (s.trace_id.clone(), s.span_id.clone())));
+        let mut fields = FieldValues::default();
+        attrs.record(&mut fields);
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (random_hex(16), None),
+        };
+        open.insert(id.into_u64(), OpenSpan {
+            name: attrs.metadata().name(),
+            trace_id,
+            span_id: random_hex(8),
+            parent_span_id,
+            start_ns: now_ns(),
+            fields,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Ok(mut open) = self.open.lock() {
+            if let Some(span) = open.get_mut(&id.into_u64()) {
+                values.record(&mut span.fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut fields = FieldValues::default();
+        event.record(&mut fields);
+        let message = fields.0.iter()
+            .position(|(name, _)| name == "message")
+            .map(|i| fields.0.remove(i).1)
+            .unwrap_or(Value::String(String::new()));
+
+        let now = now_ns().to_string();
+        let mut record = json!({
+            "timeUnixNano": now,
+            "observedTimeUnixNano": now,
+            "severityNumber": severity_number(meta.level()),
+            "severityText": meta.level().to_string(),
+            "body": any_value(&message),
+            "attributes": attributes(
+                fields.0.iter().map(|(k, v)| (k.as_str(), v))
+                    .chain([("target", &Value::String(meta.target().to_string()))])
+            ),
+        });
+        if let Some(span) = ctx.event_span(event) {
+            if let Ok(open) = self.open.lock() {
+                if let Some(open_span) = open.get(&span.id().into_u64()) {
+                    record["traceId"] = json!(open_span.trace_id);
+                    record["spanId"] = json!(open_span.span_id);
+                }
+            }
+        }
+        self.exporter.send(Item::Log(record));
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        let closed = self.open.lock().ok().and_then(|mut open| open.remove(&id.into_u64()));
+        if let Some(span) = closed {
+            self.exporter.send(Item::Span(span.to_otlp(now_ns())));
+        }
+    }
+}
+
+struct Exporter {
+    endpoint: String,
+    timeout: Duration,
+    resource: Value,
+    spans: Vec<Value>,
+    logs: Vec<Value>,
+}
+
+impl Exporter {
+    fn scope() -> Value {
+        json!({ "name": SCOPE_NAME, "version": env!("CARGO_PKG_VERSION") })
+    }
+
+    fn post(&self, path: &str, body: &Value) {
+        let url = format!("{}{}", self.endpoint.trim_end_matches('/'), path);
+        // Export failures are not logged: that would feed straight back into this exporter
+        let _ = crate::http::post(&url, body.to_string().as_bytes(), self.timeout);
+    }
+
+    fn export(&mut self) {
+        if !self.spans.is_empty() {
+            let body = json!({ "resourceSpans": [{
+                "resource": self.resource,
+                "scopeSpans": [{ "scope": Self::scope(), "spans": std::mem::take(&mut self.spans) }],
+            }]});
+            self.post("/v1/traces", &body);
+        }
+        if !self.logs.is_empty() {
+            let body = json!({ "resourceLogs": [{
+                "resource": self.resource,
+                "scopeLogs": [{ "scope": Self::scope(), "logRecords": std::mem::take(&mut self.logs) }],
+            }]});
+            self.post("/v1/logs", &body);
+        }
+        if let Some(body) = self.metrics_body() {
+            self.post("/v1/metrics", &body);
+        }
+    }
+
+    /// Cumulative monotonic sums, one metric per counter name.
+    fn metrics_body(&self) -> Option<Value> {
+        let counters = COUNTERS.lock().ok()?;
+        if counters.values.is_empty() {
+            return None;
+        }
+        let now = now_ns().to_string();
+        let start = counters.start_ns.to_string();
+        let mut metrics: Vec<Value> = Vec::new();
+        for Series { name, description, labels, value } in &counters.values {
+            let point = json!({
+                "attributes": labels.iter()
+                    .map(|(k, v)| json!({ "key": k, "value": { "stringValue": v } }))
+                    .collect::<Vec<_>>(),
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+                "asInt": value.to_string(),
+            });
+            match metrics.iter_mut().find(|m| m["name"] == *name) {
+                Some(metric) => metric["sum"]["dataPoints"].as_array_mut()?.push(point),
+                None => metrics.push(json!({
+                    "name": name,
+                    "description": description,
+                    "sum": { "dataPoints": [point], "aggregationTemporality": 2, "isMonotonic": true },
+                })),
+            }
+        }
+        Some(json!({ "resourceMetrics": [{
+            "resource": self.resource,
+            "scopeMetrics": [{ "scope": Self::scope(), "metrics": metrics }],
+        }]}))
+    }
+
+    fn run(mut self, queue: Receiver<Item>, interval: Duration) {
+        let mut next_export = Instant::now() + interval;
+        loop {
+            let wait = next_export.saturating_duration_since(Instant::now());
+            match queue.recv_timeout(wait) {
+                Ok(Item::Span(span)) => self.spans.push(span),
+                Ok(Item::Log(log)) => self.logs.push(log),
+                Ok(Item::Flush(ack)) => {
+                    while let Ok(item) = queue.try_recv() {
+                        match item {
+                            Item::Span(span) => self.spans.push(span),
+                            Item::Log(log) => self.logs.push(log),
+                            Item::Flush(other) => { let _ = other.send(()); }
+                        }
+                    }
+                    self.export();
+                    let _ = ack.send(());
+                    next_export = Instant::now() + interval;
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.export();
+                    return;
+                }
+            }
+            if Instant::now() >= next_export || self.spans.len() + self.logs.len() >= MAX_BATCH {
+                self.export();
+                next_export = Instant::now() + interval;
+            }
+        }
+    }
+}
+
+/// Starts an exporter thread for `settings.endpoint` with the given resource attributes.
+pub fn start(settings: &OtelSettings, endpoint: &str, resource: &[(&str, Value)]) -> std::io::Result<ExporterHandle> {
+    let (sender, queue) = mpsc::sync_channel(QUEUE_CAPACITY);
+    let mut resource_attributes = vec![
+        ("service.name", Value::from(settings.service_name.as_str())),
+        ("service.version", Value::from(env!("CARGO_PKG_VERSION"))),
+    ];
+    resource_attributes.extend(resource.iter().cloned());
+    let exporter = Exporter {
+        endpoint: endpoint.to_string(),
+        timeout: Duration::from_millis(settings.timeout_ms.max(1)),
+        resource: json!({ "attributes": attributes(resource_attributes.iter().map(|(k, v)| (*k, v))) }),
+        spans: Vec::new(),
+        logs: Vec::new(),
+    };
+    let interval = Duration::from_millis(settings.export_interval_ms.max(10));
+    thread::Builder::new()
+        .name("hyper-rasp-otel".into())
+        .spawn(move || exporter.run(queue, interval))?;
+    Ok(ExporterHandle { sender })
+}
+
+/// The layer for `init_library`, or None when no endpoint is configured.
+pub fn layer(settings: &OtelSettings, resource: &[(&str, Value)]) -> Option<OtelLayer> {
+    let endpoint = settings.endpoint.as_deref()?;
+    match start(settings, endpoint, resource) {
+        Ok(handle) => {
+            let layer = OtelLayer::new(handle.clone());
+            let _ = EXPORTER.set(handle);
+            let _ = OPEN_SPANS.set(Arc::clone(&layer.open));
+            Some(layer)
+        }
+        Err(e) => {
+            eprintln!("[HYPER_RASP PRE-LOGGING ERROR] Could not start OTLP exporter: {}", e);
+            None
+        }
+    }
+}
+
+/// Exports open spans and everything queued, waiting at most `timeout`. Call before `exit`.
+pub fn shutdown(timeout: Duration) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    if let Some(open) = OPEN_SPANS.get() {
+        export_open_spans(open, exporter);
+    }
+    exporter.flush(timeout);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Local collector stand-in: answers every POST with 200 and reports (path, body).
+    fn collector_stub() -> (String, Receiver<(String, Value)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let mut conn = conn.unwrap();
+                let mut request = Vec::new();
+                let mut chunk = [0u8; 8192];
+                let (path, body) = loop {
+                    let n = conn.read(&mut chunk).unwrap();
+                    request.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length: usize = head.lines()
+                            .find_map(|l| l.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        if body.len() >= length {
+                            let path = head.split_whitespace().nth(1).unwrap().to_string();
+                            break (path, serde_json::from_str(body).unwrap());
+                        }
+                    }
+                };
+                conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+                if tx.send((path, body)).is_err() {
+                    return;
+                }
+            }
+        });
+        (endpoint, rx)
+    }
+
+    fn settings() -> OtelSettings {
+        OtelSettings { export_interval_ms: 60_000, ..OtelSettings::default() }
+    }
+
+    fn received(rx: &Receiver<(String, Value)>) -> HashMap<String, Value> {
+        let mut out = HashMap::new();
+        while let Ok((path, body)) = rx.recv_timeout(Duration::from_millis(500)) {
+            out.insert(path, body);
+        }
+        out
+    }
+
+    #[test]
+    fn test_spans_and_logs_exported() {
+        let (endpoint, rx) = collector_stub();
+        let handle = start(&settings(), &endpoint, &[("process.pid", Value::from(42))]).unwrap();
+        let subscriber = tracing_subscriber::registry().with(OtelLayer::new(handle.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("hyper_rasp_init", pid = 42u64, process_name = "nginx");
+            let _enter = root.enter();
+            tracing::error!(unauthorized_library_filename = "libevil.so", alert_type = "SECURITY", "Unauthorized library detected");
+        });
+        handle.flush(Duration::from_secs(5));
+
+        let bodies = received(&rx);
+        let span = &bodies["/v1/traces"]["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["name"], "hyper_rasp_init");
+        assert!(span["attributes"].as_array().unwrap().contains(&json!({ "key": "process_name", "value": { "stringValue": "nginx" } })));
+
+        let resource = &bodies["/v1/logs"]["resourceLogs"][0]["resource"]["attributes"];
+        assert!(resource.as_array().unwrap().contains(&json!({ "key": "process.pid", "value": { "intValue": "42" } })));
+        let log = &bodies["/v1/logs"]["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(log["severityNumber"], 17);
+        assert_eq!(log["body"]["stringValue"], "Unauthorized library detected");
+        assert_eq!(log["traceId"], span["traceId"]);
+        assert_eq!(log["spanId"], span["spanId"]);
+        assert!(log["attributes"].as_array().unwrap().contains(&json!({ "key": "alert_type", "value": { "stringValue": "SECURITY" } })));
+    }
+
+    #[test]
+    fn test_counters_exported_as_sums() {
+        let (endpoint, rx) = collector_stub();
+        let handle = start(&settings(), &endpoint, &[]).unwrap();
+        record_unauthorized_library("libotel_test.so", false);
+        record_unauthorized_library("libotel_test.so", false);
+        handle.flush(Duration::from_secs(5));
+
+        let bodies = received(&rx);
+        let metrics = bodies["/v1/metrics"]["resourceMetrics"][0]["scopeMetrics"][0]["metrics"].as_array().unwrap().clone();
+        let unauthorized = metrics.iter().find(|m| m["name"] == "hyper_processor_unauthorized_loads").unwrap();
+        assert_eq!(unauthorized["sum"]["isMonotonic"], true);
+        let point = unauthorized["sum"]["dataPoints"].as_array().unwrap().iter()
+            .find(|p| p["attributes"][0]["value"]["stringValue"] == "libotel_test.so")
+            .unwrap();
+        assert_eq!(point["asInt"], "2");
+        assert_eq!(point["attributes"][1], json!({ "key": "action", "value": { "stringValue": "block" } }));
+    }
+
+    #[test]
+    fn test_child_span_shares_trace() {
+        let (endpoint, rx) = collector_stub();
+        let handle = start(&settings(), &endpoint, &[]).unwrap();
+        let subscriber = tracing_subscriber::registry().with(OtelLayer::new(handle.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root = root.enter();
+            let child = tracing::info_span!("child");
+            let _child = child.enter();
+        });
+        handle.flush(Duration::from_secs(5));
+
+        let bodies = received(&rx);
+        let spans = bodies["/v1/traces"]["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap().clone();
+        let child = spans.iter().find(|s| s["name"] == "child").unwrap();
+        let root = spans.iter().find(|s| s["name"] == "root").unwrap();
+        assert_eq!(child["traceId"], root["traceId"]);
+        assert_eq!(child["parentSpanId"], root["spanId"]);
+        assert!(root.get("parentSpanId").is_none());
+    }
+}
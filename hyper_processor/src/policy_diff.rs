@@ -0,0 +1,279 @@
+// Module comparing a deployed policy with a relearned one, for `hyper-processor diff`
+
+use std::path::Path;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::config::Settings;
+use crate::preload_check::is_default_whitelisted;
+
+/// One library a policy (or learning session) allows.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Allowed {
+    /// File name, as matched by `whitelisted_filenames`.
+    pub name: String,
+    /// Set for path-based entries; name-only entries match the file anywhere.
+    pub path: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl Allowed {
+    fn by_name(name: &str) -> Self {
+        Allowed { name: name.to_string(), path: None, sha256: None }
+    }
+
+    fn by_path(path: &str, sha256: Option<&str>) -> Self {
+        Allowed {
+            name: Path::new(path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            path: Some(path.to_string()),
+            sha256: sha256.filter(|h| !h.is_empty() && *h != "<error>").map(str::to_ascii_lowercase),
+        }
+    }
+
+    /// Path if known, else the file name.
+    pub fn label(&self) -> &str {
+        self.path.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Same library at the same path with different content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HashChange {
+    pub path: String,
+    pub old_sha256: String,
+    pub new_sha256: String,
+}
+
+/// A library replaced by another version of itself (same name stem, same directory).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionBump {
+    pub old: Allowed,
+    pub new: Allowed,
+}
+
+/// What changed between two policies.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Drift {
+    /// New dependencies: nothing in the old policy explains them.
+    pub added: Vec<Allowed>,
+    pub version_bumps: Vec<VersionBump>,
+    pub hash_changes: Vec<HashChange>,
+    pub removed: Vec<Allowed>,
+}
+
+impl Drift {
+    /// Whether the new side needs libraries the old policy would not have allowed.
+    pub fn has_unexpected_additions(&self) -> bool {
+        !self.added.is_empty()
+    }
+
+    /// Whether this drift should stop a deployment: new dependencies always do, changed
+    /// content of a pinned library unless `allow_hash_changes`.
+    pub fn fails_gate(&self, allow_hash_changes: bool) -> bool {
+        self.has_unexpected_additions() || (!allow_hash_changes && !self.hash_changes.is_empty())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Drift::default()
+    }
+}
+
+// The fields of a learning record that identify a library
+#[derive(Deserialize)]
+struct LearningRecord {
+    path: String,
+    #[serde(default)]
+    sha256: Option<String>,
+}
+
+/// Reads a YAML policy, or learning records (`.jsonl`) as written by learning mode or `learn merge`.
+pub fn load(path: &Path) -> Result<Vec<Allowed>> {
+    let is_learning = path.extension().is_some_and(|ext| ext == "jsonl" || ext == "json");
+    if is_learning {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        return content.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(number, line)| {
+                let record: LearningRecord = serde_json::from_str(line)
+                    .with_context(|| format!("{}:{}: not a learning record", path.display(), number + 1))?;
+                Ok(Allowed::by_path(&record.path, record.sha256.as_deref()))
+            })
+            .collect();
+    }
+
+    let settings: Settings = config::Config::builder()
+        .add_source(config::File::from(path).format(config::FileFormat::Yaml))
+        .build()
+        .and_then(|config| config.try_deserialize())
+        .with_context(|| format!("Could not load policy {}", path.display()))?;
+    Ok(from_settings(&settings))
+}
+
+/// The libraries a loaded policy allows explicitly.
+pub fn from_settings(settings: &Settings) -> Vec<Allowed> {
+    settings.whitelisted_filenames.iter()
+        .map(|name| Allowed::by_name(name))
+        .chain(settings.whitelisted_libraries.iter().map(|l| Allowed::by_path(&l.path, l.sha256.as_deref())))
+        .collect()
+}
+
+/// Compares `old` with `new`. Libraries the built-in system whitelist allows anyway are ignored.
+pub fn diff(old: &[Allowed], new: &[Allowed]) -> Drift {
+    let old: Vec<&Allowed> = old.iter().filter(|a| !is_default_whitelisted(&a.name)).collect();
+    let new: Vec<&Allowed> = new.iter().filter(|a| !is_default_whitelisted(&a.name)).collect();
+    let mut drift = Drift::default();
+
+    let mut added = Vec::new();
+    for entry in &new {
+        let same_path: Vec<&&Allowed> = old.iter()
+            .filter(|o| o.path.is_some() && o.path == entry.path)
+            .collect();
+        if !same_path.is_empty() {
+            // Unpinned on either side, or any pinned hash matching, counts as unchanged
+            let unchanged = entry.sha256.is_none()
+                || same_path.iter().any(|o| o.sha256.is_none() || o.sha256 == entry.sha256);
+            if !unchanged {
+                drift.hash_changes.push(HashChange {
+                    path: entry.label().to_string(),
+                    old_sha256: same_path[0].sha256.clone().unwrap_or_default(),
+                    new_sha256: entry.sha256.clone().unwrap_or_default(),
+                });
+            }
+        } else if !old.iter().any(|o| o.name == entry.name && (o.path.is_none() || entry.path.is_none())) {
+            added.push((*entry).clone());
+        }
+    }
+
+    let mut removed: Vec<Allowed> = old.iter()
+        .filter(|o| match &o.path {
+            Some(_) => !new.iter().any(|n| n.path == o.path || (n.path.is_none() && n.name == o.name)),
+            None => !new.iter().any(|n| n.name == o.name),
+        })
+        .map(|o| (*o).clone())
+        .collect();
+
+    // An addition with the same stem as a removal in the same directory is an upgrade, not a
+    // new dependency; a same-named library turning up elsewhere is exactly what to flag
+    for entry in added {
+        match removed.iter().position(|r| stem(&r.name) == stem(&entry.name) && directory(r) == directory(&entry)) {
+            Some(i) => drift.version_bumps.push(VersionBump { old: removed.remove(i), new: entry }),
+            None => drift.added.push(entry),
+        }
+    }
+    drift.removed = removed;
+    drift
+}
+
+fn directory(entry: &Allowed) -> Option<&Path> {
+    entry.path.as_deref().and_then(|path| Path::new(path).parent())
+}
+
+/// Library name without its version: `libssl.so.3` and `libpython3.11.so.1.0` become
+/// `libssl` and `libpython`.
+fn stem(name: &str) -> &str {
+    let base = name.split(".so").next().unwrap_or(name);
+    base.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.' || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pinned(path: &str, sha256: &str) -> Allowed {
+        Allowed::by_path(path, Some(sha256))
+    }
+
+    #[test]
+    fn test_diff_classifies_changes() {
+        let old = vec![
+            pinned("/opt/app/lib/libssl.so.3.0", "aa"),
+            pinned("/opt/app/lib/libfoo.so", "bb"),
+            pinned("/opt/app/lib/libgone.so", "cc"),
+            pinned("/opt/app/lib/libsame.so", "dd"),
+            Allowed::by_name("libnamed.so.1"),
+        ];
+        let new = vec![
+            pinned("/opt/app/lib/libssl.so.3.1", "a2"),
+            pinned("/opt/app/lib/libfoo.so", "BB2"),
+            pinned("/opt/app/lib/libsame.so", "DD"),
+            // Allowed by name before, pinned now
+            pinned("/usr/lib/libnamed.so.1", "ee"),
+            pinned("/opt/app/plugins/libnew.so", "ff"),
+            // Covered by the built-in whitelist either way
+            pinned("/usr/lib64/libc.so.6", "11"),
+        ];
+
+        let drift = diff(&old, &new);
+        assert_eq!(drift.added, vec![pinned("/opt/app/plugins/libnew.so", "ff")]);
+        assert_eq!(drift.version_bumps.len(), 1);
+        assert_eq!(drift.version_bumps[0].old.label(), "/opt/app/lib/libssl.so.3.0");
+        assert_eq!(drift.version_bumps[0].new.label(), "/opt/app/lib/libssl.so.3.1");
+        assert_eq!(drift.hash_changes, vec![HashChange {
+            path: "/opt/app/lib/libfoo.so".into(),
+            old_sha256: "bb".into(),
+            new_sha256: "bb2".into(),
+        }]);
+        assert_eq!(drift.removed, vec![pinned("/opt/app/lib/libgone.so", "cc")]);
+        assert!(drift.has_unexpected_additions());
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_version_bump_needs_same_directory() {
+        let old = vec![pinned("/opt/app/lib/libssl.so.3", "aa")];
+        let new = vec![pinned("/tmp/x/libssl.so", "bb")];
+        let drift = diff(&old, &new);
+        assert!(drift.version_bumps.is_empty());
+        assert_eq!(drift.added, new);
+        assert_eq!(drift.removed, old);
+    }
+
+    #[test]
+    fn test_hash_changes_fail_gate() {
+        let drift = diff(&[pinned("/opt/app/lib/libfoo.so", "aa")], &[pinned("/opt/app/lib/libfoo.so", "bb")]);
+        assert!(!drift.has_unexpected_additions());
+        assert!(drift.fails_gate(false));
+        assert!(!drift.fails_gate(true));
+
+        let bump = diff(&[pinned("/opt/app/lib/libssl.so.3.0", "aa")], &[pinned("/opt/app/lib/libssl.so.3.1", "bb")]);
+        assert!(!bump.fails_gate(false));
+    }
+
+    #[test]
+    fn test_stem() {
+        assert_eq!(stem("libssl.so.3"), "libssl");
+        assert_eq!(stem("libpython3.11.so.1.0"), "libpython");
+        assert_eq!(stem("libgcc_s-15-20250521.so.1"), "libgcc_s");
+        assert_eq!(stem("_ctypes.cpython-311-x86_64-linux-gnu.so"), "_ctypes.cpython-311-x86_64-linux-gnu");
+    }
+
+    #[test]
+    fn test_load_policy_and_learning() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = dir.path().join("policy.yaml");
+        std::fs::write(&policy, "\
+whitelisted_filenames:
+  - \"libnamed.so.1\"
+whitelisted_libraries:
+  - path: \"/opt/app/lib/libfoo.so\"
+    sha256: \"AB\"
+  - path: \"/opt/app/lib/libbar.so\"
+").unwrap();
+        assert_eq!(load(&policy).unwrap(), vec![
+            Allowed::by_name("libnamed.so.1"),
+            pinned("/opt/app/lib/libfoo.so", "ab"),
+            Allowed::by_path("/opt/app/lib/libbar.so", None),
+        ]);
+
+        let learned = dir.path().join("session.jsonl");
+        std::fs::write(&learned, "\
+{\"library\":\"libfoo.so\",\"path\":\"/opt/app/lib/libfoo.so\",\"inode\":1,\"size\":1,\"sha256\":\"ab\",\"first_seen\":\"\",\"last_seen\":\"\",\"count\":1}
+{\"library\":\"libx.so\",\"path\":\"/opt/app/lib/libx.so\",\"inode\":2,\"size\":0,\"sha256\":\"<error>\",\"first_seen\":\"\",\"last_seen\":\"\",\"count\":1}
+").unwrap();
+        assert_eq!(load(&learned).unwrap(), vec![
+            pinned("/opt/app/lib/libfoo.so", "ab"),
+            Allowed::by_path("/opt/app/lib/libx.so", None),
+        ]);
+    }
+}
@@ -4,6 +4,10 @@ use std::collections::HashSet;
 use std::path::Path;
 use anyhow::{Result};
 use crate::config::Settings; // Import Settings
+use crate::alerting::{self, DetectedLibrary, Detection};
+use crate::{event_store, forensics};
+use crate::monitor::{self, Report};
+use crate::dedup::{self, DedupKey, Decision};
 use tracing::{debug, event, Level as TracingLevel}; // Removed warn, error as event! is used for them
 use std::fs;
 use sha2::{Sha256, Digest};
@@ -33,8 +37,13 @@ static DEFAULT_SYSTEM_WHITELIST: &[&str] = &[
     // "libnss_dns.so.2",
 ];
 
+/// Whether `filename` is allowed by the built-in system whitelist, whatever the policy says.
+pub(crate) fn is_default_whitelisted(filename: &str) -> bool {
+    DEFAULT_SYSTEM_WHITELIST.contains(&filename)
+}
+
 /// Gets file size and SHA256 hash of a library file
-fn get_file_info(path: &Path) -> (u64, String) {
+pub(crate) fn get_file_info(path: &Path) -> (u64, String) {
     let mut size = 0u64;
     let mut hash = String::from("<error>");
     
@@ -63,22 +72,60 @@ fn get_file_info(path: &Path) -> (u64, String) {
 /// Checks loaded libraries parsed from maps_content against a combined whitelist.
 /// Returns Ok((found_unauthorized, audit_mode)) or Err on internal failure.
 pub fn perform_check(settings: &Settings, maps_content: &str) -> Result<(bool, bool)> {
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+    let result = check_maps(settings, maps_content, crate::integrity::self_path());
+    #[cfg(feature = "metrics")]
+    match &result {
+        Ok(_) => crate::metrics::record_check(started.elapsed(), count_libraries(maps_content)),
+        Err(_) => crate::metrics::record_check_error(),
+    }
+    result
+}
+
+/// Distinct executable shared-object mappings in `maps_content`.
+#[cfg(feature = "metrics")]
+fn count_libraries(maps_content: &str) -> usize {
+    maps_content.lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let path = parts.get(5..)?.iter().find(|p| p.starts_with('/'))?;
+            let filename = Path::new(path).file_name()?.to_str()?;
+            (parts[1].contains('x') && filename.contains(".so")).then_some(*path)
+        })
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+/// Whether `path` is listed in `whitelisted_libraries`, with matching content if it is hash-pinned.
+fn is_pinned(settings: &Settings, path: &str) -> bool {
+    settings.whitelisted_libraries.iter()
+        .filter(|pinned| pinned.path == path)
+        .any(|pinned| match &pinned.sha256 {
+            Some(expected) => get_file_info(Path::new(path)).1.eq_ignore_ascii_case(expected),
+            None => true,
+        })
+}
+
+/// Same as `perform_check`, but with our own library path supplied by the caller.
+/// Only the mapping at exactly `self_path` is exempt; a foreign file that merely
+/// shares our filename is treated like any other library.
+fn check_maps(settings: &Settings, maps_content: &str, self_path: Option<&Path>) -> Result<(bool, bool)> {
     debug!("[Check] Starting preload check...");
     let mut found_unauthorized = false;
+    let mut detected = Vec::new();
     
     // Build the effective whitelist:
     // 1. Start with the hardcoded default system libraries.
     // 2. Add libraries specified in the config file.
-    // 3. Always add our own library.
     let mut effective_whitelist: HashSet<String> = DEFAULT_SYSTEM_WHITELIST.iter()
                                                         .map(|s| s.to_string())
                                                         .collect();
     for filename in &settings.whitelisted_filenames {
         effective_whitelist.insert(filename.clone());
     }
-    effective_whitelist.insert("libhyper_processor.so".to_string()); // Add self
-    
-    debug!("[Check] Effective Whitelist Filenames: {:?}", effective_whitelist);
+
+    debug!("[Check] Effective Whitelist Filenames: {:?}, self path: {:?}", effective_whitelist, self_path);
 
     // Process the provided maps_content
     for line in maps_content.lines() {
@@ -102,15 +149,10 @@ pub fn perform_check(settings: &Settings, maps_content: &str) -> Result<(bool, b
                          if let Some(filename) = filename_osstr.to_str() {
                             // Check if the filename itself contains .so before proceeding
                             if filename.contains(".so") { 
-                                // Record in learning mode
-                                #[cfg(feature = "learning")]
-                                {
-                                    if settings.learning_mode {
-                                        crate::learning::record_library(filename);
-                                    }
-                                }
-                                
-                                let is_whitelisted = effective_whitelist.contains(filename);
+                                let is_self = self_path == Some(path);
+                                let is_whitelisted = is_self
+                                    || effective_whitelist.contains(filename)
+                                    || is_pinned(settings, path_str);
                                 debug!(
                                     "[Check] Checking filename: '{}' from path '{}'. Whitelisted: {}",
                                     filename,
@@ -118,11 +160,38 @@ pub fn perform_check(settings: &Settings, maps_content: &str) -> Result<(bool, b
                                     is_whitelisted
                                 );
                                 if !is_whitelisted {
+                                    #[cfg(feature = "metrics")]
+                                    let hashing_started = std::time::Instant::now();
                                     let (file_size, file_hash) = get_file_info(path);
+                                    #[cfg(feature = "metrics")]
+                                    crate::metrics::record_hash_duration(hashing_started.elapsed());
+                                    found_unauthorized = true;
+
+                                    // Identical repeats within the window are counted, not reported
+                                    let key = DedupKey {
+                                        path: path_str.to_string(),
+                                        hash: file_hash.clone(),
+                                        verdict: if settings.audit_mode { "audited" } else { "blocked" }.to_string(),
+                                    };
+                                    match dedup::check(settings, &key) {
+                                        Decision::Suppress => {
+                                            #[cfg(feature = "metrics")]
+                                            crate::metrics::record_suppressed_detection(filename, settings.audit_mode);
+                                            #[cfg(feature = "otel")]
+                                            crate::otel::record_suppressed_detection(filename, settings.audit_mode);
+                                            continue;
+                                        }
+                                        Decision::Emit { suppressed } if suppressed > 0 => {
+                                            dedup::log_summary(&key, suppressed, settings.dedup.window_secs);
+                                        }
+                                        Decision::Emit { .. } => {}
+                                    }
                                     
                                     // Record metrics
                                     #[cfg(feature = "metrics")]
                                     crate::metrics::record_unauthorized_library(filename, settings.audit_mode);
+                                    #[cfg(feature = "otel")]
+                                    crate::otel::record_unauthorized_library(filename, settings.audit_mode);
                                     
                                     if settings.audit_mode { 
                                         event!(TracingLevel::WARN,
@@ -143,11 +212,18 @@ pub fn perform_check(settings: &Settings, maps_content: &str) -> Result<(bool, b
                                             "Unauthorized library detected (Blocking Mode)"
                                         );
                                     }
-                                    found_unauthorized = true;
+                                    detected.push(DetectedLibrary {
+                                        filename: filename.to_string(),
+                                        path: path_str.to_string(),
+                                        size: file_size,
+                                        sha256: file_hash,
+                                    });
                                 } else {
                                     // Record authorized library
                                     #[cfg(feature = "metrics")]
                                     crate::metrics::record_authorized_library(filename);
+                                    #[cfg(feature = "otel")]
+                                    crate::otel::record_authorized_library(filename);
                                 }
                             } // else: filename doesn't contain .so, ignore
                          } else { 
@@ -161,6 +237,28 @@ pub fn perform_check(settings: &Settings, maps_content: &str) -> Result<(bool, b
         }
     }
 
+    if !detected.is_empty() {
+        let blocked = !settings.audit_mode;
+        let forensic_bundle = match forensics::snapshot(settings, blocked, &detected) {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                event!(TracingLevel::WARN, error = %e, "[Forensics] Could not write forensic bundle");
+                None
+            }
+        };
+        if let Some(path) = &forensic_bundle {
+            event!(TracingLevel::INFO, forensic_bundle = %path.display(), "[Forensics] Forensic bundle written");
+        }
+        let mut detection = Detection::new(blocked, detected);
+        detection.forensic_bundle = forensic_bundle.map(|p| p.to_string_lossy().into_owned());
+        event_store::record(settings, &detection);
+        monitor::report(Report::Detection {
+            verdict: detection.verdict.to_string(),
+            libraries: detection.libraries.clone(),
+        });
+        alerting::dispatch(settings, &detection);
+    }
+
     // Restore debug log for final state
     debug!(
         "[Check] Final check state: found_unauthorized = {}, audit_mode = {}",
@@ -182,12 +280,19 @@ mod tests {
         Settings {
             audit_mode,
             whitelisted_filenames: user_whitelist.into_iter().map(String::from).collect(),
-            learning_mode: false,
-            learning_output: None,
             // system_whitelist is handled internally by perform_check using DEFAULT_SYSTEM_WHITELIST
+            ..Settings::default()
         }
     }
 
+    // Path our own library is mapped from in the fixtures below
+    const SELF_LIB_PATH: &str = "/home/user/hyper_processor/target/release/libhyper_processor.so";
+
+    // Runs the check as if we had been loaded from SELF_LIB_PATH
+    fn check(settings: &Settings, maps_content: &str) -> Result<(bool, bool)> {
+        check_maps(settings, maps_content, Some(Path::new(SELF_LIB_PATH)))
+    }
+
     // Example /proc/self/maps content
     const MAPS_LEGIT_ONLY: &str = r#"
 7f0000000000-7f1000000000 r-xp 00000000 fd:01 1234 /usr/lib64/ld-linux-x86-64.so.2
@@ -222,31 +327,41 @@ just some garbage line
     #[test]
     fn test_all_whitelisted() {
         let settings = create_settings(false, vec![]);
-        let result = perform_check(&settings, MAPS_LEGIT_ONLY);
+        let result = check(&settings, MAPS_LEGIT_ONLY);
         assert_eq!(result.unwrap(), (false, false));
     }
 
     #[test]
     fn test_unauthorized_block() {
         let settings = create_settings(false, vec![]); // Audit off
-        let result = perform_check(&settings, MAPS_WITH_UNAUTHORIZED);
+        let result = check(&settings, MAPS_WITH_UNAUTHORIZED);
         assert_eq!(result.unwrap(), (true, false));
     }
 
     #[test]
     fn test_unauthorized_audit() {
         let settings = create_settings(true, vec![]); // Audit ON
-        let result = perform_check(&settings, MAPS_WITH_UNAUTHORIZED);
+        let result = check(&settings, MAPS_WITH_UNAUTHORIZED);
         assert_eq!(result.unwrap(), (true, true));
     }
 
     #[test]
     fn test_user_whitelisted() {
         let settings = create_settings(false, vec!["libevil.so.1"]);
-        let result = perform_check(&settings, MAPS_WITH_UNAUTHORIZED);
+        let result = check(&settings, MAPS_WITH_UNAUTHORIZED);
         assert_eq!(result.unwrap(), (false, false));
     }
     
+    #[test]
+    fn test_impostor_self_library_flagged() {
+        let settings = create_settings(false, vec![]);
+        let maps_content = "7f0000000000-7f1000000000 r-xp 00000000 fd:01 1234 /tmp/libhyper_processor.so\n";
+        assert_eq!(check(&settings, maps_content).unwrap(), (true, false));
+        // Without a resolved self path nothing is exempt by name
+        let result = check_maps(&settings, MAPS_LEGIT_ONLY, None);
+        assert_eq!(result.unwrap(), (true, false));
+    }
+
     #[test]
     fn test_non_executable_ignored() {
         let settings = create_settings(false, vec![]);
@@ -306,6 +421,25 @@ just some garbage line
         assert_eq!(hash2, "<error>");
     }
     
+    #[test]
+    fn test_pinned_library_requires_matching_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("libplugin.so");
+        std::fs::write(&lib, b"plugin v1").unwrap();
+        let maps_content = format!("7f0000000000-7f1000000000 r-xp 00000000 fd:01 77 {}\n", lib.display());
+        let pinned = |sha256: Option<String>| Settings {
+            whitelisted_libraries: vec![crate::config::PinnedLibrary { path: lib.display().to_string(), sha256 }],
+            ..create_settings(false, vec![])
+        };
+
+        assert_eq!(check(&pinned(None), &maps_content).unwrap(), (false, false));
+        assert_eq!(check(&pinned(Some(get_file_info(&lib).1.to_uppercase())), &maps_content).unwrap(), (false, false));
+        assert_eq!(check(&pinned(Some("00".repeat(32))), &maps_content).unwrap(), (true, false));
+        // Same file name elsewhere is not covered by a path pin
+        let moved = maps_content.replace(&dir.path().display().to_string(), "/tmp/elsewhere");
+        assert_eq!(check(&pinned(None), &moved).unwrap(), (true, false));
+    }
+
     #[test]
     fn test_maps_with_extra_whitespace() {
         let settings = create_settings(false, vec![]);
@@ -0,0 +1,238 @@
+// Module for scrubbing secrets out of argv, LD_PRELOAD and environment values before they are logged
+
+use std::sync::OnceLock;
+use anyhow::{Context, Result};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use crate::config::{RedactionMode, RedactionSettings};
+
+const MASK: &str = "[REDACTED]";
+
+static ACTIVE: OnceLock<Redactor> = OnceLock::new();
+
+/// Compiled `redaction` settings.
+#[derive(Debug, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    secret_flags: Vec<String>,
+    mode: RedactionMode,
+    max_length: Option<usize>,
+    /// Set when the configured rules could not be compiled: every value is replaced.
+    redact_everything: bool,
+}
+
+impl Redactor {
+    pub fn new(settings: &RedactionSettings) -> Result<Self> {
+        let patterns = settings.argv_patterns.iter()
+            .map(|p| Regex::new(p).with_context(|| format!("Invalid redaction pattern '{}'", p)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Redactor {
+            patterns,
+            secret_flags: settings.secret_flags.clone(),
+            mode: settings.mode,
+            max_length: settings.max_length,
+            redact_everything: false,
+        })
+    }
+
+    /// Fails closed: used when the configured rules are invalid.
+    pub fn everything(settings: &RedactionSettings) -> Self {
+        Redactor {
+            mode: settings.mode,
+            redact_everything: true,
+            ..Redactor::default()
+        }
+    }
+
+    fn replacement(&self, secret: &str) -> String {
+        match self.mode {
+            RedactionMode::Mask => MASK.to_string(),
+            RedactionMode::Hash => {
+                let digest = format!("{:x}", Sha256::digest(secret.as_bytes()));
+                format!("[sha256:{}]", &digest[..16])
+            }
+        }
+    }
+
+    /// Applies the patterns to one token. A pattern with capture groups replaces only
+    /// the captured text (e.g. the value in `--password=(.*)`); otherwise the whole match.
+    fn redact_token(&self, token: &str) -> String {
+        if self.redact_everything {
+            return self.replacement(token);
+        }
+        let mut out = token.to_string();
+        for pattern in &self.patterns {
+            let Some(captures) = pattern.captures(&out) else {
+                continue;
+            };
+            let mut ranges: Vec<(usize, usize)> = captures.iter().skip(1).flatten()
+                .map(|m| (m.start(), m.end()))
+                .collect();
+            if ranges.is_empty() {
+                let whole = captures.get(0).unwrap();
+                ranges.push((whole.start(), whole.end()));
+            }
+            // Only the outermost groups: an inner group's text is already covered, and
+            // its offsets would be stale once the outer one is replaced
+            ranges.sort_unstable_by_key(|&(start, end)| (start, std::cmp::Reverse(end)));
+            let mut outermost: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+            for (start, end) in ranges {
+                if outermost.last().is_none_or(|&(_, last_end)| start >= last_end) {
+                    outermost.push((start, end));
+                }
+            }
+            // Replace back to front so earlier offsets stay valid
+            for (start, end) in outermost.into_iter().rev() {
+                let replacement = self.replacement(&out[start..end]);
+                out.replace_range(start..end, &replacement);
+            }
+        }
+        out
+    }
+
+    fn truncate(&self, value: String) -> String {
+        match self.max_length {
+            Some(max) if value.chars().count() > max => {
+                let kept: String = value.chars().take(max).collect();
+                format!("{kept}...[truncated]")
+            }
+            _ => value,
+        }
+    }
+
+    /// Redacts argv. The token after any of `secret_flags` (`--token abc`) is replaced outright.
+    pub fn redact_argv<S: AsRef<str>>(&self, argv: &[S]) -> Vec<String> {
+        let mut out = Vec::with_capacity(argv.len());
+        let mut next_is_secret = false;
+        for arg in argv {
+            let arg = arg.as_ref();
+            if next_is_secret {
+                out.push(self.replacement(arg));
+                next_is_secret = false;
+                continue;
+            }
+            next_is_secret = self.secret_flags.iter().any(|flag| flag == arg);
+            out.push(self.redact_token(arg));
+        }
+        out
+    }
+
+    /// Redacts a NUL-separated `/proc/<pid>/cmdline` into one space-joined, length-limited string.
+    pub fn redact_cmdline(&self, raw: &[u8]) -> String {
+        let text = String::from_utf8_lossy(raw);
+        let argv: Vec<&str> = text.split('\0').filter(|s| !s.is_empty()).collect();
+        self.truncate(self.redact_argv(&argv).join(" "))
+    }
+
+    /// Redacts each LD_PRELOAD entry, keeping the original separators.
+    pub fn redact_ld_preload(&self, value: &str) -> String {
+        let mut out = String::with_capacity(value.len());
+        let mut token = String::new();
+        for c in value.chars() {
+            if c == ' ' || c == ':' {
+                if !token.is_empty() {
+                    out.push_str(&self.redact_token(&token));
+                    token.clear();
+                }
+                out.push(c);
+            } else {
+                token.push(c);
+            }
+        }
+        if !token.is_empty() {
+            out.push_str(&self.redact_token(&token));
+        }
+        self.truncate(out)
+    }
+
+    /// Redacts one `KEY=VALUE` environment entry; patterns see the whole entry.
+    pub fn redact_env_entry(&self, entry: &str) -> String {
+        self.truncate(self.redact_token(entry))
+    }
+
+    /// Keeps only the key of a `KEY=VALUE` environment entry, replacing the whole value.
+    pub fn mask_env_entry(&self, entry: &str) -> String {
+        match entry.split_once('=') {
+            Some((key, value)) => self.truncate(format!("{key}={}", self.replacement(value))),
+            None => self.redact_env_entry(entry),
+        }
+    }
+}
+
+/// Installs the process-wide rules. Returns the compile error, if any, after falling back to
+/// redacting everything.
+pub fn init(settings: &RedactionSettings) -> Result<()> {
+    let (redactor, result) = match Redactor::new(settings) {
+        Ok(redactor) => (redactor, Ok(())),
+        Err(e) => (Redactor::everything(settings), Err(e)),
+    };
+    let _ = ACTIVE.set(redactor);
+    result
+}
+
+/// The rules installed by `init`, or a pass-through redactor before that.
+pub fn active() -> &'static Redactor {
+    static PASS_THROUGH: OnceLock<Redactor> = OnceLock::new();
+    ACTIVE.get().unwrap_or_else(|| PASS_THROUGH.get_or_init(Redactor::default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(patterns: &[&str], mode: RedactionMode, max_length: Option<usize>) -> Redactor {
+        Redactor::new(&RedactionSettings {
+            argv_patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            secret_flags: vec!["--token".into(), "-p".into()],
+            mode,
+            max_length,
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_capture_group_replaces_value_only() {
+        let r = redactor(&["^--password=(.+)$"], RedactionMode::Mask, None);
+        assert_eq!(r.redact_argv(&["app", "--password=hunter2", "--verbose"]),
+            ["app", "--password=[REDACTED]", "--verbose"]);
+    }
+
+    #[test]
+    fn test_nested_and_repeated_groups() {
+        let r = redactor(&["^(--password=(.+))$"], RedactionMode::Mask, None);
+        assert_eq!(r.redact_argv(&["--password=hunter2"]), ["[REDACTED]"]);
+        // The repeated inner group only reports its last iteration; the outer one covers it
+        let r = redactor(&["^--pin=((\\d)+)$"], RedactionMode::Hash, None);
+        let redacted = r.redact_argv(&["--pin=1234"]);
+        assert_eq!(redacted, [format!("--pin={}", r.replacement("1234"))]);
+    }
+
+    #[test]
+    fn test_whole_match_and_secret_flags() {
+        let r = redactor(&["AKIA[0-9A-Z]{16}"], RedactionMode::Mask, None);
+        assert_eq!(r.redact_cmdline(b"aws\0--key\0AKIAABCDEFGHIJKLMNOP\0--token\0abc\0-p\0pw\0"),
+            "aws --key [REDACTED] --token [REDACTED] -p [REDACTED]");
+    }
+
+    #[test]
+    fn test_hash_mode_is_stable() {
+        let r = redactor(&["^--password=(.+)$"], RedactionMode::Hash, None);
+        let first = r.redact_argv(&["--password=hunter2"]);
+        assert_eq!(first, r.redact_argv(&["--password=hunter2"]));
+        assert!(first[0].starts_with("--password=[sha256:"));
+        assert!(!first[0].contains("hunter2"));
+    }
+
+    #[test]
+    fn test_max_length_and_ld_preload() {
+        let r = redactor(&["secret"], RedactionMode::Mask, Some(24));
+        assert_eq!(r.redact_ld_preload("/opt/secret/lib.so:/usr/lib/libfoo.so"), "/opt/[REDACTED]/lib.so:/...[truncated]");
+        assert_eq!(r.redact_env_entry("API=secret"), "API=[REDACTED]");
+    }
+
+    #[test]
+    fn test_invalid_pattern_fails_closed() {
+        let settings = RedactionSettings { argv_patterns: vec!["(".into()], ..RedactionSettings::default() };
+        assert!(Redactor::new(&settings).is_err());
+        assert_eq!(Redactor::everything(&settings).redact_argv(&["app"]), ["[REDACTED]"]);
+    }
+}
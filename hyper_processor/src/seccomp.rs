@@ -0,0 +1,230 @@
+// Module for installing a seccomp deny-list once the preload check has passed
+
+use anyhow::{anyhow, bail, Result};
+use tracing::debug;
+use crate::config::{SeccompAction, SeccompProfile, Settings, MAX_SECCOMP_ERRNO};
+
+/// Name of the built-in profile, used when `seccomp.profiles` does not define it: fails
+/// `ptrace`, `process_vm_writev` and `memfd_create` with EPERM and allows everything else.
+pub const BUILTIN_PROFILE: &str = "post-check";
+
+// Syscalls most services never need once the process is up and verified
+static BUILTIN_DENY: &[&str] = &["ptrace", "process_vm_writev", "memfd_create"];
+
+// Classic BPF opcodes (linux/filter.h)
+const BPF_LD_W_ABS: u16 = 0x20; // BPF_LD | BPF_W | BPF_ABS
+const BPF_JMP_JEQ_K: u16 = 0x15; // BPF_JMP | BPF_JEQ | BPF_K
+const BPF_JMP_JGE_K: u16 = 0x35; // BPF_JMP | BPF_JGE | BPF_K
+const BPF_RET_K: u16 = 0x06; // BPF_RET | BPF_K
+
+// Offsets into struct seccomp_data
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH_CURRENT: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH_CURRENT: u32 = 0xC000_00B7;
+
+// x32 syscalls share the x86_64 audit arch but set this bit in the number
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// Syscalls that can be named in a profile; anything else must be given by number
+static SYSCALL_NAMES: &[(&str, libc::c_long)] = &[
+    ("ptrace", libc::SYS_ptrace),
+    ("process_vm_readv", libc::SYS_process_vm_readv),
+    ("process_vm_writev", libc::SYS_process_vm_writev),
+    ("memfd_create", libc::SYS_memfd_create),
+    ("execve", libc::SYS_execve),
+    ("execveat", libc::SYS_execveat),
+    ("init_module", libc::SYS_init_module),
+    ("finit_module", libc::SYS_finit_module),
+    ("delete_module", libc::SYS_delete_module),
+    ("kexec_load", libc::SYS_kexec_load),
+    ("bpf", libc::SYS_bpf),
+    ("perf_event_open", libc::SYS_perf_event_open),
+    ("userfaultfd", libc::SYS_userfaultfd),
+    ("personality", libc::SYS_personality),
+    ("mount", libc::SYS_mount),
+    ("umount2", libc::SYS_umount2),
+    ("pivot_root", libc::SYS_pivot_root),
+    ("chroot", libc::SYS_chroot),
+    ("setns", libc::SYS_setns),
+    ("unshare", libc::SYS_unshare),
+    ("keyctl", libc::SYS_keyctl),
+    ("add_key", libc::SYS_add_key),
+    ("request_key", libc::SYS_request_key),
+    ("open_by_handle_at", libc::SYS_open_by_handle_at),
+    ("name_to_handle_at", libc::SYS_name_to_handle_at),
+    ("io_uring_setup", libc::SYS_io_uring_setup),
+    ("pidfd_getfd", libc::SYS_pidfd_getfd),
+];
+
+/// Maps a syscall name (or a plain number) from the config file to its number on this architecture.
+fn syscall_number(name: &str) -> Option<u32> {
+    SYSCALL_NAMES.iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, nr)| *nr as u32)
+        .or_else(|| name.parse().ok())
+}
+
+/// Returns the profile selected in `settings`, if any, falling back to the built-in one.
+pub fn selected_profile(settings: &Settings) -> Option<(String, SeccompProfile)> {
+    let name = settings.seccomp.profile.as_ref()?;
+    let profile = match settings.seccomp.profiles.get(name) {
+        Some(profile) => profile.clone(),
+        None if name == BUILTIN_PROFILE => SeccompProfile {
+            deny_syscalls: BUILTIN_DENY.iter().map(|s| s.to_string()).collect(),
+            ..SeccompProfile::default()
+        },
+        None => return None,
+    };
+    Some((name.clone(), profile))
+}
+
+fn action_value(profile: &SeccompProfile) -> u32 {
+    match profile.action {
+        SeccompAction::Errno => libc::SECCOMP_RET_ERRNO | (profile.errno as u32 & libc::SECCOMP_RET_DATA),
+        SeccompAction::Kill => libc::SECCOMP_RET_KILL_PROCESS,
+        SeccompAction::Log => libc::SECCOMP_RET_LOG,
+    }
+}
+
+fn stmt(code: u16, k: u32) -> libc::sock_filter {
+    libc::sock_filter { code, jt: 0, jf: 0, k }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+/// Builds a deny-list filter: foreign-arch calls and listed syscalls get the profile action,
+/// everything else is allowed.
+fn build_filter(profile: &SeccompProfile) -> Result<Vec<libc::sock_filter>> {
+    let mut numbers = Vec::with_capacity(profile.deny_syscalls.len());
+    for name in &profile.deny_syscalls {
+        let nr = syscall_number(name.trim())
+            .ok_or_else(|| anyhow!("Unknown syscall '{}' in seccomp profile", name))?;
+        numbers.push(nr);
+    }
+    // Profiles from the config file are checked when it is loaded
+    if profile.action == SeccompAction::Errno && !(1..=MAX_SECCOMP_ERRNO).contains(&profile.errno) {
+        bail!("Seccomp errno {} is out of range 1..={}", profile.errno, MAX_SECCOMP_ERRNO);
+    }
+    let action = action_value(profile);
+
+    let mut filter = vec![
+        stmt(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+        jump(BPF_JMP_JEQ_K, AUDIT_ARCH_CURRENT, 1, 0),
+        stmt(BPF_RET_K, action),
+        stmt(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    {
+        filter.push(jump(BPF_JMP_JGE_K, X32_SYSCALL_BIT, 0, 1));
+        filter.push(stmt(BPF_RET_K, action));
+    }
+    for nr in numbers {
+        filter.push(jump(BPF_JMP_JEQ_K, nr, 0, 1));
+        filter.push(stmt(BPF_RET_K, action));
+    }
+    filter.push(stmt(BPF_RET_K, libc::SECCOMP_RET_ALLOW));
+
+    if filter.len() > u16::MAX as usize {
+        bail!("Seccomp profile is too large ({} instructions)", filter.len());
+    }
+    Ok(filter)
+}
+
+/// Installs the profile for every thread of the process. Irreversible by design, and so is
+/// the PR_SET_NO_NEW_PRIVS it needs: setuid execs from here on run without privileges.
+pub fn install(profile: &SeccompProfile) -> Result<()> {
+    let mut filter = build_filter(profile)?;
+    let prog = libc::sock_fprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_mut_ptr(),
+    };
+
+    // Required to install a filter without CAP_SYS_ADMIN
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        bail!("prctl(PR_SET_NO_NEW_PRIVS) failed: {}", std::io::Error::last_os_error());
+    }
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const libc::sock_fprog,
+        )
+    };
+    if rc != 0 {
+        bail!("seccomp(SECCOMP_SET_MODE_FILTER) failed: {}", std::io::Error::last_os_error());
+    }
+    debug!("[Seccomp] Installed filter with {} instructions", filter.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings_with(profile: Option<&str>) -> Settings {
+        let mut settings = Settings::default();
+        settings.seccomp.profile = profile.map(String::from);
+        settings
+    }
+
+    #[test]
+    fn test_no_profile_selected() {
+        assert!(selected_profile(&settings_with(None)).is_none());
+        assert!(selected_profile(&settings_with(Some("missing"))).is_none());
+    }
+
+    #[test]
+    fn test_builtin_profile() {
+        let (name, profile) = selected_profile(&settings_with(Some(BUILTIN_PROFILE))).unwrap();
+        assert_eq!(name, BUILTIN_PROFILE);
+        assert_eq!(profile.action, SeccompAction::Errno);
+        assert!(profile.deny_syscalls.contains(&"ptrace".to_string()));
+    }
+
+    #[test]
+    fn test_syscall_names_and_numbers() {
+        assert_eq!(syscall_number("ptrace"), Some(libc::SYS_ptrace as u32));
+        assert_eq!(syscall_number("425"), Some(425));
+        assert_eq!(syscall_number("not_a_syscall"), None);
+    }
+
+    #[test]
+    fn test_build_filter() {
+        let profile = SeccompProfile {
+            deny_syscalls: vec!["ptrace".into(), "memfd_create".into()],
+            action: SeccompAction::Kill,
+            ..SeccompProfile::default()
+        };
+        let filter = build_filter(&profile).unwrap();
+        let last = filter.last().unwrap();
+        assert_eq!(last.k, libc::SECCOMP_RET_ALLOW);
+        let kills = filter.iter()
+            .filter(|insn| insn.code == BPF_RET_K && insn.k == libc::SECCOMP_RET_KILL_PROCESS)
+            .count();
+        // arch mismatch + (x32 on x86_64) + one per syscall
+        assert_eq!(kills, 1 + usize::from(cfg!(target_arch = "x86_64")) + 2);
+    }
+
+    #[test]
+    fn test_build_filter_unknown_syscall() {
+        let profile = SeccompProfile {
+            deny_syscalls: vec!["definitely_not_real".into()],
+            ..SeccompProfile::default()
+        };
+        assert!(build_filter(&profile).is_err());
+    }
+
+    #[test]
+    fn test_build_filter_rejects_bad_errno() {
+        let profile = SeccompProfile { errno: 65537, ..SeccompProfile::default() };
+        assert!(build_filter(&profile).is_err());
+    }
+}
@@ -0,0 +1,486 @@
+// Module for routing tracing events to the sinks selected in Settings
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::fmt::{self, format::Writer, time::FormatTime, MakeWriter};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use crate::config::{LogFormat, SinkConfig};
+use crate::event_store::Locked;
+use crate::formats::{self, FieldValues, FormattedLayer, SpanFieldsLayer};
+
+/// Identifier used for syslog APP-NAME and journald SYSLOG_IDENTIFIER.
+const APP_NAME: &str = "hyper_processor";
+
+pub type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync + 'static>;
+
+/// Builds one layer per configured sink. An empty list keeps the historical JSON-on-stderr output.
+/// Sinks that cannot be opened are reported on stderr and skipped; if none remain, stderr is used.
+pub fn build_layers<S>(sinks: &[SinkConfig], format: LogFormat) -> Vec<BoxedLayer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    // Span fields are captured once and shared by the journald and CEF/ECS layers
+    let mut layers: Vec<BoxedLayer<S>> = vec![Box::new(SpanFieldsLayer)];
+    for sink in sinks {
+        match build_layer(sink, format) {
+            Ok(layer) => layers.push(layer),
+            Err(e) => eprintln!("[HYPER_RASP PRE-LOGGING ERROR] Could not open sink {:?}: {}", sink, e),
+        }
+    }
+    if layers.len() == 1 {
+        layers.push(line_layer(format, io::stderr));
+    }
+    layers
+}
+
+fn build_layer<S>(sink: &SinkConfig, format: LogFormat) -> io::Result<BoxedLayer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Ok(match sink {
+        SinkConfig::Stderr => line_layer(format, io::stderr),
+        SinkConfig::Syslog { socket, facility } => line_layer(format, SyslogSink::new(socket, *facility)?),
+        SinkConfig::Journald { socket } => Box::new(JournaldLayer::new(socket)?),
+        SinkConfig::File { path, max_bytes, max_files } => {
+            line_layer(format, RotatingFile::open(Path::new(path), *max_bytes, *max_files)?)
+        }
+        SinkConfig::UnixDatagram { path } => line_layer(format, DatagramSink::new(path)?),
+    })
+}
+
+/// One event per line (or datagram) in the configured layout.
+fn line_layer<S, W>(format: LogFormat, writer: W) -> BoxedLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Json => json_layer(writer),
+        LogFormat::Cef | LogFormat::Ecs => Box::new(FormattedLayer::new(format, writer)),
+    }
+}
+
+/// The default JSON event layout.
+fn json_layer<S, W>(writer: W) -> BoxedLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    Box::new(
+        fmt::layer()
+            .json()
+            .with_current_span(true) // Enable to see span fields in logs
+            .with_span_list(true)   // Include span context in logs
+            .with_target(true)
+            .with_file(true)
+            .with_line_number(true)
+            .with_writer(writer),
+    )
+}
+
+/// Syslog severity for a tracing level (RFC 5424 section 6.2.1).
+fn syslog_severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+pub(crate) fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|s| s.trim().to_string())
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| String::from("-"))
+}
+
+fn rfc3339_now() -> String {
+    let mut out = String::new();
+    if fmt::time::SystemTime.format_time(&mut Writer::new(&mut out)).is_err() {
+        out = String::from("-");
+    }
+    out
+}
+
+/// RFC 5424 header: `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD `.
+fn syslog_header(facility: u8, severity: u8, timestamp: &str, hostname: &str, pid: u32) -> String {
+    format!("<{}>1 {} {} {} {} - - ", u16::from(facility) * 8 + u16::from(severity), timestamp, hostname, APP_NAME, pid)
+}
+
+/// RFC 5424 messages over a local datagram socket (normally /dev/log), one datagram per event.
+struct SyslogSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+    facility: u8,
+    hostname: String,
+    pid: u32,
+}
+
+impl SyslogSink {
+    fn new(path: &str, facility: u8) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(SyslogSink {
+            socket,
+            path: PathBuf::from(path),
+            facility,
+            hostname: hostname(),
+            pid: std::process::id(),
+        })
+    }
+}
+
+/// Buffers one formatted event and hands it to `send` when dropped.
+struct EventBuffer<F: FnMut(&[u8])> {
+    buf: Vec<u8>,
+    send: Option<F>,
+}
+
+impl<F: FnMut(&[u8])> EventBuffer<F> {
+    fn new(send: F) -> Self {
+        EventBuffer { buf: Vec::with_capacity(512), send: Some(send) }
+    }
+}
+
+/// Writer handed out per event by the sinks below.
+type SinkWriter<'a> = EventBuffer<Box<dyn FnMut(&[u8]) + 'a>>;
+
+impl<F: FnMut(&[u8])> Write for EventBuffer<F> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[u8])> Drop for EventBuffer<F> {
+    fn drop(&mut self) {
+        if let Some(mut send) = self.send.take() {
+            if !self.buf.is_empty() {
+                send(&self.buf);
+            }
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogSink {
+    type Writer = SinkWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer_with_severity(syslog_severity(&Level::INFO))
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.writer_with_severity(syslog_severity(meta.level()))
+    }
+}
+
+impl SyslogSink {
+    fn writer_with_severity(&self, severity: u8) -> SinkWriter<'_> {
+        EventBuffer::new(Box::new(move |line: &[u8]| {
+            let mut datagram = syslog_header(self.facility, severity, &rfc3339_now(), &self.hostname, self.pid).into_bytes();
+            datagram.extend_from_slice(trim_newline(line));
+            if self.socket.send(&datagram).is_err() {
+                // syslogd may have restarted; try once to reconnect
+                if self.socket.connect(&self.path).is_ok() {
+                    let _ = self.socket.send(&datagram);
+                }
+            }
+        }))
+    }
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+/// Each event as a single JSON datagram to a local collector socket.
+struct DatagramSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl DatagramSink {
+    fn new(path: &str) -> io::Result<Self> {
+        Ok(DatagramSink { socket: UnixDatagram::unbound()?, path: PathBuf::from(path) })
+    }
+}
+
+impl<'a> MakeWriter<'a> for DatagramSink {
+    type Writer = SinkWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        // The collector may not be up yet; events are dropped rather than blocking the process
+        EventBuffer::new(Box::new(move |line: &[u8]| {
+            let _ = self.socket.send_to(trim_newline(line), &self.path);
+        }))
+    }
+}
+
+/// Append-only log file rotated to `<path>.1 .. <path>.<max_files>` once it exceeds `max_bytes`.
+/// Every protected process appends to the same file, so the size check and the rotation run
+/// under an flock on `<path>.lock`, and a process whose file was rotated away reopens `path`.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    lock: File,
+    file: Mutex<File>,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut lock_path = path.to_path_buf().into_os_string();
+        lock_path.push(".lock");
+        let lock = OpenOptions::new().create(true).append(true).open(PathBuf::from(lock_path))?;
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            lock,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self) -> io::Result<File> {
+        if self.max_files == 0 {
+            // No history kept: just start over
+            return OpenOptions::new().create(true).write(true).truncate(true).open(&self.path);
+        }
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        OpenOptions::new().create(true).append(true).open(&self.path)
+    }
+
+    /// True when `file` is no longer the one at `path` (another process rotated it).
+    fn is_stale(&self, file: &File) -> bool {
+        match (file.metadata(), fs::metadata(&self.path)) {
+            (Ok(open), Ok(current)) => open.dev() != current.dev() || open.ino() != current.ino(),
+            _ => true,
+        }
+    }
+
+    fn rotate_if_full(&self, file: &mut File, incoming: u64) -> io::Result<()> {
+        if self.is_stale(file) {
+            *file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        // The size on disk, not ours: other processes append to the same file
+        let size = file.metadata()?.len();
+        if size > 0 && size + incoming > self.max_bytes {
+            *file = self.rotate()?;
+        }
+        Ok(())
+    }
+
+    fn append(&self, line: &[u8]) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if self.max_bytes == 0 {
+            let _ = file.write_all(line);
+            return;
+        }
+        let locked = self.lock.try_clone().map_err(anyhow::Error::from).and_then(Locked::acquire);
+        match locked {
+            Ok(_locked) => {
+                if let Err(e) = self.rotate_if_full(&mut file, line.len() as u64) {
+                    eprintln!("[HYPER_RASP] Failed to rotate {}: {}", self.path.display(), e);
+                }
+                let _ = file.write_all(line);
+            }
+            Err(e) => {
+                eprintln!("[HYPER_RASP] Failed to lock {}: {}", self.path.display(), e);
+                let _ = file.write_all(line);
+            }
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = SinkWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        EventBuffer::new(Box::new(move |line: &[u8]| self.append(line)))
+    }
+}
+
+/// Native journald protocol: one datagram of `KEY=value` fields per event.
+struct JournaldLayer {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl JournaldLayer {
+    fn new(path: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(JournaldLayer { socket, path: PathBuf::from(path) })
+    }
+}
+
+/// Journald field names must be uppercase ASCII letters, digits and underscores, not starting with `_`.
+fn journal_field_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    while out.starts_with('_') || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.remove(0);
+    }
+    if out.is_empty() {
+        out.push_str("FIELD");
+    }
+    out
+}
+
+/// Appends one field, using the binary length-prefixed form when the value contains a newline.
+fn append_journal_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    } else {
+        buf.push(b'=');
+        buf.extend_from_slice(value.as_bytes());
+    }
+    buf.push(b'\n');
+}
+
+impl<S> Layer<S> for JournaldLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut buf = Vec::with_capacity(512);
+        append_journal_field(&mut buf, "PRIORITY", &syslog_severity(meta.level()).to_string());
+        append_journal_field(&mut buf, "SYSLOG_IDENTIFIER", APP_NAME);
+        append_journal_field(&mut buf, "TARGET", meta.target());
+        if let Some(file) = meta.file() {
+            append_journal_field(&mut buf, "CODE_FILE", file);
+        }
+        if let Some(line) = meta.line() {
+            append_journal_field(&mut buf, "CODE_LINE", &line.to_string());
+        }
+
+        for (name, value) in formats::span_fields(event, &ctx) {
+            append_journal_field(&mut buf, &journal_field_name(&name), &formats::value_text(&value));
+        }
+
+        let mut fields = FieldValues::default();
+        event.record(&mut fields);
+        for (name, value) in &fields.0 {
+            let key = if name == "message" { String::from("MESSAGE") } else { journal_field_name(name) };
+            append_journal_field(&mut buf, &key, &formats::value_text(value));
+        }
+
+        if self.socket.send(&buf).is_err() && self.socket.connect(&self.path).is_ok() {
+            let _ = self.socket.send(&buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_syslog_header() {
+        let header = syslog_header(4, syslog_severity(&Level::ERROR), "2024-01-01T00:00:00Z", "host", 42);
+        assert_eq!(header, "<35>1 2024-01-01T00:00:00Z host hyper_processor 42 - - ");
+    }
+
+    #[test]
+    fn test_journal_field_names() {
+        assert_eq!(journal_field_name("unauthorized_library_filename"), "UNAUTHORIZED_LIBRARY_FILENAME");
+        assert_eq!(journal_field_name("_private.key"), "PRIVATE_KEY");
+    }
+
+    #[test]
+    fn test_journal_binary_field() {
+        let mut buf = Vec::new();
+        append_journal_field(&mut buf, "A", "x");
+        append_journal_field(&mut buf, "B", "1\n2");
+        let mut expected = b"A=x\nB\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"1\n2\n");
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_syslog_sink_sends_datagram() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("log.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let sink = SyslogSink::new(path.to_str().unwrap(), 10).unwrap();
+        let subscriber = tracing_subscriber::registry().with(json_layer(sink));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(alert_type = "AUDIT", "hello");
+        });
+
+        let mut buf = [0u8; 4096];
+        let n = server.recv(&mut buf).unwrap();
+        let datagram = String::from_utf8_lossy(&buf[..n]);
+        assert!(datagram.starts_with("<84>1 "), "{datagram}");
+        assert!(datagram.contains("\"alert_type\":\"AUDIT\""));
+        assert!(!datagram.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rasp.log");
+        let file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["first-line\n", "second-line\n", "third-line\n", "fourth-line\n"] {
+            file.append(line.as_bytes());
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth-line\n");
+        assert_eq!(fs::read_to_string(file.rotated_path(1)).unwrap(), "third-line\n");
+        assert_eq!(fs::read_to_string(file.rotated_path(2)).unwrap(), "second-line\n");
+        assert!(!file.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn test_rotating_file_shared_between_writers() {
+        // Two sinks on one path stand in for two processes
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rasp.log");
+        let first = RotatingFile::open(&path, 20, 3).unwrap();
+        let second = RotatingFile::open(&path, 20, 3).unwrap();
+        first.append(b"first-line\n");
+        // Sees the line written by `first` and rotates instead of overfilling the file
+        second.append(b"second-line\n");
+        // Follows the rotation instead of writing to the renamed file
+        first.append(b"third-line\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third-line\n");
+        assert_eq!(fs::read_to_string(first.rotated_path(1)).unwrap(), "second-line\n");
+        assert_eq!(fs::read_to_string(first.rotated_path(2)).unwrap(), "first-line\n");
+    }
+}
@@ -0,0 +1,238 @@
+// Module for enforcing W^X by interposing mmap, mprotect and pkey_mprotect
+//
+// The interposers are only exported with the `preload` feature: in a binary or an embedder
+// linking the rlib they would replace libc's functions for the whole program.
+#![cfg_attr(not(feature = "preload"), allow(dead_code, unused_imports))]
+
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::path::Path;
+use std::sync::OnceLock;
+use libc::{c_int, c_void, off_t, size_t};
+use tracing::{event, Level as TracingLevel};
+use crate::config::{Settings, WxMode};
+
+/// Effective policy, set once from `init_library`. Until then every call passes straight through.
+static POLICY: OnceLock<WxPolicy> = OnceLock::new();
+
+thread_local! {
+    // Set while we are inside an interposer so logging (which may map memory) cannot recurse
+    static IN_GUARD: Cell<bool> = const { Cell::new(false) };
+}
+
+extern "C" {
+    // glibc <execinfo.h>
+    fn backtrace(buffer: *mut *mut c_void, size: c_int) -> c_int;
+}
+
+struct WxPolicy {
+    mode: WxMode,
+    allowed_libraries: Vec<String>,
+}
+
+/// Activates the guard according to `settings`. Audit mode downgrades `deny` to `log`.
+pub fn init(settings: &Settings) {
+    let mut mode = settings.wx_guard.mode;
+    if settings.audit_mode && mode == WxMode::Deny {
+        mode = WxMode::Log;
+    }
+    if mode != WxMode::Off && !cfg!(feature = "preload") {
+        event!(TracingLevel::ERROR, "wx_guard.mode is set but this build has no interposers; rebuild the library with --features preload");
+    }
+    let _ = POLICY.set(WxPolicy {
+        mode,
+        allowed_libraries: settings.wx_guard.allowed_libraries.clone(),
+    });
+}
+
+/// Returns the W^X violation for a mapping request, if any.
+fn classify(prot: c_int, anonymous: bool) -> Option<&'static str> {
+    let write = prot & libc::PROT_WRITE != 0;
+    let exec = prot & libc::PROT_EXEC != 0;
+    if write && exec {
+        Some("writable_executable")
+    } else if exec && anonymous {
+        Some("anonymous_executable")
+    } else {
+        None
+    }
+}
+
+/// Finds whether `addr` lies in an anonymous mapping (no backing file) according to `maps_content`.
+fn is_anonymous_region(maps_content: &str, addr: usize) -> bool {
+    for line in maps_content.lines() {
+        let mut parts = line.split_whitespace();
+        let Some((start, end)) = parts.next().and_then(|range| range.split_once('-')) else {
+            continue;
+        };
+        let (Ok(start), Ok(end)) = (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) else {
+            continue;
+        };
+        if addr >= start && addr < end {
+            // address perms offset dev inode [path]
+            return !parts.nth(4).is_some_and(|path| path.starts_with('/'));
+        }
+    }
+    // Not mapped at all; the kernel will reject the call anyway
+    false
+}
+
+/// Basename of the first library on the stack that isn't us, i.e. whoever called the interposer.
+fn calling_library() -> Option<String> {
+    let mut frames = [std::ptr::null_mut::<c_void>(); 16];
+    let depth = unsafe { backtrace(frames.as_mut_ptr(), frames.len() as c_int) };
+    let own = crate::integrity::self_path();
+    for &frame in frames.iter().take(depth.max(0) as usize) {
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        if unsafe { libc::dladdr(frame, &mut info) } == 0 || info.dli_fname.is_null() {
+            continue;
+        }
+        let fname = unsafe { CStr::from_ptr(info.dli_fname) }.to_string_lossy();
+        let path = Path::new(fname.as_ref());
+        if own == Some(path) {
+            continue;
+        }
+        return path.file_name().map(|name| name.to_string_lossy().into_owned());
+    }
+    None
+}
+
+/// Decides whether a violating call goes ahead. Logs every violation.
+fn permit(policy: &WxPolicy, syscall: &str, violation: &str, addr: usize, len: size_t, prot: c_int) -> bool {
+    let caller = calling_library();
+    let allowed = caller.as_deref().is_some_and(|caller| {
+        policy.allowed_libraries.iter().any(|lib| lib == caller)
+    });
+    let caller = caller.as_deref().unwrap_or("<unknown>");
+
+    if allowed {
+        event!(TracingLevel::DEBUG, syscall, violation, caller, "[WX] Allowed W^X request from allow-listed library");
+        return true;
+    }
+    if policy.mode == WxMode::Deny {
+        event!(TracingLevel::ERROR,
+            syscall, violation, caller,
+            addr = format!("{addr:#x}").as_str(), len, prot,
+            alert_type = "SECURITY",
+            "W^X violation denied"
+        );
+        false
+    } else {
+        event!(TracingLevel::WARN,
+            syscall, violation, caller,
+            addr = format!("{addr:#x}").as_str(), len, prot,
+            alert_type = "AUDIT",
+            "W^X violation detected (Audit Mode)"
+        );
+        true
+    }
+}
+
+/// Runs `check` under the reentrancy guard; returns true if the call may proceed.
+fn guarded(check: impl FnOnce(&WxPolicy) -> bool) -> bool {
+    let Some(policy) = POLICY.get().filter(|p| p.mode != WxMode::Off) else {
+        return true;
+    };
+    if IN_GUARD.with(|g| g.replace(true)) {
+        return true;
+    }
+    let result = check(policy);
+    IN_GUARD.with(|g| g.set(false));
+    result
+}
+
+fn set_errno(value: c_int) {
+    unsafe { *libc::__errno_location() = value };
+}
+
+/// # Safety
+/// Same contract as libc `mmap`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn mmap(addr: *mut c_void, len: size_t, prot: c_int, flags: c_int, fd: c_int, offset: off_t) -> *mut c_void {
+    let proceed = guarded(|policy| {
+        match classify(prot, flags & libc::MAP_ANONYMOUS != 0) {
+            Some(violation) => permit(policy, "mmap", violation, addr as usize, len, prot),
+            None => true,
+        }
+    });
+    if !proceed {
+        set_errno(libc::EACCES);
+        return libc::MAP_FAILED;
+    }
+    libc::syscall(libc::SYS_mmap, addr, len, prot, flags, fd, offset) as *mut c_void
+}
+
+/// # Safety
+/// Same contract as libc `mprotect`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn mprotect(addr: *mut c_void, len: size_t, prot: c_int) -> c_int {
+    if !check_protect("mprotect", addr, len, prot) {
+        set_errno(libc::EACCES);
+        return -1;
+    }
+    libc::syscall(libc::SYS_mprotect, addr, len, prot) as c_int
+}
+
+/// # Safety
+/// Same contract as libc `pkey_mprotect`.
+#[cfg(feature = "preload")]
+#[no_mangle]
+pub unsafe extern "C" fn pkey_mprotect(addr: *mut c_void, len: size_t, prot: c_int, pkey: c_int) -> c_int {
+    if !check_protect("pkey_mprotect", addr, len, prot) {
+        set_errno(libc::EACCES);
+        return -1;
+    }
+    libc::syscall(libc::SYS_pkey_mprotect, addr, len, prot, pkey) as c_int
+}
+
+fn check_protect(syscall: &str, addr: *mut c_void, len: size_t, prot: c_int) -> bool {
+    guarded(|policy| {
+        // Only consult /proc/self/maps when the request asks for exec without write
+        let anonymous = prot & libc::PROT_EXEC != 0 && prot & libc::PROT_WRITE == 0
+            && std::fs::read_to_string("/proc/self/maps")
+                .map(|maps| is_anonymous_region(&maps, addr as usize))
+                .unwrap_or(false);
+        match classify(prot, anonymous) {
+            Some(violation) => permit(policy, syscall, violation, addr as usize, len, prot),
+            None => true,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPS: &str = r#"
+7f0000000000-7f1000000000 r-xp 00000000 fd:01 9012 /usr/lib64/libc.so.6
+7f2000000000-7f3000000000 rw-p 00000000 00:00 0
+7f4000000000-7f5000000000 rw-p 00000000 00:00 0 [heap]
+"#;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(libc::PROT_READ | libc::PROT_WRITE, true), None);
+        assert_eq!(classify(libc::PROT_READ | libc::PROT_EXEC, false), None);
+        assert_eq!(classify(libc::PROT_WRITE | libc::PROT_EXEC, false), Some("writable_executable"));
+        assert_eq!(classify(libc::PROT_READ | libc::PROT_EXEC, true), Some("anonymous_executable"));
+    }
+
+    #[test]
+    fn test_is_anonymous_region() {
+        assert!(!is_anonymous_region(MAPS, 0x7f0000001000));
+        assert!(is_anonymous_region(MAPS, 0x7f2000001000));
+        assert!(is_anonymous_region(MAPS, 0x7f4000001000));
+        assert!(!is_anonymous_region(MAPS, 0x1000));
+    }
+
+    #[test]
+    fn test_permit_modes() {
+        let deny = WxPolicy { mode: WxMode::Deny, allowed_libraries: vec![] };
+        assert!(!permit(&deny, "mmap", "writable_executable", 0, 4096, libc::PROT_WRITE | libc::PROT_EXEC));
+
+        let log = WxPolicy { mode: WxMode::Log, allowed_libraries: vec![] };
+        assert!(permit(&log, "mmap", "writable_executable", 0, 4096, libc::PROT_WRITE | libc::PROT_EXEC));
+    }
+}